@@ -0,0 +1,133 @@
+use almighty_push::stack::{self, Revision};
+use almighty_push::state::{Operation, PrInfo, State};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::collections::BTreeMap;
+
+const STACK_DEPTH: usize = 200;
+const PR_RECORDS: usize = 5000;
+
+fn change_id(i: usize) -> String {
+    // jj change IDs use the letters k-z
+    let mut id = String::new();
+    let mut n = i;
+    for _ in 0..12 {
+        id.push((b'k' + (n % 16) as u8) as char);
+        n /= 16;
+    }
+    id
+}
+
+// `jj log` output for a linear stack, newest first, as LOG_TEMPLATE prints it
+fn log_output(depth: usize) -> String {
+    (0..depth).rev().map(|i| {
+        let parent = if i == 0 { "zzzzzzzzzzzz".to_string() } else { change_id(i - 1) };
+        format!("{}|{:040x}|Change number {}|false|{}\n", change_id(i), i, i, parent)
+    }).collect()
+}
+
+// Linear stack whose bottom merges in an unrelated chain of the same depth
+fn merged_stack(depth: usize) -> Vec<Revision> {
+    let mut output = log_output(depth);
+    for i in 0..depth {
+        let id = change_id(10_000 + i);
+        let parent = if i == 0 { "zzzzzzzzzzzz".to_string() } else { change_id(10_000 + i - 1) };
+        output.push_str(&format!("{}|{:040x}|Side {}|false|{}\n", id, i, i, parent));
+    }
+    let (mut revisions, _) = stack::parse_log(&output, false);
+    if let Some(bottom) = revisions.iter_mut().find(|r| r.change_id == change_id(0)) {
+        bottom.parent_change_ids = vec!["trunkchange0".to_string(), change_id(10_000 + depth - 1)];
+    }
+    revisions
+}
+
+fn push_output(depth: usize) -> String {
+    let mut output = String::from("Changes to push to origin:\n");
+    for i in 0..depth {
+        output.push_str(&format!("  Add bookmark push-{} to {:012x}\n", change_id(i), i));
+    }
+    output.push_str("Moving bookmark push-done\n");
+    output
+}
+
+fn op_log(entries: usize) -> String {
+    (0..entries).map(|i| match i % 3 {
+        0 => format!("squash commits into {}\n", change_id(i)),
+        1 => format!("abandon commit {} {}\n", change_id(i), change_id(i + 1)),
+        _ => "snapshot working copy\n".to_string(),
+    }).collect()
+}
+
+fn large_state() -> State {
+    let mut state = State::default();
+    for i in 0..PR_RECORDS {
+        state.prs.insert(change_id(i), PrInfo {
+            pr_number: i as u32,
+            pr_url: format!("https://github.com/owner/repo/pull/{}", i),
+            branch_name: format!("push-{}", change_id(i)),
+            commit_id: format!("{:040x}", i),
+            change_id: Some(change_id(i)),
+            base_branch: Some("main".to_string()),
+        });
+        if i % 2 == 0 {
+            state.merged_prs.insert(change_id(i));
+        }
+    }
+    state.stack_order = (0..STACK_DEPTH).map(change_id).collect();
+    state.operations = (0..100).map(|i| Operation {
+        id: format!("op-{}", i),
+        op_type: "push".to_string(),
+        timestamp: "2025-01-01T00:00:00Z".to_string(),
+        changes_affected: (0..STACK_DEPTH).map(change_id).collect(),
+        success: true,
+        actions: vec![format!("Pushed push-{}", change_id(i))],
+        calls: BTreeMap::from([("jj log".to_string(), 10)]),
+    }).collect();
+    state
+}
+
+fn stack_linearization(c: &mut Criterion) {
+    let output = log_output(STACK_DEPTH);
+    c.bench_function("parse_log 200 revisions", |b| {
+        b.iter(|| stack::parse_log(black_box(&output), false))
+    });
+
+    let revisions = merged_stack(STACK_DEPTH);
+    c.bench_function("drop_out_of_stack_parents 400 revisions", |b| {
+        b.iter(|| {
+            let mut revisions = revisions.clone();
+            stack::drop_out_of_stack_parents(black_box(&mut revisions), false);
+            revisions
+        })
+    });
+}
+
+fn push_output_parsing(c: &mut Criterion) {
+    let output = push_output(STACK_DEPTH);
+    c.bench_function("push_updated_bookmark 200 bookmarks", |b| {
+        b.iter(|| stack::push_updated_bookmark(black_box(&output)))
+    });
+}
+
+fn change_id_extraction(c: &mut Criterion) {
+    let log = op_log(5000);
+    c.bench_function("squashed_change_ids 5000 operations", |b| {
+        b.iter(|| stack::squashed_change_ids(black_box(&log)))
+    });
+
+    let bodies: String = (0..STACK_DEPTH).map(|i| {
+        format!("{}\nChange {}\n\nSome details\nTest Plan: ran it\n{}\n", change_id(i), i, stack::DESCRIPTION_SEPARATOR)
+    }).collect();
+    c.bench_function("parse_description_bodies 200 revisions", |b| {
+        b.iter(|| stack::parse_description_bodies(black_box(&bodies)))
+    });
+}
+
+fn state_serialization(c: &mut Criterion) {
+    let state = large_state();
+    let json = state.to_json().unwrap();
+    c.bench_function("state to_json 5000 PRs", |b| b.iter(|| black_box(&state).to_json().unwrap()));
+    c.bench_function("state from_json 5000 PRs", |b| b.iter(|| State::from_json(black_box(&json)).unwrap()));
+}
+
+criterion_group!(benches, stack_linearization, push_output_parsing, change_id_extraction, state_serialization);
+criterion_main!(benches);
@@ -0,0 +1,234 @@
+use anyhow::{bail, Context, Result};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::config::{self, Config};
+use crate::errors::{CodedError, ErrorCode};
+
+static APP_AUTHENTICATED: OnceLock<u64> = OnceLock::new();
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+// Where the GitHub credentials for this run come from, in order of precedence
+pub enum TokenSource {
+    GithubApp(u64),
+    Profile(String),
+    GhTokenEnv,
+    GithubTokenEnv,
+    GhCli,
+}
+
+impl std::fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TokenSource::GithubApp(id) => write!(f, "GitHub App {} installation token", id),
+            TokenSource::Profile(name) => write!(f, "profile {}", name),
+            TokenSource::GhTokenEnv => write!(f, "GH_TOKEN environment variable"),
+            TokenSource::GithubTokenEnv => write!(f, "GITHUB_TOKEN environment variable"),
+            TokenSource::GhCli => write!(f, "gh auth token (system keychain or gh config)"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Claims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+// Authenticate gh as a GitHub App installation when one is configured.
+// The installation token is exported as GH_TOKEN so every gh call made
+// afterwards acts as the app and PRs are attributed to it.
+pub fn authenticate(config: &Config, verbose: bool) -> Result<()> {
+    let Some(app) = github_app(config)? else {
+        return Ok(());
+    };
+
+    let jwt = app_jwt(&app)?;
+    let token = installation_token(&app, &jwt, verbose)?;
+    env::set_var("GH_TOKEN", token);
+    let _ = APP_AUTHENTICATED.set(app.app_id);
+
+    if verbose {
+        eprintln!("[debug] Authenticated as GitHub App {}", app.app_id);
+    }
+    Ok(())
+}
+
+// Switch gh to the account, token and host of a named profile by exporting
+// GH_TOKEN/GH_HOST for every gh call made afterwards
+pub fn apply_profile(name: &str, verbose: bool) -> Result<()> {
+    let profile = config::load_profile(name)?;
+
+    if let Some(host) = &profile.host {
+        env::set_var("GH_HOST", host);
+    }
+
+    let token = match (&profile.token_env, &profile.user) {
+        (Some(var), _) => Some(env::var(var).map_err(|_| CodedError::new(ErrorCode::ProfileUnavailable,
+            format!("Profile {} reads its token from {}, which is not set", name, var)))?),
+        (None, Some(user)) => {
+            let mut args = vec!["auth", "token", "--user", user.as_str()];
+            if let Some(host) = &profile.host {
+                args.extend(["--hostname", host.as_str()]);
+            }
+            if verbose {
+                eprintln!("[debug] Running: gh {}", args.join(" "));
+            }
+            let started = Instant::now();
+            let output = Command::new("gh").args(&args).output().context("Failed to run: gh auth token")?;
+            crate::record_call(&["gh", "auth", "token"], started.elapsed());
+            if !output.status.success() {
+                bail!(CodedError::new(ErrorCode::ProfileUnavailable, format!(
+                    "Profile {}: no gh login for {} (run `gh auth login`): {}",
+                    name, user, String::from_utf8_lossy(&output.stderr).trim())));
+            }
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        (None, None) => None,
+    };
+
+    if let Some(token) = token {
+        env::set_var("GH_TOKEN", token);
+        let _ = PROFILE.set(name.to_string());
+    }
+    if verbose {
+        eprintln!("[debug] Using profile {}", name);
+    }
+    Ok(())
+}
+
+// Resolve the token used for GitHub requests: GitHub App, then profile, then GH_TOKEN,
+// then GITHUB_TOKEN, then whatever `gh auth token` returns
+pub fn resolve_token(verbose: bool) -> Result<(TokenSource, String)> {
+    let from_env = |name: &str| env::var(name).ok().filter(|token| !token.trim().is_empty());
+
+    if let Some(token) = from_env("GH_TOKEN") {
+        let source = match (APP_AUTHENTICATED.get(), PROFILE.get()) {
+            (Some(&app_id), _) => TokenSource::GithubApp(app_id),
+            (None, Some(name)) => TokenSource::Profile(name.clone()),
+            (None, None) => TokenSource::GhTokenEnv,
+        };
+        return Ok((source, token));
+    }
+    if let Some(token) = from_env("GITHUB_TOKEN") {
+        return Ok((TokenSource::GithubTokenEnv, token));
+    }
+
+    if verbose {
+        eprintln!("[debug] Running: gh auth token");
+    }
+    let started = Instant::now();
+    // Without gh (e.g. in CI containers) a token from the environment is the only option
+    let Ok(output) = Command::new("gh").args(["auth", "token"]).output() else {
+        bail!(CodedError::new(ErrorCode::NoGithubToken,
+                              "No GitHub token found and gh is not installed: set GH_TOKEN or GITHUB_TOKEN \
+                               (in GitHub Actions, `GITHUB_TOKEN: ${{ secrets.GITHUB_TOKEN }}`)"));
+    };
+    crate::record_call(&["gh", "auth", "token"], started.elapsed());
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || token.is_empty() {
+        bail!(CodedError::new(ErrorCode::NoGithubToken,
+                              "No GitHub token found: set GH_TOKEN or GITHUB_TOKEN, or run `gh auth login`"));
+    }
+    Ok((TokenSource::GhCli, token))
+}
+
+// Resolved GitHub App credentials
+struct AppCredentials {
+    app_id: u64,
+    installation_id: Option<u64>,
+    private_key: Option<String>,
+    private_key_path: Option<String>,
+}
+
+// App settings from .almighty.toml, overridden by ALMIGHTY_APP_* environment variables
+fn github_app(config: &Config) -> Result<Option<AppCredentials>> {
+    let configured = config.github_app.as_ref();
+    let env_id = |name: &str| -> Result<Option<u64>> {
+        env::var(name).ok()
+            .map(|value| value.trim().parse().with_context(|| format!("{} must be a number", name)))
+            .transpose()
+    };
+
+    let Some(app_id) = env_id("ALMIGHTY_APP_ID")?.or(configured.map(|app| app.app_id)) else {
+        return Ok(None);
+    };
+
+    Ok(Some(AppCredentials {
+        app_id,
+        installation_id: env_id("ALMIGHTY_APP_INSTALLATION_ID")?
+            .or(configured.and_then(|app| app.installation_id)),
+        private_key: env::var("ALMIGHTY_APP_PRIVATE_KEY").ok(),
+        private_key_path: env::var("ALMIGHTY_APP_PRIVATE_KEY_PATH").ok()
+            .or(configured.and_then(|app| app.private_key_path.clone())),
+    }))
+}
+
+// Sign a short-lived JWT identifying the app
+fn app_jwt(app: &AppCredentials) -> Result<String> {
+    let pem = match (&app.private_key, &app.private_key_path) {
+        (Some(key), _) => key.clone(),
+        (None, Some(path)) => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read GitHub App private key {}", path))?,
+        (None, None) => bail!(CodedError::new(ErrorCode::GithubAppAuth,
+                                              format!("GitHub App {} is configured without a private key", app.app_id))),
+    };
+    let key = EncodingKey::from_rsa_pem(pem.as_bytes())
+        .context("GitHub App private key is not a valid RSA PEM key")?;
+
+    // Backdate iat to tolerate clock drift; GitHub caps exp at 10 minutes
+    let now = chrono::Utc::now().timestamp();
+    let claims = Claims {
+        iat: now - 60,
+        exp: now + 540,
+        iss: app.app_id.to_string(),
+    };
+
+    jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)
+        .context("Failed to sign GitHub App JWT")
+}
+
+// Exchange the app JWT for an installation access token
+fn installation_token(app: &AppCredentials, jwt: &str, verbose: bool) -> Result<String> {
+    let Some(installation_id) = app.installation_id else {
+        bail!(CodedError::new(ErrorCode::GithubAppAuth,
+                              format!("GitHub App {} is configured without an installation_id", app.app_id)));
+    };
+
+    let api_url = match env::var("GH_HOST") {
+        Ok(host) if !host.is_empty() && host != "github.com" => format!("https://{}/api/v3", host),
+        _ => "https://api.github.com".to_string(),
+    };
+    let endpoint = format!("/app/installations/{}/access_tokens", installation_id);
+    if verbose {
+        eprintln!("[debug] Requesting: POST {}", endpoint);
+    }
+
+    // Requested directly rather than through gh, which may not be installed, and
+    // with the JWT as the Bearer token rather than any logged-in credentials. The
+    // forge's agent brings the configured proxy and user agent along.
+    let started = Instant::now();
+    let response = crate::forge::agent().post(&format!("{}{}", api_url, endpoint))
+        .set("Authorization", &format!("Bearer {}", jwt))
+        .set("Accept", "application/vnd.github+json")
+        .call();
+    crate::record_call(&["api", "POST", &endpoint], started.elapsed());
+
+    let response = match response {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => bail!(CodedError::new(ErrorCode::GithubAppAuth, format!(
+            "Failed to create GitHub App installation token: {} {}", status, response.into_string().unwrap_or_default().trim()))),
+        Err(e) => bail!(CodedError::new(ErrorCode::GithubAppAuth, format!("Failed to create GitHub App installation token: {}", e))),
+    };
+    let body: serde_json::Value = response.into_json().context("Failed to parse the installation token response")?;
+    match body["token"].as_str() {
+        Some(token) if !token.is_empty() => Ok(token.to_string()),
+        _ => bail!("GitHub did not return an installation token"),
+    }
+}
@@ -0,0 +1,41 @@
+use crate::config::Checklist;
+use crate::paths;
+
+// Checklist sections that apply to a revision: those without path patterns,
+// and those with a pattern matching one of the changed paths
+pub fn sections(checklists: &[Checklist], changed_paths: &[String]) -> String {
+    let mut sections = String::new();
+    for checklist in checklists {
+        if !checklist.paths.is_empty() && !paths::any_match(&checklist.paths, changed_paths) {
+            continue;
+        }
+        sections.push_str(&format!("\n## {}\n\n", checklist.title));
+        for item in &checklist.items {
+            sections.push_str(&format!("- [ ] {}\n", item));
+        }
+    }
+    sections
+}
+
+// Carry items ticked on GitHub over into a regenerated body, so updating the
+// PR doesn't clear reviewers' progress
+pub fn keep_checked(body: &str, current: &str) -> String {
+    let checked: Vec<&str> = current.lines()
+        .filter_map(|line| line.trim_start().strip_prefix("- [x] ").or_else(|| line.trim_start().strip_prefix("- [X] ")))
+        .map(str::trim)
+        .collect();
+    if checked.is_empty() {
+        return body.to_string();
+    }
+
+    let mut kept: String = body.lines().map(|line| {
+        match line.strip_prefix("- [ ] ") {
+            Some(item) if checked.contains(&item.trim()) => format!("- [x] {}\n", item),
+            _ => format!("{}\n", line),
+        }
+    }).collect();
+    if !body.ends_with('\n') {
+        kept.pop();
+    }
+    kept
+}
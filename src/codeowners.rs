@@ -0,0 +1,111 @@
+use std::fs;
+
+use crate::paths;
+
+// Where GitHub looks for the file, in the order it looks
+const LOCATIONS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+// The rules of the repository's CODEOWNERS file, in file order
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    // Globs in paths::glob_matches syntax equivalent to the CODEOWNERS pattern
+    globs: Vec<String>,
+    // Users and `org/team`s, without the `@`
+    owners: Vec<String>,
+}
+
+// The CODEOWNERS file of the working copy, if it has one
+pub fn load() -> Option<CodeOwners> {
+    let content = LOCATIONS.iter().find_map(|path| fs::read_to_string(path).ok())?;
+    Some(parse(&content))
+}
+
+fn parse(content: &str) -> CodeOwners {
+    let rules = content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split('#').next()?.split_whitespace();
+            let pattern = fields.next()?;
+            // Owners given by email can't be requested as reviewers
+            let owners = fields.filter_map(|owner| owner.strip_prefix('@')).map(str::to_string).collect();
+            Some(Rule { globs: globs(pattern), owners })
+        })
+        .collect();
+    CodeOwners { rules }
+}
+
+// CODEOWNERS patterns follow .gitignore: a pattern without a slash (other
+// than a trailing one) matches at any depth, anything else is relative to the
+// root, and a pattern naming a directory covers everything below it
+fn globs(pattern: &str) -> Vec<String> {
+    let trimmed = pattern.trim_start_matches('/').trim_end_matches('/');
+    let anchored = pattern.starts_with('/') || trimmed.contains('/');
+    let glob = if anchored { trimmed.to_string() } else { format!("**/{}", trimmed) };
+    if pattern.ends_with('/') {
+        return vec![format!("{}/**", glob)];
+    }
+    // `docs/*` only covers files directly in docs/
+    if trimmed.rsplit('/').next().is_some_and(|last| last.contains('*')) {
+        return vec![glob];
+    }
+    vec![format!("{}/**", glob), glob]
+}
+
+impl CodeOwners {
+    // Owners of any of the paths; as on GitHub, the last matching rule decides
+    // a path's owners, and a rule without owners leaves it unowned
+    pub fn owners(&self, changed_paths: &[String]) -> Vec<String> {
+        let mut owners: Vec<String> = Vec::new();
+        for path in changed_paths {
+            let Some(rule) = self.rules.iter().rev()
+                .find(|rule| rule.globs.iter().any(|glob| paths::glob_matches(glob, path))) else { continue };
+            for owner in &rule.owners {
+                if !owners.iter().any(|o| o.eq_ignore_ascii_case(owner)) {
+                    owners.push(owner.clone());
+                }
+            }
+        }
+        owners
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let owners = parse("* @everyone\n/src/ @core\nsrc/auth/ @security\n");
+        assert_eq!(owners.owners(&paths(&["README.md"])), vec!["everyone"]);
+        assert_eq!(owners.owners(&paths(&["src/main.rs"])), vec!["core"]);
+        assert_eq!(owners.owners(&paths(&["src/auth/token.rs"])), vec!["security"]);
+    }
+
+    #[test]
+    fn rule_without_owners_unowns() {
+        let owners = parse("* @everyone\n/generated/\n");
+        assert!(owners.owners(&paths(&["generated/schema.rs"])).is_empty());
+    }
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        let owners = parse("*.sql @dba # databases\ndocs/* @writers\n");
+        assert_eq!(owners.owners(&paths(&["db/migrations/001.sql"])), vec!["dba"]);
+        assert_eq!(owners.owners(&paths(&["docs/intro.md"])), vec!["writers"]);
+        assert!(owners.owners(&paths(&["docs/api/intro.md"])).is_empty());
+    }
+
+    #[test]
+    fn owners_are_merged_without_emails_or_duplicates() {
+        let owners = parse("/a/ @alice dev@example.com @acme/web\n/b/ @Alice\n");
+        assert_eq!(owners.owners(&paths(&["a/x", "b/y"])), vec!["alice", "acme/web"]);
+    }
+}
@@ -0,0 +1,512 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const CONFIG_FILE: &str = ".almighty.toml";
+pub const DEFAULT_BRANCH_PREFIX: &str = "push-";
+
+/// Configuration read from `.almighty.toml` and the layers under and over it (see [`Config::load`])
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Branch the stack targets instead of the repository's default branch
+    pub base_branch: Option<String>,
+    /// How GitHub is talked to: its HTTP API, or the gh CLI
+    pub forge: ForgeKind,
+    /// jj remote of the upstream repository PRs are opened on, when branches are pushed to a fork
+    pub pr_remote: Option<String>,
+    /// Prefix of the branches pushed for each change
+    pub branch_prefix: String,
+    /// Additional branch prefixes treated as managed (e.g. historical naming schemes)
+    pub managed_prefixes: Vec<String>,
+    /// Mark draft PRs ready for review once their parent PR merges into main
+    pub auto_ready_drafts: bool,
+    /// Ask before closing orphaned PRs when running in a terminal
+    pub confirm_close_prs: bool,
+    /// Delete the remote branch and local bookmark of a PR as soon as it is merged
+    pub delete_merged_branches: bool,
+    /// Embed a mermaid graph of the PR dependency chain in each PR's stack section
+    pub mermaid_graph: bool,
+    /// Maximum number of GitHub requests run in parallel
+    pub max_concurrency: usize,
+    /// Only keep this many PRs open; revisions above are pushed but queued
+    pub max_open_prs: Option<usize>,
+    /// Warn when the stack has more revisions than this
+    pub max_stack_depth: usize,
+    /// Refuse to push stacks deeper than max_stack_depth unless --force is passed
+    pub enforce_max_stack_depth: bool,
+    /// Record each change's PR as a `Pull-Request:` trailer in its description so
+    /// other clones can recover the mapping without the state file
+    pub stateless: bool,
+    /// Refuse to push revisions whose description has no test plan section
+    pub strict: bool,
+    /// Build PR bodies from the repository's pull request template with the description filled in
+    pub use_pr_template: bool,
+    /// Keep the stack overview in a comment on each PR and leave PR bodies to their authors
+    pub stack_comment: bool,
+    /// PR body template; `{{stack}}`, `{{change_id}}`, `{{badge:<workflow>}}` etc. are expanded
+    pub body_template: Option<String>,
+    /// Escape Markdown in commit titles shown in PR bodies; off for titles written as Markdown
+    pub escape_markdown: bool,
+    /// Show each PR's CI check status (✅/❌/🟡) in the default stack section
+    pub stack_checks: bool,
+    /// Line of the stack section for each PR; `{{marker}}`, `{{pr}}`, `{{title}}`, `{{state}}` etc. are expanded
+    pub stack_template: Option<String>,
+    /// Whether the stack section lists PRs from the bottom of the stack up, or from the top down
+    pub stack_order: StackOrder,
+    /// Extra `{{name}}` variables available in body_template and stack_template
+    pub template_vars: BTreeMap<String, String>,
+    /// Profile from the user's profiles.toml used for this repository
+    pub profile: Option<String>,
+    /// Authenticate as a GitHub App installation instead of the gh user
+    pub github_app: Option<GithubApp>,
+    /// HTTP(S) proxy for GitHub and git traffic; HTTPS_PROXY in the environment wins
+    pub proxy: Option<String>,
+    /// Hosts that bypass the proxy; NO_PROXY in the environment wins
+    pub no_proxy: Vec<String>,
+    /// Put PR URLs on the clipboard after every run, like --copy
+    pub copy: Option<CopyUrls>,
+    /// Review checklists appended to PR bodies (`[[checklists]]`)
+    pub checklists: Vec<Checklist>,
+    /// Labels applied to every PR
+    pub labels: Vec<String>,
+    /// Labels applied to PRs whose revision changes matching paths (`[[path_labels]]`)
+    pub path_labels: Vec<PathLabels>,
+    /// Reviewers (users or `org/team`) requested on every PR
+    pub reviewers: Vec<String>,
+    /// Request reviews from the CODEOWNERS owners of the files each revision changes
+    pub codeowners: bool,
+    /// Reviewers requested on PRs whose revision changes matching paths (`[[path_reviewers]]`)
+    pub path_reviewers: Vec<PathReviewers>,
+    /// Named areas of the repository as path globs, for max_areas_per_pr
+    pub areas: BTreeMap<String, Vec<String>>,
+    /// Warn when a revision changes files in more than this many areas
+    pub max_areas_per_pr: Option<usize>,
+    /// Warn when a revision changes more than this many lines
+    pub max_lines_per_pr: Option<usize>,
+    /// How `land` merges PRs
+    pub merge_method: MergeMethod,
+    /// Which piece of a split change keeps the original PR
+    pub split_pr: SplitSuccessor,
+    /// What to do when a PR merges before the PRs below it, or into another PR's branch
+    pub out_of_order_merge: OutOfOrderMerge,
+    /// Serialize runs across machines with a lock ref on GitHub, not just the local lock file
+    pub remote_lock: bool,
+    /// Revset of commits never pushed or given PRs, in addition to jj's git.private-commits
+    pub never_push: Option<String>,
+    /// Label kept on PRs whose base is another stacked PR, and removed once they target trunk
+    pub blocked_label: Option<String>,
+    /// Bring PRs that are behind their base up to date when the base requires it before merging
+    pub update_branch: Option<UpdateBranch>,
+    /// Shared defaults: `owner/repo:path/to/file.toml` or an https URL; settings here override them
+    pub shared_config: Option<String>,
+    /// Hours a fetched shared_config is cached before it is fetched again
+    pub shared_config_ttl_hours: u64,
+}
+
+/// A checklist section added to PR bodies, for every PR or only those touching some paths
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Checklist {
+    /// Section heading
+    pub title: String,
+    /// Globs (`auth/**`); when set, only revisions changing a matching path get the checklist
+    #[serde(default)]
+    pub paths: Vec<String>,
+    pub items: Vec<String>,
+}
+
+/// Labels for PRs whose revision changes a path matching one of the globs
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathLabels {
+    pub paths: Vec<String>,
+    pub labels: Vec<String>,
+}
+
+/// Reviewers (users or `org/team`) for PRs whose revision changes a path matching one of the globs
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathReviewers {
+    pub paths: Vec<String>,
+    pub reviewers: Vec<String>,
+}
+
+/// Which PR URLs `--copy` puts on the clipboard
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyUrls {
+    /// Every PR in the stack, bottom to top, one per line
+    All,
+    /// Only the PR at the top of the stack
+    Top,
+}
+
+/// Client used for GitHub requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeKind {
+    /// GitHub's REST and GraphQL APIs over HTTPS
+    #[default]
+    Api,
+    /// The gh CLI, one process per request
+    Gh,
+}
+
+/// Merge method `land` asks GitHub for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMethod {
+    /// Squash the PR into one commit on the base branch
+    #[default]
+    Squash,
+    /// Rebase the PR's commits onto the base branch
+    Rebase,
+    /// Add a merge commit
+    Merge,
+}
+
+impl MergeMethod {
+    /// Name of the method in GitHub's API and gh's `--squash`/`--rebase`/`--merge` flags
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+            MergeMethod::Merge => "merge",
+        }
+    }
+}
+
+/// Successor of a split change that inherits its PR; the other pieces get new PRs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitSuccessor {
+    /// The lowest piece in the stack
+    #[default]
+    First,
+    /// The piece changing the most lines
+    Largest,
+}
+
+/// Order of the PRs in the stack section
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StackOrder {
+    /// The PR closest to the base first, as `jj log` lists it reversed
+    #[default]
+    BottomUp,
+    /// The top of the stack first, as `jj log` lists it
+    TopDown,
+}
+
+/// Handling of PRs merged out of stack order
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutOfOrderMerge {
+    /// Rebase the revisions above the merged one onto where it was merged, and retarget their PRs
+    #[default]
+    Rebase,
+    /// Leave the local stack alone and only retarget the PRs above past the merged one
+    Retarget,
+    /// Only warn; the stack and PR bases stay as they are
+    Warn,
+}
+
+/// How PRs behind a base that requires up-to-date branches are updated
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateBranch {
+    /// GitHub's update-branch API, which merges the base into the PR branch
+    Api,
+    /// Rebase the revisions locally onto the base and push them again
+    Rebase,
+}
+
+/// GitHub App installation used for authentication (`[github_app]` table)
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GithubApp {
+    pub app_id: u64,
+    pub installation_id: Option<u64>,
+    /// PEM private key; ALMIGHTY_APP_PRIVATE_KEY can supply the key contents instead
+    pub private_key_path: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            branch_prefix: DEFAULT_BRANCH_PREFIX.to_string(),
+            managed_prefixes: Vec::new(),
+            auto_ready_drafts: false,
+            confirm_close_prs: true,
+            delete_merged_branches: false,
+            mermaid_graph: false,
+            max_concurrency: 4,
+            max_open_prs: None,
+            max_stack_depth: 20,
+            enforce_max_stack_depth: false,
+            stateless: false,
+            strict: false,
+            use_pr_template: false,
+            stack_comment: false,
+            body_template: None,
+            escape_markdown: true,
+            stack_checks: false,
+            stack_template: None,
+            stack_order: StackOrder::BottomUp,
+            template_vars: BTreeMap::new(),
+            profile: None,
+            github_app: None,
+            proxy: None,
+            no_proxy: Vec::new(),
+            copy: None,
+            checklists: Vec::new(),
+            labels: Vec::new(),
+            path_labels: Vec::new(),
+            reviewers: Vec::new(),
+            codeowners: false,
+            path_reviewers: Vec::new(),
+            areas: BTreeMap::new(),
+            max_areas_per_pr: None,
+            max_lines_per_pr: None,
+            merge_method: MergeMethod::Squash,
+            split_pr: SplitSuccessor::First,
+            out_of_order_merge: OutOfOrderMerge::Rebase,
+            remote_lock: false,
+            never_push: None,
+            blocked_label: None,
+            update_branch: None,
+            base_branch: None,
+            forge: ForgeKind::Api,
+            pr_remote: None,
+            shared_config: None,
+            shared_config_ttl_hours: 24,
+        }
+    }
+}
+
+/// Commented-out starter `.almighty.toml` written by `almighty-push init`
+pub fn starter_config(repo: &str) -> String {
+    let defaults = Config::default();
+    format!(r#"# almighty-push configuration for {repo}
+# Every setting is optional; uncomment to change the default.
+
+# Prefix of the branches pushed for each change
+# branch_prefix = "{prefix}"
+
+# Mark a draft PR ready for review once the PR below it merges into main
+# auto_ready_drafts = {auto_ready}
+
+# Ask before closing orphaned PRs when running in a terminal
+# confirm_close_prs = {confirm}
+
+# Delete a PR's branch and bookmark as soon as the PR is merged
+# delete_merged_branches = {delete_merged}
+
+# Only open PRs for the bottom N revisions
+# max_open_prs = 3
+
+# Warn about (or with enforce_max_stack_depth, refuse) deeper stacks
+# max_stack_depth = {depth}
+
+# Refuse to push revisions without a test plan section
+# strict = {strict}
+"#,
+        repo = repo,
+        prefix = defaults.branch_prefix,
+        auto_ready = defaults.auto_ready_drafts,
+        confirm = defaults.confirm_close_prs,
+        delete_merged = defaults.delete_merged_branches,
+        depth = defaults.max_stack_depth,
+        strict = defaults.strict,
+    )
+}
+
+/// A GitHub identity defined in `~/.config/almighty/profiles.toml` (`[profiles.<name>]`)
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// gh account whose token is used (`gh auth token --user`)
+    pub user: Option<String>,
+    /// Environment variable holding the token to use instead
+    pub token_env: Option<String>,
+    /// GitHub host, for GitHub Enterprise
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfilesFile {
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// Directory holding user-level almighty-push files
+pub fn user_config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("almighty"))
+}
+
+/// Look up a named profile in the user's profiles.toml
+pub fn load_profile(name: &str) -> Result<Profile> {
+    let path = user_config_dir()
+        .context("Cannot locate the user config directory (HOME is not set)")?
+        .join("profiles.toml");
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Profile {} requested but {} could not be read", name, path.display()))?;
+    let mut file: ProfilesFile = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    file.profiles.remove(name)
+        .with_context(|| format!("No profile named {} in {}", name, path.display()))
+}
+
+/// Directory holding cached downloads
+fn user_cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("almighty"))
+}
+
+/// Contents of a shared_config source, from the cache while it is younger than
+/// `ttl`. When fetching fails a stale copy is used, and without one the shared
+/// defaults are skipped with a warning rather than blocking every run.
+fn load_shared_config(source: &str, ttl: Duration) -> Option<String> {
+    let cache = user_cache_dir().map(|dir| {
+        let name: String = source.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' }).collect();
+        dir.join("shared").join(name)
+    });
+    let cached = cache.as_ref().and_then(|path| {
+        let age = fs::metadata(path).and_then(|m| m.modified()).ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+        Some((fs::read_to_string(path).ok()?, age))
+    });
+    if let Some((content, age)) = &cached {
+        if *age < ttl {
+            return Some(content.clone());
+        }
+    }
+
+    match fetch_shared_config(source) {
+        Ok(content) => {
+            if let Some(path) = &cache {
+                let written = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(path, &content));
+                if let Err(e) = written {
+                    eprintln!("⚠️  Could not cache shared config {}: {}", source, e);
+                }
+            }
+            Some(content)
+        }
+        Err(e) => match cached {
+            Some((content, _)) => {
+                eprintln!("⚠️  Could not refresh shared config {} ({}); using the cached copy", source, e);
+                Some(content)
+            }
+            None => {
+                eprintln!("⚠️  Could not fetch shared config {} ({}); continuing without it", source, e);
+                None
+            }
+        },
+    }
+}
+
+/// Download a shared_config: an https URL directly, `owner/repo:path` through the GitHub API
+fn fetch_shared_config(source: &str) -> Result<String> {
+    if source.starts_with("https://") {
+        return crate::forge::download(source);
+    }
+    let (repo, path) = source.split_once(':')
+        .with_context(|| format!("shared_config must be an https URL or owner/repo:path, not {}", source))?;
+    crate::forge::GithubApi::new().file_contents(repo, path, false)
+}
+
+/// Lay `overrides` over `base`: tables are merged key by key, anything else
+/// (including arrays) is replaced
+fn merge_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => merge_tables(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Settings that can be overridden by an `ALMIGHTY_<NAME>` environment variable
+const ENV_SETTINGS: &[&str] = &[
+    "base_branch", "forge", "branch_prefix", "auto_ready_drafts", "confirm_close_prs", "delete_merged_branches", "mermaid_graph",
+    "max_concurrency", "max_open_prs", "max_stack_depth", "enforce_max_stack_depth", "stateless",
+    "strict", "use_pr_template", "stack_comment", "escape_markdown", "stack_checks", "codeowners", "remote_lock", "pr_remote", "never_push", "blocked_label", "shared_config", "merge_method",
+];
+
+/// Parse a config file into a table; a missing file is an empty one
+fn read_table(path: &Path) -> Result<toml::Table> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display())),
+        Err(_) => Ok(toml::Table::new()),
+    }
+}
+
+/// Settings given as `ALMIGHTY_<NAME>` environment variables. Values are read
+/// as TOML (`true`, `3`) and anything that isn't valid TOML as a string.
+fn env_table() -> toml::Table {
+    ENV_SETTINGS.iter().filter_map(|name| {
+        let raw = std::env::var(format!("ALMIGHTY_{}", name.to_uppercase())).ok()?;
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", raw)).ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or(toml::Value::String(raw));
+        Some((name.to_string(), value))
+    }).collect()
+}
+
+impl Config {
+    /// Layered configuration, later layers winning: shared_config, the user's
+    /// `~/.config/almighty/config.toml`, the repository's `.almighty.toml`, then
+    /// `ALMIGHTY_<NAME>` environment variables
+    pub fn load() -> Result<Self> {
+        let mut layered = match user_config_dir() {
+            Some(dir) => read_table(&dir.join("config.toml"))?,
+            None => toml::Table::new(),
+        };
+        merge_tables(&mut layered, read_table(Path::new(CONFIG_FILE))?);
+        merge_tables(&mut layered, env_table());
+
+        let shared = match layered.get("shared_config") {
+            Some(toml::Value::String(source)) => {
+                let ttl = layered.get("shared_config_ttl_hours").and_then(toml::Value::as_integer)
+                    .map_or(Self::default().shared_config_ttl_hours, |hours| hours.max(0) as u64);
+                load_shared_config(source, Duration::from_secs(ttl * 60 * 60))
+                    .map(|content| toml::from_str::<toml::Table>(&content)
+                        .with_context(|| format!("Failed to parse shared config {}", source)))
+                    .transpose()?
+            }
+            _ => None,
+        };
+        if let Some(mut merged) = shared {
+            // Shared defaults can't chain to further shared files
+            merged.remove("shared_config");
+            merge_tables(&mut merged, layered);
+            layered = merged;
+        }
+        toml::Value::Table(layered).try_into()
+            .with_context(|| format!("Invalid configuration in {}, the user config.toml or ALMIGHTY_* variables", CONFIG_FILE))
+    }
+
+    /// Whether any rule needs the paths each revision changes
+    pub fn uses_paths(&self) -> bool {
+        self.checklists.iter().any(|checklist| !checklist.paths.is_empty()) || !self.path_labels.is_empty()
+            || !self.path_reviewers.is_empty()
+            || (self.max_areas_per_pr.is_some() && !self.areas.is_empty())
+    }
+
+    /// Whether a branch was created by almighty-push (current or additional prefix)
+    pub fn is_managed_branch(&self, branch: &str) -> bool {
+        branch.starts_with(&self.branch_prefix)
+            || self.managed_prefixes.iter().any(|prefix| branch.starts_with(prefix.as_str()))
+    }
+}
@@ -0,0 +1,113 @@
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+// Commands kept for the transcript in a diagnostic bundle
+const TRANSCRIPT_LEN: usize = 100;
+
+static TRANSCRIPT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+// Install a panic hook that writes a diagnostic bundle after the default
+// panic message is printed
+pub fn install() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line())).unwrap_or_default();
+        let message = info.payload().downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "(no message)".to_string());
+        let failure = format!("panic at {}: {}", location, message);
+        report(&failure, &Backtrace::force_capture().to_string());
+    }));
+}
+
+// Remember a command for the transcript, secrets stripped and long arguments summarized
+pub fn record_command(args: &[&str]) {
+    let command = args.iter().map(|arg| {
+        if arg.contains('\n') {
+            format!("<{} lines>", arg.lines().count())
+        } else {
+            redact(arg)
+        }
+    }).collect::<Vec<_>>().join(" ");
+
+    let mut transcript = TRANSCRIPT.lock().unwrap_or_else(|e| e.into_inner());
+    if transcript.len() == TRANSCRIPT_LEN {
+        transcript.pop_front();
+    }
+    transcript.push_back(command);
+}
+
+// Write the bundle for a panic and tell the user where it is
+fn report(failure: &str, backtrace: &str) {
+    match write_bundle(failure, backtrace) {
+        Ok(dir) => eprintln!(
+            "\nA diagnostic bundle was written to {}\nSecrets are stripped, but please review it before attaching it to a bug report.",
+            dir.display()
+        ),
+        Err(err) => eprintln!("⚠️  Could not write a diagnostic bundle: {}", err),
+    }
+}
+
+fn write_bundle(failure: &str, backtrace: &str) -> std::io::Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!(
+        "almighty-push-crash-{}-{}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir)?;
+
+    let mut report = String::new();
+    let _ = writeln!(report, "almighty-push {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(report, "jj: {}", tool_version("jj"));
+    let _ = writeln!(report, "gh: {}", tool_version("gh"));
+    let _ = writeln!(report, "os: {} {}", std::env::consts::OS, std::env::consts::ARCH);
+    let args: Vec<String> = std::env::args().collect();
+    let _ = writeln!(report, "args: {}", redact(&args.join(" ")));
+    let _ = writeln!(report, "\n{}\n\nBacktrace:\n{}", redact(failure), backtrace);
+    fs::write(dir.join("report.txt"), report)?;
+
+    let transcript = TRANSCRIPT.lock().unwrap_or_else(|e| e.into_inner());
+    let transcript: String = transcript.iter().map(|command| format!("{}\n", command)).collect();
+    fs::write(dir.join("transcript.txt"), transcript)?;
+
+    if let Ok(state) = fs::read_to_string(".almighty") {
+        fs::write(dir.join("state.json"), redact(&state))?;
+    }
+
+    Ok(dir)
+}
+
+fn tool_version(tool: &str) -> String {
+    Command::new(tool).arg("--version").output().ok()
+        .and_then(|output| String::from_utf8_lossy(&output.stdout).lines().next().map(str::to_string))
+        .unwrap_or_else(|| "not found".to_string())
+}
+
+// Strip GitHub tokens, JWTs, bearer credentials and URL passwords from text
+fn redact(text: &str) -> String {
+    static PATTERN: OnceLock<regex::Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| regex::Regex::new(concat!(
+        r"gh[pousr]_[A-Za-z0-9_]{20,}",
+        r"|github_pat_[A-Za-z0-9_]{20,}",
+        r"|eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+",
+        r"|(?i:bearer|token)\s+[A-Za-z0-9._-]{20,}",
+        r"|://[^/\s:@]+:[^/\s@]+@",
+    )).unwrap());
+
+    let mut redacted = pattern.replace_all(text, "[REDACTED]").into_owned();
+    for var in ["GH_TOKEN", "GITHUB_TOKEN", "ALMIGHTY_APP_PRIVATE_KEY"] {
+        if let Ok(secret) = std::env::var(var) {
+            if secret.len() >= 8 {
+                redacted = redacted.replace(&secret, "[REDACTED]");
+            }
+        }
+    }
+    redacted
+}
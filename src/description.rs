@@ -0,0 +1,217 @@
+// Parsing of the sections and trailers in a commit description body (the
+// lines after the first)
+
+// Headings that start a test plan section, compared case-insensitively
+const TEST_PLAN_HEADINGS: &[&str] = &["test plan", "testing", "test"];
+
+// The text of a `Test Plan:` / `## Testing` section, if the description has one.
+// The section runs until the next heading, `Label:` line or trailer block.
+pub fn test_plan(body: &str) -> Option<String> {
+    let mut lines = body.lines();
+    let mut plan = Vec::new();
+
+    loop {
+        let line = lines.next()?;
+        if let Some(rest) = section_start(line) {
+            if !rest.is_empty() {
+                plan.push(rest);
+            }
+            break;
+        }
+    }
+
+    for line in lines {
+        if is_heading(line) || section_label(line).is_some() || is_trailer(line) {
+            break;
+        }
+        plan.push(line);
+    }
+
+    let plan = plan.join("\n").trim().to_string();
+    (!plan.is_empty()).then_some(plan)
+}
+
+// Text following a test plan heading on the same line ("" for a bare heading)
+fn section_start(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    if is_heading(trimmed) {
+        let title = trimmed.trim_start_matches('#').trim().trim_end_matches(':');
+        return TEST_PLAN_HEADINGS.contains(&title.to_lowercase().as_str()).then_some("");
+    }
+    let (label, rest) = section_label(trimmed)?;
+    TEST_PLAN_HEADINGS.contains(&label.to_lowercase().as_str()).then_some(rest)
+}
+
+fn is_heading(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+// `Summary:` / `Test Plan: ran it` style labels: a few capitalised words and a colon
+fn section_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    let words = label.split_whitespace().count();
+    let capitalised = label.starts_with(|c: char| c.is_ascii_uppercase());
+    let plain = label.chars().all(|c| c.is_alphabetic() || c == ' ');
+    (capitalised && plain && (1..=3).contains(&words) && !rest.starts_with("//"))
+        .then(|| (label.trim(), rest.trim()))
+}
+
+// Git trailers such as `Co-authored-by: Name <email>`
+pub fn is_trailer(line: &str) -> bool {
+    line.split_once(": ").is_some_and(|(key, _)| {
+        key.contains('-') && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+// `Name <email>` of every `Co-authored-by:` trailer, in order and without duplicates
+pub fn co_authors(body: &str) -> Vec<String> {
+    let mut authors: Vec<String> = Vec::new();
+    for line in body.lines() {
+        let Some((key, value)) = line.trim().split_once(':') else { continue };
+        let value = value.trim();
+        if key.eq_ignore_ascii_case("co-authored-by") && !value.is_empty()
+            && !authors.iter().any(|a| a.eq_ignore_ascii_case(value)) {
+            authors.push(value.to_string());
+        }
+    }
+    authors
+}
+
+// Comma- or space-separated values of every `key:` line (`Reviewers: alice,
+// @acme/payments`), without `@` prefixes or duplicates
+pub fn list_trailer(body: &str, key: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    for line in body.lines() {
+        let Some((name, value)) = line.trim().split_once(':') else { continue };
+        if !name.trim().eq_ignore_ascii_case(key) {
+            continue;
+        }
+        for value in value.split([',', ' ']).map(|v| v.trim().trim_start_matches('@')).filter(|v| !v.is_empty()) {
+            if !values.iter().any(|v| v == value) {
+                values.push(value.to_string());
+            }
+        }
+    }
+    values
+}
+
+// The body without its closing trailer block (`Co-authored-by:`, `Reviewers:`,
+// `Labels:` and the like), as prose for a PR description
+pub fn without_trailers(body: &str) -> &str {
+    let body = body.trim_end();
+    let (rest, last_paragraph) = body.rsplit_once("\n\n").unwrap_or(("", body));
+    let trailers = last_paragraph.lines().all(|line| {
+        is_trailer(line) || line.split_once(':').is_some_and(|(key, _)| ["reviewers", "labels"].contains(&key.trim().to_lowercase().as_str()))
+    });
+    if trailers { rest.trim_end() } else { body }
+}
+
+// (text, trailers): the closing paragraph of `text` when it consists of
+// trailers, including the `Stack:` line of merge_trailers, split off
+pub fn split_trailer_block(text: &str) -> (&str, &str) {
+    let (rest, last_paragraph) = text.rsplit_once("\n\n").unwrap_or(("", text));
+    let trailers = !last_paragraph.trim().is_empty()
+        && last_paragraph.lines().all(|line| is_trailer(line) || line.starts_with("Stack: "));
+    if trailers { (rest, last_paragraph) } else { (text, "") }
+}
+
+// Trailer recording a change's PR in stateless mode
+pub const PR_TRAILER: &str = "Pull-Request";
+
+// PR number and URL from a `Pull-Request: <url>` trailer
+pub fn pr_trailer(body: &str) -> Option<(u32, String)> {
+    body.lines().rev().find_map(|line| {
+        let (key, value) = line.trim().split_once(':')?;
+        if !key.eq_ignore_ascii_case(PR_TRAILER) {
+            return None;
+        }
+        let url = value.trim();
+        let number = url.trim_end_matches('/').rsplit('/').next()?.trim_start_matches('#').parse().ok()?;
+        Some((number, url.to_string()))
+    })
+}
+
+// Trailer carrying the jj change ID in merge commit messages, so landed work
+// maps back to its change exactly
+pub const CHANGE_ID_TRAILER: &str = "Change-Id";
+
+// Trailers that end a merge commit message: the change ID and the PRs of its stack
+pub fn merge_trailers(change_id: &str, stack_prs: &[u32]) -> String {
+    let mut trailers = format!("{}: {}", CHANGE_ID_TRAILER, change_id);
+    if !stack_prs.is_empty() {
+        let prs: Vec<String> = stack_prs.iter().map(|n| format!("#{}", n)).collect();
+        trailers.push_str(&format!("\nStack: {}", prs.join(" ")));
+    }
+    trailers
+}
+
+// Full description with `key: value` appended to its trailer block
+pub fn with_trailer(title: &str, body: &str, key: &str, value: &str) -> String {
+    let trailer = format!("{}: {}", key, value);
+    if body.is_empty() {
+        return format!("{}\n\n{}\n", title, trailer);
+    }
+
+    // Join an existing trailer block rather than starting a new paragraph
+    let last_paragraph = body.rsplit("\n\n").next().unwrap_or_default();
+    let separator = if last_paragraph.lines().all(is_trailer) { "\n" } else { "\n\n" };
+    format!("{}\n\n{}{}{}\n", title, body, separator, trailer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_trailers() {
+        assert!(is_trailer("Co-authored-by: Ada <ada@example.com>"));
+        assert!(is_trailer("Change-Id: abc"));
+        assert!(!is_trailer("Note: a sentence"));
+        assert!(!is_trailer("Co authored by: Ada"));
+    }
+
+    #[test]
+    fn reads_co_authors_once_each() {
+        let body = "Text\n\nCo-authored-by: Ada <a@x>\nco-authored-by: ada <A@X>";
+        assert_eq!(co_authors(body), vec!["Ada <a@x>"]);
+    }
+
+    #[test]
+    fn reads_list_trailers() {
+        let body = "Text\n\nReviewers: @alice, acme/web bob\nreviewers: alice";
+        assert_eq!(list_trailer(body, "Reviewers"), vec!["alice", "acme/web", "bob"]);
+    }
+
+    #[test]
+    fn strips_the_closing_trailer_block() {
+        assert_eq!(without_trailers("Fix it\n\nReviewers: alice\nCo-authored-by: Ada <a@x>\n"), "Fix it");
+        assert_eq!(without_trailers("Fix it\n\nSee: the docs"), "Fix it\n\nSee: the docs");
+    }
+
+    #[test]
+    fn splits_off_the_trailer_block() {
+        assert_eq!(split_trailer_block("Body\n\nChange-Id: abc\nStack: #1 #2"), ("Body", "Change-Id: abc\nStack: #1 #2"));
+        assert_eq!(split_trailer_block("Body\n\nPlain text"), ("Body\n\nPlain text", ""));
+    }
+
+    #[test]
+    fn reads_the_pr_trailer() {
+        let body = "Title\n\nPull-Request: https://github.com/o/r/pull/42\n";
+        assert_eq!(pr_trailer(body), Some((42, "https://github.com/o/r/pull/42".to_string())));
+        assert_eq!(pr_trailer("Title"), None);
+    }
+
+    #[test]
+    fn inserts_trailers() {
+        assert_eq!(with_trailer("Title", "", "Pull-Request", "u"), "Title\n\nPull-Request: u\n");
+        assert_eq!(with_trailer("Title", "Body", "Pull-Request", "u"), "Title\n\nBody\n\nPull-Request: u\n");
+        assert_eq!(with_trailer("Title", "Body\n\nChange-Id: c", "Pull-Request", "u"),
+                   "Title\n\nBody\n\nChange-Id: c\nPull-Request: u\n");
+    }
+
+    #[test]
+    fn builds_merge_trailers() {
+        assert_eq!(merge_trailers("abc", &[1, 2]), "Change-Id: abc\nStack: #1 #2");
+        assert_eq!(merge_trailers("abc", &[]), "Change-Id: abc");
+    }
+}
@@ -0,0 +1,363 @@
+use std::fmt;
+
+// Stable codes for user-facing failures. Messages stay short; `almighty-push
+// --explain <code>` prints the causes and fixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoGithubRemote,
+    LockTimeout,
+    CommandFailed,
+    RemoteLockTimeout,
+    GithubApiFailed,
+    RateLimited,
+    Conflicts,
+    StackTooDeep,
+    MissingTestPlan,
+    DivergentChange,
+    ForeignCommits,
+    ForcePushRefused,
+    PushNotVerified,
+    BasePrNotOpen,
+    BranchOwnedByOther,
+    BaseBranchMissing,
+    LandFailed,
+    RequiredChecks,
+    MergeNotConfirmed,
+    NoGithubToken,
+    GithubAppAuth,
+    ProfileUnavailable,
+    WrongDirectory,
+    DoctorProblems,
+    VerifyFailed,
+}
+
+const ALL_CODES: &[ErrorCode] = &[
+    ErrorCode::NoGithubRemote,
+    ErrorCode::LockTimeout,
+    ErrorCode::CommandFailed,
+    ErrorCode::RemoteLockTimeout,
+    ErrorCode::GithubApiFailed,
+    ErrorCode::RateLimited,
+    ErrorCode::Conflicts,
+    ErrorCode::StackTooDeep,
+    ErrorCode::MissingTestPlan,
+    ErrorCode::DivergentChange,
+    ErrorCode::ForeignCommits,
+    ErrorCode::ForcePushRefused,
+    ErrorCode::PushNotVerified,
+    ErrorCode::BasePrNotOpen,
+    ErrorCode::BranchOwnedByOther,
+    ErrorCode::BaseBranchMissing,
+    ErrorCode::LandFailed,
+    ErrorCode::RequiredChecks,
+    ErrorCode::MergeNotConfirmed,
+    ErrorCode::NoGithubToken,
+    ErrorCode::GithubAppAuth,
+    ErrorCode::ProfileUnavailable,
+    ErrorCode::WrongDirectory,
+    ErrorCode::DoctorProblems,
+    ErrorCode::VerifyFailed,
+];
+
+impl ErrorCode {
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::NoGithubRemote => "AP001",
+            ErrorCode::LockTimeout => "AP002",
+            ErrorCode::CommandFailed => "AP003",
+            ErrorCode::RemoteLockTimeout => "AP004",
+            ErrorCode::GithubApiFailed => "AP005",
+            ErrorCode::RateLimited => "AP006",
+            ErrorCode::Conflicts => "AP010",
+            ErrorCode::StackTooDeep => "AP011",
+            ErrorCode::MissingTestPlan => "AP012",
+            ErrorCode::DivergentChange => "AP013",
+            ErrorCode::ForeignCommits => "AP014",
+            ErrorCode::ForcePushRefused => "AP020",
+            ErrorCode::PushNotVerified => "AP021",
+            ErrorCode::BasePrNotOpen => "AP022",
+            ErrorCode::BranchOwnedByOther => "AP023",
+            ErrorCode::BaseBranchMissing => "AP024",
+            ErrorCode::LandFailed => "AP025",
+            ErrorCode::RequiredChecks => "AP026",
+            ErrorCode::MergeNotConfirmed => "AP027",
+            ErrorCode::NoGithubToken => "AP030",
+            ErrorCode::GithubAppAuth => "AP031",
+            ErrorCode::ProfileUnavailable => "AP032",
+            ErrorCode::WrongDirectory => "AP040",
+            ErrorCode::DoctorProblems => "AP041",
+            ErrorCode::VerifyFailed => "AP042",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ErrorCode::NoGithubRemote => "no GitHub remote",
+            ErrorCode::LockTimeout => "another run holds the lock",
+            ErrorCode::CommandFailed => "jj or gh command failed",
+            ErrorCode::RemoteLockTimeout => "another machine holds the remote lock",
+            ErrorCode::GithubApiFailed => "GitHub API request failed",
+            ErrorCode::RateLimited => "GitHub rate limit exceeded",
+            ErrorCode::Conflicts => "stack has conflicts",
+            ErrorCode::StackTooDeep => "stack too deep",
+            ErrorCode::MissingTestPlan => "missing test plan",
+            ErrorCode::DivergentChange => "divergent change",
+            ErrorCode::ForeignCommits => "commits by other authors",
+            ErrorCode::ForcePushRefused => "force push refused",
+            ErrorCode::PushNotVerified => "remote branch doesn't match",
+            ErrorCode::BasePrNotOpen => "base PR not open",
+            ErrorCode::BranchOwnedByOther => "branch belongs to another user",
+            ErrorCode::BaseBranchMissing => "base branch not on the remote",
+            ErrorCode::LandFailed => "PR couldn't be landed",
+            ErrorCode::RequiredChecks => "required checks not passing",
+            ErrorCode::MergeNotConfirmed => "merge not confirmed",
+            ErrorCode::NoGithubToken => "no GitHub token",
+            ErrorCode::GithubAppAuth => "GitHub App authentication failed",
+            ErrorCode::ProfileUnavailable => "profile unavailable",
+            ErrorCode::WrongDirectory => "not at the workspace root",
+            ErrorCode::DoctorProblems => "doctor found problems",
+            ErrorCode::VerifyFailed => "verify found problems",
+        }
+    }
+
+    fn explanation(self) -> &'static str {
+        match self {
+            ErrorCode::NoGithubRemote => "\
+The remote almighty-push uses (--remote, else origin, else the only remote)
+doesn't exist or doesn't point at github.com.
+
+Fixes:
+  - Check the remotes with `jj git remote list`
+  - Pick the GitHub remote with --remote <name>
+  - Add one with `jj git remote add origin git@github.com:<owner>/<repo>.git`",
+            ErrorCode::LockTimeout => "\
+Another almighty-push run in this repository held .almighty.lock for the
+whole wait. Runs are serialized so they don't race on branches and state.
+
+Fixes:
+  - Wait for the other run to finish
+  - If no other run is active (e.g. it was killed), delete .almighty.lock",
+            ErrorCode::CommandFailed => "\
+A jj or gh invocation exited with an error; its stderr is included in the
+message.
+
+Fixes:
+  - Re-run with --verbose to see every command and its output
+  - Run `almighty-push doctor` to check tooling and credentials",
+            ErrorCode::RemoteLockTimeout => "\
+remote_lock is enabled and a run on another machine (same jj user.email) held
+the lock ref refs/almighty-push/locks/<user> on GitHub for the whole wait.
+Locks older than 10 minutes are taken over automatically.
+
+Fixes:
+  - Wait for the other run to finish
+  - If it was killed, delete the ref:
+    gh api -X DELETE repos/<owner>/<repo>/git/refs/almighty-push/locks/<user>",
+            ErrorCode::GithubApiFailed => "\
+A request to GitHub's API was rejected or couldn't be sent. The message
+includes the HTTP status: 401 means the token is missing or invalid, 403 or
+404 usually that it lacks access to the repository, 422 that GitHub refused
+the change (e.g. a PR already exists for the branch).
+
+Fixes:
+  - Check the token (`almighty-push doctor` shows where it comes from)
+  - Re-run with --verbose to see each request
+  - Set forge = \"gh\" in .almighty.toml to go through the gh CLI instead",
+            ErrorCode::RateLimited => "\
+GitHub kept rejecting requests because the token used up its rate limit.
+Short waits are retried with backoff; the run stops when GitHub asks for a
+longer one, or the limit persists, and the message says when it lifts.
+
+Fixes:
+  - Re-run after the time shown; nothing is lost, the run picks up where it stopped
+  - Check the remaining quota with `gh api rate_limit`
+  - Lower max_concurrency in .almighty.toml if secondary limits keep tripping
+  - Use a GitHub App (ALMIGHTY_APP_*), whose installation has its own limit",
+            ErrorCode::Conflicts => "\
+One or more revisions in the stack have unresolved conflicts, which can't
+be pushed meaningfully.
+
+Fixes:
+  - Resolve them (`jj resolve` or edit the files) and re-run",
+            ErrorCode::StackTooDeep => "\
+The stack has more revisions than max_stack_depth and
+enforce_max_stack_depth is set. Huge stacks usually mean the changes aren't
+based on an up-to-date default branch.
+
+Fixes:
+  - Rebase onto the default branch: `jj rebase -d main@origin`
+  - Push anyway with --force, or raise max_stack_depth in .almighty.toml",
+            ErrorCode::MissingTestPlan => "\
+strict = true in .almighty.toml requires every revision's description to
+contain a test plan section.
+
+Fixes:
+  - Add a `Test Plan:` line or a `## Testing` section with `jj describe`",
+            ErrorCode::DivergentChange => "\
+A change ID in the stack has several visible commits, so it can't map to a
+single branch and PR.
+
+Fixes:
+  - Keep one copy: `jj abandon <commit>` for the others
+  - Re-run with --divergence newest or --divergence oldest",
+            ErrorCode::ForeignCommits => "\
+The stack contains commits authored by someone other than your jj user
+(user.email), usually because it was rebased onto a colleague's unmerged
+work. Opening PRs for them would put their work under your name.
+
+Fixes:
+  - --include-foreign opens PRs for them anyway
+  - --exclude-foreign leaves them out and stacks on the open PR of their branch
+    (they must be at the bottom of the stack)",
+            ErrorCode::ForcePushRefused => "\
+--no-force was passed and a remote branch has commits that the push would
+replace (listed above the error).
+
+Fixes:
+  - Fetch and fold the remote commits into your change, then re-run
+  - Drop --no-force to overwrite them",
+            ErrorCode::PushNotVerified => "\
+After pushing (and retrying), a remote branch still doesn't point at the
+commit that was pushed. Something else may be pushing to it, or the push is
+being rejected (e.g. branch protection).
+
+Fixes:
+  - Re-run with --verbose and check the push output
+  - Check for other tools or people pushing to the branch",
+            ErrorCode::BasePrNotOpen => "\
+--base-pr must name an open PR; stacking on a merged or closed PR isn't
+possible.
+
+Fixes:
+  - Check the PR number, or rebase onto the default branch instead",
+            ErrorCode::BranchOwnedByOther => "\
+A branch the push would force-push over belongs to an open PR opened by
+another GitHub user. In repositories where several people run almighty-push
+with the same branch_prefix, this happens when you both push the same change.
+
+Fixes:
+  - Give each user their own branch namespace: branch_prefix = \"push/{user}/\"
+  - Coordinate with the PR's author before taking the change over",
+            ErrorCode::BaseBranchMissing => "\
+The branch given by --base or base_branch, or the branch `backport` was asked
+to copy the stack onto, doesn't exist on origin, so there is nothing to stack
+on or open PRs against.
+
+Fixes:
+  - Check the branch name, or push the branch first
+  - Remove --base / base_branch to use the repository's default branch",
+            ErrorCode::LandFailed => "\
+`land` merges open PRs from the bottom of the stack, each once it targets the
+base branch and GitHub has the commit the stack has locally; with no open PR
+in the stack there is nothing to land. GitHub itself refuses
+merges that branch protection blocks: missing approvals, failing or pending
+required checks, conflicts, or a merge method the repository doesn't allow.
+
+Fixes:
+  - Run `almighty-push status` to see the PR's checks and review
+  - Push the stack first if the PR is behind the local commit
+  - Pick an allowed method with --method or merge_method",
+            ErrorCode::RequiredChecks => "\
+The base branch requires status checks that haven't passed on the PR's head
+commit. Each required check is listed above as failing, pending or missing
+(never reported for this commit).
+
+Fixes:
+  - Fix and push failing checks, or re-run them on GitHub
+  - `almighty-push land --wait` waits for pending and missing checks
+  - A missing check may be misnamed in branch protection, or its workflow
+    may not run for this PR",
+            ErrorCode::MergeNotConfirmed => "\
+GitHub accepted the merge but didn't report the PR as merged in time, so
+`land` stopped before rebasing the rest of the stack: restacking onto a base
+that may not contain the PR yet would leave the stack in a mess.
+
+Fixes:
+  - Check the PR on GitHub; once it shows as merged, run `almighty-push` to
+    restack and continue with `almighty-push land`
+  - With --wait, `land` waits as long for the merge as it does for checks",
+            ErrorCode::NoGithubToken => "\
+No credentials were found: no GitHub App, profile, GH_TOKEN, GITHUB_TOKEN or
+gh login.
+
+Fixes:
+  - Run `gh auth login`, or export GH_TOKEN
+  - In GitHub Actions, set GITHUB_TOKEN: ${{ secrets.GITHUB_TOKEN }}",
+            ErrorCode::GithubAppAuth => "\
+A GitHub App is configured ([github_app] or ALMIGHTY_APP_*) but an
+installation token couldn't be obtained.
+
+Fixes:
+  - Check app_id, installation_id and the private key
+  - Make sure the app is installed on the repository",
+            ErrorCode::ProfileUnavailable => "\
+The selected profile couldn't provide a token: its gh account isn't logged
+in, or its token_env variable isn't set.
+
+Fixes:
+  - `gh auth login` for the profile's account, or export the variable
+  - Check ~/.config/almighty/profiles.toml",
+            ErrorCode::WrongDirectory => "\
+almighty-push keeps .almighty, .almighty.lock and .almighty.toml in the
+directory it runs from, which must be the jj workspace root.
+
+Fixes:
+  - cd to the directory printed by `jj root` and re-run",
+            ErrorCode::DoctorProblems => "\
+`almighty-push doctor` found problems; each is marked with ✗ above.
+
+Fixes:
+  - Address the ✗ lines and re-run doctor",
+            ErrorCode::VerifyFailed => "\
+`almighty-push verify` found the stack and its PRs out of sync: PR bases that
+don't follow the stack, commits that weren't pushed, a non-linear stack, or
+state that doesn't match GitHub. Each issue is listed above.
+
+Fixes:
+  - Run almighty-push to push the stack and retarget its PRs
+  - Resolve conflicts and divergent changes, then push again
+  - Rebase merge commits out of the stack",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        ALL_CODES.iter().copied().find(|c| c.code().eq_ignore_ascii_case(code.trim()))
+    }
+}
+
+// An error carrying a stable code; shown as "AP010: message"
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    message: String,
+}
+
+impl CodedError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code.code(), self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+// Print the explanation of a code, or the list of codes if it's unknown
+pub fn explain(code: &str) {
+    match ErrorCode::from_code(code) {
+        Some(code) => println!("{}: {}\n\n{}", code.code(), code.title(), code.explanation()),
+        None => {
+            if !code.is_empty() {
+                eprintln!("Unknown error code {}\n", code);
+            }
+            println!("Error codes:");
+            for code in ALL_CODES {
+                println!("  {}  {}", code.code(), code.title());
+            }
+        }
+    }
+}
@@ -0,0 +1,655 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::auth;
+use crate::errors::{CodedError, ErrorCode};
+
+// The PR operations almighty-push needs from GitHub, independent of how the
+// requests are made. Implemented by the HTTP API client and by the gh CLI.
+pub trait Forge: Send + Sync {
+    // Open a PR and return its URL
+    fn create_pr(&self, repo: &str, pr: &NewPullRequest, verbose: bool) -> Result<String>;
+    fn edit_pr(&self, repo: &str, number: u32, edit: &PrEdit, verbose: bool) -> Result<()>;
+    fn close_pr(&self, repo: &str, number: u32, comment: Option<&str>, verbose: bool) -> Result<()>;
+    fn comment_pr(&self, repo: &str, number: u32, body: &str, verbose: bool) -> Result<()>;
+    // A PR by number or head branch (preferring an open one), with the given
+    // fields named as in `gh pr view --json`; None if there is no such PR
+    fn view_pr(&self, repo: &str, pr: &str, fields: &[&str], verbose: bool) -> Result<Option<Value>>;
+    // Run a GraphQL query and return its `data`
+    fn graphql(&self, query: &str, variables: Value, verbose: bool) -> Result<Value>;
+    // Call a REST endpoint (`repos/owner/name/...`) with a JSON object body
+    fn rest(&self, method: &str, path: &str, body: Value, verbose: bool) -> Result<Value>;
+    // OAuth scopes of the token; None for tokens without classic scopes
+    // (fine-grained PATs and app installation tokens)
+    fn token_scopes(&self, verbose: bool) -> Result<Option<Vec<String>>>;
+    // Persist whatever is kept between runs; called once as the run ends
+    fn save_cache(&self) {}
+}
+
+pub struct NewPullRequest<'a> {
+    // Branch, or `owner:branch` for a PR from a fork
+    pub head: &'a str,
+    pub base: &'a str,
+    pub title: &'a str,
+    pub body: &'a str,
+}
+
+// Fields of a PR to change; None leaves a field as it is
+#[derive(Default)]
+pub struct PrEdit<'a> {
+    pub base: Option<&'a str>,
+    pub title: Option<&'a str>,
+    pub body: Option<&'a str>,
+}
+
+static FORGE: OnceLock<Box<dyn Forge>> = OnceLock::new();
+
+// Choose how GitHub is talked to for the rest of the run
+pub fn select(kind: crate::config::ForgeKind) {
+    let forge: Box<dyn Forge> = match kind {
+        crate::config::ForgeKind::Api => Box::new(GithubApi::new()),
+        crate::config::ForgeKind::Gh => Box::new(GhCli),
+    };
+    let _ = FORGE.set(forge);
+}
+
+pub fn forge() -> &'static dyn Forge {
+    FORGE.get_or_init(|| Box::new(GithubApi::new())).as_ref()
+}
+
+// Wrap up the run's use of the forge, if anything used it
+pub fn finish() {
+    if let Some(forge) = FORGE.get() {
+        forge.save_cache();
+    }
+}
+
+// GraphQL selection for `gh pr view --json` field names
+fn selection(fields: &[&str]) -> String {
+    fields.iter().map(|&field| match field {
+        "author" => "author { login }",
+        field => field,
+    }).collect::<Vec<_>>().join(" ")
+}
+
+// view_pr through GraphQL, which names PR fields as gh does
+fn view_pr_graphql(forge: &dyn Forge, repo: &str, pr: &str, fields: &[&str], verbose: bool) -> Result<Option<Value>> {
+    let (owner, name) = repo.split_once('/').with_context(|| format!("Invalid repository {}", repo))?;
+    let variables = json!({ "owner": owner, "name": name });
+    if let Ok(number) = pr.trim_start_matches('#').parse::<u32>() {
+        let query = format!(
+            "query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ pullRequest(number: {}) {{ {} }} }} }}",
+            number, selection(fields));
+        // A missing PR is reported as an error alongside a null pullRequest
+        return match forge.graphql(&query, variables, verbose) {
+            Ok(data) => Ok(Some(data["repository"]["pullRequest"].clone()).filter(|pr| !pr.is_null())),
+            Err(e) if format!("{:#}", e).contains("Could not resolve to a PullRequest") => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+    let query = format!(
+        "query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ \
+         pullRequests(headRefName: {}, first: 10, orderBy: {{field: CREATED_AT, direction: DESC}}) {{ nodes {{ state {} }} }} }} }}",
+        Value::from(pr), selection(fields));
+    let data = forge.graphql(&query, variables, verbose)?;
+    let nodes = data["repository"]["pullRequests"]["nodes"].as_array().cloned().unwrap_or_default();
+    Ok(nodes.iter().find(|pr| pr["state"] == "OPEN").or(nodes.first()).cloned())
+}
+
+// GitHub Enterprise Server host selected by GH_HOST, as it is for gh; None for github.com
+fn enterprise_host() -> Option<String> {
+    std::env::var("GH_HOST").ok().filter(|host| !host.is_empty() && host != "github.com")
+}
+
+// Address of GitHub's web interface, for links to PRs, commits and compare views
+pub fn web_url() -> String {
+    format!("https://{}", enterprise_host().as_deref().unwrap_or("github.com"))
+}
+
+// Percent-encode a value (a label name, say) for use as one segment of an API path
+pub fn encode_path_segment(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        byte => format!("%{:02X}", byte),
+    }).collect()
+}
+
+// Number at the end of a PR URL
+pub fn pr_number_from_url(url: &str) -> Option<u32> {
+    url.trim().trim_end_matches('/').rsplit('/').next()?.parse().ok()
+}
+
+// GitHub's REST and GraphQL APIs over HTTPS, authenticated with the token
+// auth::resolve_token finds (resolved on the first request)
+pub struct GithubApi {
+    agent: ureq::Agent,
+    api_url: String,
+    graphql_url: String,
+    token: OnceLock<String>,
+    // REST GET responses by URL, loaded from RESPONSE_CACHE on first use
+    cache: Mutex<Option<HashMap<String, CachedResponse>>>,
+    // Whether the cache changed since it was loaded, so save_cache has work to do
+    cache_changed: AtomicBool,
+}
+
+// REST responses are kept here, next to the state file, so later runs can
+// ask GitHub whether they changed. A 304 answer doesn't count against the
+// rate limit. GraphQL has no conditional requests, so its answers aren't kept.
+const RESPONSE_CACHE: &str = ".almighty-cache";
+// Responses unused for this long are dropped, and at most this many are kept
+// (the most recently used), so the file stays small in long-lived workspaces
+const RESPONSE_CACHE_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const RESPONSE_CACHE_MAX_ENTRIES: usize = 200;
+
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: Value,
+    // When the response was last fetched or confirmed unchanged, in seconds since the epoch
+    #[serde(default)]
+    used: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// An HTTP client for everything almighty-push downloads itself. It honours
+// HTTPS_PROXY and friends as they are when it's built, so it is built after
+// the configured proxy has been applied.
+pub fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(60))
+        .try_proxy_from_env(true)
+        .user_agent(concat!("almighty-push/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+// Download `url` as text, failing on anything but a 2xx answer
+pub fn download(url: &str) -> Result<String> {
+    match agent().get(url).call() {
+        Ok(response) => response.into_string().with_context(|| format!("Failed to read {}", url)),
+        Err(ureq::Error::Status(status, _)) => anyhow::bail!("GET {}: HTTP {}", url, status),
+        Err(e) => anyhow::bail!("GET {}: {}", url, e),
+    }
+}
+
+impl GithubApi {
+    pub fn new() -> Self {
+        let (api_url, graphql_url) = match enterprise_host() {
+            Some(host) => (format!("https://{}/api/v3", host), format!("https://{}/api/graphql", host)),
+            None => ("https://api.github.com".to_string(), "https://api.github.com/graphql".to_string()),
+        };
+        Self { agent: agent(), api_url, graphql_url, token: OnceLock::new(), cache: Mutex::new(None), cache_changed: AtomicBool::new(false) }
+    }
+
+    // Apply `f` to the response cache. It is only used at the root of a jj
+    // workspace, where the state file lives, so runs elsewhere (doctor outside
+    // a repository, say) don't leave cache files behind.
+    fn with_cache<T>(&self, f: impl FnOnce(&mut HashMap<String, CachedResponse>) -> T) -> Option<T> {
+        if !Path::new(".jj").is_dir() {
+            return None;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        let cache = cache.get_or_insert_with(|| {
+            fs::read_to_string(RESPONSE_CACHE).ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default()
+        });
+        Some(f(cache))
+    }
+
+    // Remember a GET response that carries a validator; save_cache persists it
+    fn cache_response(&self, url: &str, response: CachedResponse) {
+        if response.etag.is_none() && response.last_modified.is_none() {
+            return;
+        }
+        self.with_cache(|cache| cache.insert(url.to_string(), response));
+        self.cache_changed.store(true, Ordering::Relaxed);
+    }
+
+    // The raw contents of `path` on a repository's default branch
+    pub fn file_contents(&self, repo: &str, path: &str, verbose: bool) -> Result<String> {
+        let endpoint = format!("repos/{}/contents/{}", repo, path.split('/').map(encode_path_segment).collect::<Vec<_>>().join("/"));
+        if verbose {
+            eprintln!("[debug] Requesting: GET /{}", endpoint);
+        }
+        let response = self.agent.get(&format!("{}/{}", self.api_url, endpoint))
+            .set("Authorization", &format!("Bearer {}", self.token(verbose)?))
+            .set("Accept", "application/vnd.github.raw")
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .call();
+        match response {
+            Ok(response) => response.into_string().with_context(|| format!("Failed to read {}:{}", repo, path)),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let message = serde_json::from_str::<Value>(&body).ok()
+                    .and_then(|json| json["message"].as_str().map(str::to_string))
+                    .unwrap_or(body);
+                anyhow::bail!(CodedError::new(ErrorCode::GithubApiFailed, format!("GET /{}: HTTP {} {}", endpoint, status, message)))
+            }
+            Err(e) => anyhow::bail!(CodedError::new(ErrorCode::GithubApiFailed, format!("GET /{}: {}", endpoint, e))),
+        }
+    }
+
+    fn token(&self, verbose: bool) -> Result<&str> {
+        if let Some(token) = self.token.get() {
+            return Ok(token);
+        }
+        let (_, token) = auth::resolve_token(verbose)?;
+        Ok(self.token.get_or_init(|| token))
+    }
+
+    fn request(&self, method: &str, url: &str, body: Value, verbose: bool) -> Result<Value> {
+        let path = url.strip_prefix(self.api_url.as_str()).unwrap_or(url);
+        if verbose {
+            eprintln!("[debug] Requesting: {} {}", method, path);
+        }
+        let token = self.token(verbose)?;
+        // Everything but REST POSTs (new PRs, comments) can be repeated safely
+        let idempotent = method != "POST" || url == self.graphql_url;
+
+        for attempt in 0.. {
+            let started = Instant::now();
+            let mut request = self.agent.request(method, url)
+                .set("Authorization", &format!("Bearer {}", token))
+                .set("Accept", "application/vnd.github+json")
+                .set("X-GitHub-Api-Version", "2022-11-28");
+            if method == "GET" {
+                let validators = self.with_cache(|cache| cache.get(url).map(|cached| (cached.etag.clone(), cached.last_modified.clone())));
+                if let Some((etag, last_modified)) = validators.flatten() {
+                    if let Some(etag) = etag {
+                        request = request.set("If-None-Match", &etag);
+                    }
+                    if let Some(last_modified) = last_modified {
+                        request = request.set("If-Modified-Since", &last_modified);
+                    }
+                }
+            }
+            let response = if body.is_null() { request.call() } else { request.send_json(body.clone()) };
+            crate::record_call(&["api", method, path], started.elapsed());
+
+            return match response {
+                Ok(response) if response.status() == 304 => {
+                    if verbose {
+                        eprintln!("[debug] Not modified: {} {}", method, path);
+                    }
+                    self.cache_changed.store(true, Ordering::Relaxed);
+                    self.with_cache(|cache| cache.get_mut(url).map(|cached| {
+                        cached.used = now_secs();
+                        cached.body.clone()
+                    })).flatten()
+                        .with_context(|| format!("GitHub answered {} {} with 304 but nothing is cached", method, path))
+                }
+                Ok(response) if response.status() == 204 => Ok(Value::Null),
+                Ok(response) if method == "GET" => {
+                    let etag = response.header("etag").map(str::to_string);
+                    let last_modified = response.header("last-modified").map(str::to_string);
+                    let json: Value = response.into_json().with_context(|| format!("Failed to parse the response to {} {}", method, path))?;
+                    self.cache_response(url, CachedResponse { etag, last_modified, body: json.clone(), used: now_secs() });
+                    Ok(json)
+                }
+                Ok(response) => response.into_json().with_context(|| format!("Failed to parse the response to {} {}", method, path)),
+                Err(ureq::Error::Status(status, response)) => {
+                    let limit = RateLimit::from_headers(&response);
+                    let body = response.into_string().unwrap_or_default();
+                    let message = serde_json::from_str::<Value>(&body).ok()
+                        .and_then(|json| json["message"].as_str().map(str::to_string))
+                        .unwrap_or(body);
+                    if status == 429 || (status == 403 && (limit.exhausted() || is_rate_limit_message(&message))) {
+                        limit.wait(&format!("{} {}", method, path), attempt)?;
+                        continue;
+                    }
+                    let error = CodedError::new(ErrorCode::GithubApiFailed, format!("{} {}: HTTP {} {}", method, path, status, message));
+                    if idempotent && matches!(status, 500 | 502 | 503 | 504) && retry_transient(&error, attempt) {
+                        continue;
+                    }
+                    anyhow::bail!(error)
+                }
+                Err(e) => {
+                    let error = CodedError::new(ErrorCode::GithubApiFailed, format!("{} {}: {}", method, path, e));
+                    if idempotent && retry_transient(&error, attempt) {
+                        continue;
+                    }
+                    anyhow::bail!(error)
+                }
+            };
+        }
+        unreachable!()
+    }
+}
+
+impl Forge for GithubApi {
+    fn create_pr(&self, repo: &str, pr: &NewPullRequest, verbose: bool) -> Result<String> {
+        let created = self.rest("POST", &format!("repos/{}/pulls", repo), json!({
+            "head": pr.head, "base": pr.base, "title": pr.title, "body": pr.body,
+        }), verbose)?;
+        created["html_url"].as_str().map(str::to_string).context("GitHub did not return the new PR's URL")
+    }
+
+    fn edit_pr(&self, repo: &str, number: u32, edit: &PrEdit, verbose: bool) -> Result<()> {
+        let mut fields = serde_json::Map::new();
+        for (name, value) in [("base", edit.base), ("title", edit.title), ("body", edit.body)] {
+            if let Some(value) = value {
+                fields.insert(name.to_string(), value.into());
+            }
+        }
+        self.rest("PATCH", &format!("repos/{}/pulls/{}", repo, number), Value::Object(fields), verbose)?;
+        Ok(())
+    }
+
+    fn close_pr(&self, repo: &str, number: u32, comment: Option<&str>, verbose: bool) -> Result<()> {
+        if let Some(comment) = comment {
+            self.comment_pr(repo, number, comment, verbose)?;
+        }
+        self.rest("PATCH", &format!("repos/{}/pulls/{}", repo, number), json!({ "state": "closed" }), verbose)?;
+        Ok(())
+    }
+
+    fn comment_pr(&self, repo: &str, number: u32, body: &str, verbose: bool) -> Result<()> {
+        self.rest("POST", &format!("repos/{}/issues/{}/comments", repo, number), json!({ "body": body }), verbose)?;
+        Ok(())
+    }
+
+    fn view_pr(&self, repo: &str, pr: &str, fields: &[&str], verbose: bool) -> Result<Option<Value>> {
+        view_pr_graphql(self, repo, pr, fields, verbose)
+    }
+
+    fn graphql(&self, query: &str, variables: Value, verbose: bool) -> Result<Value> {
+        let response = self.request("POST", &self.graphql_url, json!({ "query": query, "variables": variables }), verbose)?;
+        if let Some(errors) = response["errors"].as_array().filter(|errors| !errors.is_empty()) {
+            let messages: Vec<&str> = errors.iter().filter_map(|e| e["message"].as_str()).collect();
+            anyhow::bail!(CodedError::new(ErrorCode::GithubApiFailed, format!("GraphQL query failed: {}", messages.join("; "))));
+        }
+        Ok(response["data"].clone())
+    }
+
+    fn rest(&self, method: &str, path: &str, body: Value, verbose: bool) -> Result<Value> {
+        self.request(method, &format!("{}/{}", self.api_url, path), body, verbose)
+    }
+
+    fn token_scopes(&self, verbose: bool) -> Result<Option<Vec<String>>> {
+        let token = self.token(verbose)?;
+        let started = Instant::now();
+        let response = self.agent.get(&self.api_url).set("Authorization", &format!("Bearer {}", token)).call();
+        crate::record_call(&["api", "GET", "/"], started.elapsed());
+        let response = response.map_err(|e| CodedError::new(ErrorCode::GithubApiFailed, format!("GET /: {}", e)))?;
+        Ok(response.header("x-oauth-scopes").map(parse_scopes))
+    }
+
+    fn save_cache(&self) {
+        if !self.cache_changed.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        let Some(cache) = cache.as_mut() else { return };
+        let oldest = now_secs().saturating_sub(RESPONSE_CACHE_MAX_AGE.as_secs());
+        cache.retain(|_, cached| cached.used >= oldest);
+        if cache.len() > RESPONSE_CACHE_MAX_ENTRIES {
+            let mut urls: Vec<(u64, String)> = cache.iter().map(|(url, cached)| (cached.used, url.clone())).collect();
+            urls.sort_unstable_by(|a, b| b.cmp(a));
+            for (_, url) in &urls[RESPONSE_CACHE_MAX_ENTRIES..] {
+                cache.remove(url);
+            }
+        }
+        // Written through a temporary file of this process's own, so neither a
+        // concurrent reader nor another run saving at the same time sees half of it
+        let tmp = format!("{}.{}.tmp", RESPONSE_CACHE, std::process::id());
+        if serde_json::to_string(cache).ok().and_then(|content| fs::write(&tmp, content).ok()).is_some() {
+            let _ = fs::rename(&tmp, RESPONSE_CACHE);
+        }
+    }
+}
+
+// Attempts at a request that keeps failing with a server error or a dropped
+// connection before the run gives up
+const TRANSIENT_ATTEMPTS: u32 = 3;
+
+// Whether to retry after a transient failure of attempt `attempt`; if so,
+// says so and waits (1s, then 2s, plus jitter) first
+fn retry_transient(error: &dyn std::fmt::Display, attempt: u32) -> bool {
+    if attempt + 1 >= TRANSIENT_ATTEMPTS {
+        return false;
+    }
+    let wait = Duration::from_secs(1 << attempt);
+    eprintln!("⚠️  {}; retrying in {}s", error, wait.as_secs());
+    std::thread::sleep(wait + jitter(Duration::from_secs(1)));
+    true
+}
+
+// Whether a failed request or gh command hit a server error or a dropped
+// connection. For gh only its stderr is looked at; the arguments are
+// arbitrary text.
+fn is_transient(error: &anyhow::Error) -> bool {
+    const TRANSIENT: &[&str] = &[
+        "HTTP 500", "HTTP 502", "HTTP 503", "HTTP 504", "Connection Failed", "Network Error",
+        "connection reset", "connection refused", "timeout", "timed out", "unexpected EOF",
+    ];
+    let message = format!("{:#}", error);
+    let message = message.rsplit_once("Stderr:").map_or(message.as_str(), |(_, stderr)| stderr);
+    TRANSIENT.iter().any(|pattern| message.contains(pattern))
+}
+
+// Open a PR, retrying transient failures. A failed attempt may still have
+// opened it, so before each retry an open PR from the head branch is looked
+// for, and returned rather than opening a duplicate.
+pub fn create_pr(forge: &dyn Forge, repo: &str, pr: &NewPullRequest, verbose: bool) -> Result<String> {
+    // A fork's head is `owner:branch`; the PR's headRefName is the branch
+    let branch = pr.head.rsplit(':').next().unwrap_or(pr.head);
+    for attempt in 0.. {
+        let error = match forge.create_pr(repo, pr, verbose) {
+            Err(e) if is_transient(&e) => e,
+            result => return result,
+        };
+        if !retry_transient(&format!("Opening a PR for {} failed: {:#}", branch, error), attempt) {
+            return Err(error);
+        }
+        let existing = forge.view_pr(repo, branch, &["url", "state"], verbose).ok().flatten()
+            .filter(|existing| existing["state"] == "OPEN");
+        if let Some(url) = existing.as_ref().and_then(|existing| existing["url"].as_str()) {
+            return Ok(url.to_string());
+        }
+    }
+    unreachable!()
+}
+
+// Attempts at a request GitHub rate limits before the run gives up
+const RATE_LIMIT_ATTEMPTS: u32 = 5;
+// Longest wait for a rate limit to lift; GitHub asking for more fails the run
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(120);
+
+// What a rate-limited response says about when to try again
+#[derive(Default)]
+struct RateLimit {
+    // Retry-After, sent with secondary (abuse) limits
+    retry_after: Option<u64>,
+    // X-RateLimit-Remaining / X-RateLimit-Reset of the primary hourly limit
+    remaining: Option<u64>,
+    reset: Option<i64>,
+}
+
+impl RateLimit {
+    fn from_headers(response: &ureq::Response) -> Self {
+        let header = |name| response.header(name).and_then(|value| value.trim().parse().ok());
+        Self {
+            retry_after: header("retry-after"),
+            remaining: header("x-ratelimit-remaining"),
+            reset: header("x-ratelimit-reset").map(|reset: u64| reset as i64),
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.remaining == Some(0) || self.retry_after.is_some()
+    }
+
+    // Sleep until the request can be retried, or fail with when the limit
+    // lifts if that is too far off or `attempt` was the last one
+    fn wait(&self, what: &str, attempt: u32) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let wait = match (self.retry_after, self.reset) {
+            (Some(seconds), _) => Duration::from_secs(seconds),
+            (None, Some(reset)) if self.remaining == Some(0) => Duration::from_secs(reset.saturating_sub(now).max(1) as u64),
+            // Secondary limits without a Retry-After: back off exponentially
+            _ => Duration::from_secs(5 << attempt),
+        };
+        if wait > MAX_RATE_LIMIT_WAIT || attempt + 1 >= RATE_LIMIT_ATTEMPTS {
+            let until = chrono::DateTime::from_timestamp(now + wait.as_secs() as i64, 0)
+                .map(|until| until.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+                .unwrap_or_default();
+            anyhow::bail!(CodedError::new(ErrorCode::RateLimited, format!("{}: rate limited by GitHub until {}", what, until)));
+        }
+        eprintln!("⚠️  Rate limited by GitHub; retrying {} in {}s", what, wait.as_secs());
+        std::thread::sleep(wait + jitter(Duration::from_secs(1)));
+        Ok(())
+    }
+}
+
+// gh reports both the primary ("API rate limit exceeded") and secondary
+// ("exceeded a secondary rate limit") limits with these words
+fn is_rate_limit_message(message: &str) -> bool {
+    message.to_lowercase().contains("rate limit")
+}
+
+// Up to `max` of random delay, so concurrent requests that were limited
+// together don't all retry at the same instant
+fn jitter(max: Duration) -> Duration {
+    // RandomState is seeded randomly, which is all the randomness needed here
+    let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+    max.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+fn parse_scopes(value: &str) -> Vec<String> {
+    value.split(',').map(|scope| scope.trim().to_string()).filter(|scope| !scope.is_empty()).collect()
+}
+
+// The gh CLI, one process per request
+pub struct GhCli;
+
+impl GhCli {
+    // Run a gh command, retrying while GitHub rate limits it and after
+    // transient failures of commands that are safe to repeat. gh doesn't pass
+    // on the rate limit headers, so the waits are plain exponential backoff.
+    fn run(&self, args: &[&str], verbose: bool) -> Result<String> {
+        let what = match args {
+            ["gh", "api", "-X", method, path, ..] => format!("{} {}", method, path),
+            _ => args[..args.len().min(4)].join(" "),
+        };
+        // Commands that post something aren't repeated after a transient
+        // failure, which may have happened after GitHub acted on them
+        let idempotent = !(matches!(args, ["gh", "pr", "create" | "comment", ..] | ["gh", "api", "-X", "POST", ..])
+            || args.starts_with(&["gh", "pr", "close"]) && args.contains(&"--comment"));
+
+        for attempt in 0.. {
+            let error = match crate::run_command(args, false, verbose) {
+                Err(e) => e,
+                result => return result,
+            };
+            // Only gh's stderr counts; the arguments may well mention rate limits
+            let message = format!("{:#}", error);
+            let stderr = message.rsplit_once("Stderr:").map_or("", |(_, stderr)| stderr);
+            if is_rate_limit_message(stderr) {
+                RateLimit::default().wait(&what, attempt)?;
+            } else if !(idempotent && is_transient(&error) && retry_transient(&format!("{} failed", what), attempt)) {
+                return Err(error);
+            }
+        }
+        unreachable!()
+    }
+}
+
+impl Forge for GhCli {
+    fn create_pr(&self, repo: &str, pr: &NewPullRequest, verbose: bool) -> Result<String> {
+        let output = self.run(&[
+            "gh", "pr", "create", "-R", repo, "--head", pr.head, "--base", pr.base, "--title", pr.title, "--body", pr.body
+        ], verbose)?;
+        output.lines().map(str::trim).filter(|line| line.starts_with("https://")).find_map(|url| {
+            pr_number_from_url(url).map(|_| url.to_string())
+        }).with_context(|| format!("Could not find the URL of the PR created for {}", pr.head))
+    }
+
+    fn edit_pr(&self, repo: &str, number: u32, edit: &PrEdit, verbose: bool) -> Result<()> {
+        let number = number.to_string();
+        let mut args = vec!["gh", "pr", "edit", &number, "-R", repo];
+        for (flag, value) in [("--base", edit.base), ("--title", edit.title), ("--body", edit.body)] {
+            if let Some(value) = value {
+                args.extend([flag, value]);
+            }
+        }
+        self.run(&args, verbose)?;
+        Ok(())
+    }
+
+    fn close_pr(&self, repo: &str, number: u32, comment: Option<&str>, verbose: bool) -> Result<()> {
+        let number = number.to_string();
+        let mut args = vec!["gh", "pr", "close", &number, "-R", repo];
+        if let Some(comment) = comment {
+            args.extend(["--comment", comment]);
+        }
+        self.run(&args, verbose)?;
+        Ok(())
+    }
+
+    fn comment_pr(&self, repo: &str, number: u32, body: &str, verbose: bool) -> Result<()> {
+        self.run(&["gh", "pr", "comment", &number.to_string(), "-R", repo, "--body", body], verbose)?;
+        Ok(())
+    }
+
+    fn view_pr(&self, repo: &str, pr: &str, fields: &[&str], verbose: bool) -> Result<Option<Value>> {
+        let output = crate::run_command(&["gh", "pr", "view", pr, "-R", repo, "--json", &fields.join(",")], true, verbose)?;
+        Ok(serde_json::from_str::<Value>(&output).ok().filter(Value::is_object))
+    }
+
+    fn graphql(&self, query: &str, variables: Value, verbose: bool) -> Result<Value> {
+        let mut fields = Vec::new();
+        for (name, value) in variables.as_object().into_iter().flatten() {
+            // -F would turn strings such as "123" into numbers
+            let flag = if value.is_string() { "-f" } else { "-F" };
+            let value = value.as_str().map_or_else(|| value.to_string(), str::to_string);
+            fields.extend([flag.to_string(), format!("{}={}", name, value)]);
+        }
+        let query = format!("query={}", query);
+        let mut args = vec!["gh", "api", "graphql", "-f", &query];
+        args.extend(fields.iter().map(String::as_str));
+        let output = self.run(&args, verbose)?;
+        let response: Value = serde_json::from_str(&output).context("Failed to parse GraphQL response")?;
+        Ok(response["data"].clone())
+    }
+
+    fn rest(&self, method: &str, path: &str, body: Value, verbose: bool) -> Result<Value> {
+        let mut args: Vec<String> = ["gh", "api", "-X", method, path].map(str::to_string).into();
+        for (name, value) in body.as_object().into_iter().flatten() {
+            match value {
+                Value::String(value) => args.extend(["-f".to_string(), format!("{}={}", name, value)]),
+                Value::Array(items) => {
+                    for item in items {
+                        let item = item.as_str().map_or_else(|| item.to_string(), str::to_string);
+                        args.extend(["-f".to_string(), format!("{}[]={}", name, item)]);
+                    }
+                }
+                value => args.extend(["-F".to_string(), format!("{}={}", name, value)]),
+            }
+        }
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = self.run(&args, verbose)?;
+        Ok(serde_json::from_str(&output).unwrap_or(Value::Null))
+    }
+
+    fn token_scopes(&self, verbose: bool) -> Result<Option<Vec<String>>> {
+        let output = crate::run_command(&["gh", "api", "-i", "/"], true, verbose)?;
+        Ok(output.lines()
+            .take_while(|line| !line.trim().is_empty())
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("x-oauth-scopes").then(|| parse_scopes(value))
+            }))
+    }
+}
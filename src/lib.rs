@@ -0,0 +1,16 @@
+//! Stack detection used by almighty-push, for other jj tooling.
+//!
+//! ```no_run
+//! use almighty_push::stack::Stack;
+//!
+//! let stack = Stack::from_revset("main@origin..@")?;
+//! for rev in stack.revisions() {
+//!     println!("{} {}", &rev.change_id[..8], rev.description);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`stack`] and [`state`] follow semver; the CLI's other internals are private.
+
+pub mod stack;
+pub mod state;
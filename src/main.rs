@@ -1,20 +1,43 @@
+mod auth;
+mod checklist;
+mod codeowners;
+mod config;
+mod crash;
+mod description;
+mod errors;
+mod forge;
+mod output;
+mod paths;
+mod ssh;
+mod template;
+
 use anyhow::{bail, Context, Result};
-use chrono;
-use clap::Parser;
-use regex;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use chrono::Datelike;
+use clap::{Parser, Subcommand};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{self, Command};
+use std::sync::{Mutex, OnceLock};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use almighty_push::stack::{self, branch_name_for, Revision, Stack};
+use almighty_push::state::{Backport, Backup, BasePr, Operation, PrInfo, State, STATE_VERSION};
+use config::{Config, CopyUrls, MergeMethod, OutOfOrderMerge, SplitSuccessor, StackOrder, UpdateBranch, DEFAULT_BRANCH_PREFIX};
+use errors::{CodedError, ErrorCode};
+use forge::{NewPullRequest, PrEdit};
+use output::OutputFormat;
+
 /// Push jj stacks to GitHub as PRs
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = "Almighty Push - Automated jj stack pusher and PR creator for GitHub.\nPushes all changes in current stack above main and creates properly stacked PRs.")]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Show what would be done without actually doing it
-    #[arg(long)]
+    #[arg(long, global = true)]
     dry_run: bool,
 
     /// Delete remote branches when closing orphaned PRs
@@ -25,72 +48,387 @@ struct Args {
     #[arg(long)]
     no_pr: bool,
 
+    /// Reset PR bases to the computed stack order, even if they were changed manually
+    #[arg(long)]
+    force_bases: bool,
+
+    /// Push stacks deeper than max_stack_depth even when enforce_max_stack_depth is set
+    #[arg(long)]
+    force: bool,
+
+    /// Abort instead of force-pushing over a remote branch that has diverged
+    #[arg(long)]
+    no_force: bool,
+
+    /// How to resolve divergent changes (one change ID, several commits) in the stack
+    #[arg(long, value_enum, default_value_t = DivergencePolicy::Ask)]
+    divergence: DivergencePolicy,
+
+    /// jj remote to fetch from and push to (default: origin, or the only remote if there's no origin)
+    #[arg(long, global = true, value_name = "NAME")]
+    remote: Option<String>,
+
+    /// jj remote of the repository PRs are opened on, when branches are pushed to a fork with --remote (overrides pr_remote)
+    #[arg(long, global = true, value_name = "NAME")]
+    pr_remote: Option<String>,
+
+    /// Build PR bodies from this template file instead of the repository's pull request template
+    #[arg(long, global = true, value_name = "PATH")]
+    body_template: Option<PathBuf>,
+
+    /// Branch the stack is based on and PRs target, instead of the default branch (overrides base_branch)
+    #[arg(long, global = true, value_name = "BRANCH")]
+    base: Option<String>,
+
+    /// Request review from these users or org/teams on every PR, on top of the reviewers config
+    #[arg(long = "reviewer", value_name = "USER", value_delimiter = ',')]
+    reviewers: Vec<String>,
+
+    /// Request reviews from the CODEOWNERS owners of each PR's files
+    #[arg(long)]
+    codeowners: bool,
+
+    /// Add these labels to every PR, on top of the labels config
+    #[arg(long = "label", value_name = "LABEL", value_delimiter = ',')]
+    labels: Vec<String>,
+
+    /// Stack on top of another PR's branch until that PR merges
+    #[arg(long, value_name = "NUMBER")]
+    base_pr: Option<u32>,
+
+    /// Open PRs in your name for commits authored by someone else in the stack
+    #[arg(long, conflicts_with = "exclude_foreign")]
+    include_foreign: bool,
+
+    /// Leave other authors' commits at the bottom of the stack out and stack on their branch
+    #[arg(long)]
+    exclude_foreign: bool,
+
+    /// Use a profile from ~/.config/almighty/profiles.toml (overrides ALMIGHTY_PROFILE and the repo's profile)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Output format for results on stdout (run report, dry-run plan, stats)
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Put the stack's PR URLs on the clipboard after the run (all, or just the top one)
+    #[arg(long, value_enum, value_name = "WHICH", num_args = 0..=1, default_missing_value = "all")]
+    copy: Option<CopyUrls>,
+
+    /// Open PRs created by this run in the browser (first, last or all of them)
+    #[arg(long, value_enum, value_name = "WHICH", num_args = 0..=1, default_missing_value = "all")]
+    open: Option<OpenPrs>,
+
+    /// Also write the run report (or dry-run plan) to this file: JSON for *.json, markdown otherwise
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Print how many jj and GitHub calls were made, and how long they took
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Explain an error code (e.g. AP010) and exit; an unknown code lists them all
+    #[arg(long, value_name = "CODE", num_args = 0..=1, default_missing_value = "")]
+    explain: Option<String>,
+
     /// Enable verbose output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 }
 
-#[derive(Debug, Clone)]
-struct Revision {
-    change_id: String,
-    commit_id: String,
-    description: String,
-    branch_name: Option<String>,
-    pr_number: Option<u32>,
-    pr_url: Option<String>,
-    pr_state: Option<String>,
-    has_conflicts: bool,
-    parent_change_ids: Vec<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Default)]
-struct State {
-    version: u32,
-    prs: HashMap<String, PrInfo>,
-    merged_prs: HashSet<String>,
-    closed_prs: HashSet<String>,
-    last_operation_id: Option<String>,
-    #[serde(default)]
-    stack_order: Vec<String>,
-    #[serde(default)]
-    operations: Vec<Operation>,
-    #[serde(default)]
-    last_updated: Option<String>,
-    #[serde(default)]
-    merged_into_pr: HashMap<String, String>,  // Maps change_id -> PR branch it was merged into
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Operation {
-    id: String,
-    op_type: String,
-    timestamp: String,
-    changes_affected: Vec<String>,
-    success: bool,
-}
-
-const STATE_VERSION: u32 = 2;
-const LOCK_FILE: &str = ".almighty.lock";
-const LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Set up almighty-push in this repository: config, state and doctor checks
+    Init {
+        /// Add almighty-push's state, lock and cache files to .gitignore
+        #[arg(long)]
+        gitignore: bool,
+    },
+    /// Check the local setup and the base branch's protection rules
+    Doctor,
+    /// Check that the stack, its PRs and the state file agree, without changing anything
+    Verify,
+    /// Show each revision's branch, PR, PR state, checks, review and base, without changing anything
+    Status,
+    /// Merge the bottom PR of the stack, then rebase the rest onto the base, retarget and push
+    Land {
+        /// Land every PR from the bottom of the stack up to and including this one
+        /// (the bottom PR alone by default)
+        #[arg(value_name = "PR", conflicts_with = "through")]
+        pr: Option<u32>,
+
+        /// Same as the PR argument
+        #[arg(long, value_name = "PR")]
+        through: Option<u32>,
+
+        /// Keep landing PRs from the bottom while they are approved with passing checks
+        #[arg(long, conflicts_with_all = ["pr", "through"])]
+        all_green: bool,
+
+        /// With --all-green, also land PRs without a review decision, for repositories
+        /// whose base branch doesn't require reviews
+        #[arg(long, requires = "all_green")]
+        allow_unreviewed: bool,
+
+        /// Merge method; defaults to merge_method in the config
+        #[arg(long, value_enum)]
+        method: Option<MergeMethod>,
+
+        /// Wait for pending or missing required checks instead of refusing to land
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Close every open PR in the stack, delete its branches and clear state
+    Abandon {
+        /// Also abandon the local changes with `jj abandon`
+        #[arg(long)]
+        local: bool,
+    },
+    /// Move the stack's PRs onto branches named with the configured branch_prefix
+    RenameBranches,
+    /// Reorder the stack in $EDITOR, rebase it into the new order and push
+    Reorder,
+    /// Look up a change's PR or a PR's change: branch, PR number, URL and state
+    Find {
+        /// Change ID (or prefix), or PR number
+        query: String,
+    },
+    /// Print the URL of the PR for a change ID (or prefix) or PR number
+    Url {
+        query: String,
+    },
+    /// Print the PR of each change tracked in the state file, without network access
+    Annotate {
+        /// Print a jj template expression labelling each tracked change with its PR
+        #[arg(long)]
+        for_template: bool,
+    },
+    /// Add a `jj prs` alias to this repository's jj config that shows each change's PR in `jj log`
+    IntegrateJj,
+    /// Return the repository to where it was before an automatic rebase
+    RestoreBackup {
+        /// Backup to restore (see --list); defaults to the latest
+        #[arg(long)]
+        id: Option<String>,
+
+        /// List the recorded backups instead of restoring one
+        #[arg(long)]
+        list: bool,
+    },
+    /// Copy the stack onto a release branch and open a parallel stack of PRs against it
+    Backport {
+        /// Release branch to copy the stack onto (e.g. release/1.2)
+        #[arg(long)]
+        onto: String,
+    },
+    /// Report PR throughput, time-to-merge and review latency for managed PRs
+    Stats {
+        /// Print the report as JSON (same as --format json)
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show past runs recorded in the state file
+    History {
+        /// Show the details of a single run
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// Export an overview of the stack as a standalone document
+    Export {
+        /// Document format
+        #[arg(long, value_enum, default_value_t = ExportFormat::Markdown)]
+        format: ExportFormat,
+
+        /// Upload the document as a secret gist and print its URL
+        #[arg(long)]
+        gist: bool,
+    },
+    /// Render the PR title and body that would be generated for a revision
+    Preview {
+        /// Change ID (or prefix) of the revision; defaults to the top of the stack
+        change: Option<String>,
+    },
+    /// Write an HTML report of the stack, PR states, checks and run history
+    Report {
+        /// Directory to write index.html into
+        #[arg(long, value_name = "DIR")]
+        html: PathBuf,
+
+        /// Serve the report once on localhost after writing it, for a quick look in a browser
+        #[arg(long)]
+        serve: bool,
+
+        /// Port used by --serve
+        #[arg(long, default_value_t = 8000)]
+        port: u16,
+    },
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct PrInfo {
-    pr_number: u32,
-    pr_url: String,
-    branch_name: String,
-    commit_id: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    change_id: Option<String>,
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Markdown,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum DivergencePolicy {
+    /// Ask which commit to keep (aborts when not running in a terminal)
+    Ask,
+    /// Keep the most recently committed copy
+    Newest,
+    /// Keep the oldest copy
+    Oldest,
+    /// Stop and leave the divergence for `jj` to resolve by hand
+    Abort,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OpenPrs {
+    /// The new PR lowest in the stack
+    First,
+    /// The new PR highest in the stack
+    Last,
+    /// Every new PR
+    All,
 }
 
+const LOCK_FILE: &str = ".almighty.lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+// Age after which another machine may take over a remote lock
+const REMOTE_LOCK_STALE: Duration = Duration::from_secs(600);
+// GitHub computes mergeability in the background after a push; poll this often
+const MERGEABLE_POLLS: usize = 3;
+const MERGEABLE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+// How long `land` waits for GitHub to report an accepted merge (--wait waits
+// LAND_CHECKS_TIMEOUT instead), and how often it looks
+const LAND_MERGE_TIMEOUT: Duration = Duration::from_secs(60);
+const LAND_MERGE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+// How long `land --wait` waits for required checks, and how often it looks
+const LAND_CHECKS_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const LAND_CHECKS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+// Backups kept in the state file
+const MAX_BACKUPS: usize = 20;
+// Share of the diff a new change must have in common with a vanished one to
+// be offered its PR
+const RECREATED_SIMILARITY: f64 = 0.8;
+
+
+fn main() {
+    crash::install();
+
+    let result = run();
+    forge::finish();
+    if let Err(err) = result {
+        eprintln!("Error: {:?}", err);
+        if let Some(coded) = err.downcast_ref::<CodedError>() {
+            eprintln!("Run `almighty-push --explain {}` for causes and fixes", coded.code.code());
+        }
+        process::exit(1);
+    }
+}
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
     let args = Args::parse();
 
+    if let Some(code) = &args.explain {
+        errors::explain(code);
+        return Ok(());
+    }
+
     if args.verbose {
         eprintln!("almighty-push v{}", env!("CARGO_PKG_VERSION"));
     }
 
+    // These only read the state file and jj, so skip config and auth; annotate
+    // runs inside every `jj prs`, and all of them work offline
+    match args.command {
+        Some(Commands::Annotate { for_template }) => return annotate(for_template),
+        Some(Commands::History { ref id }) => return show_history(id.as_deref()),
+        Some(Commands::RestoreBackup { ref id, list }) => return restore_backup(id.as_deref(), list, args.dry_run, args.verbose),
+        _ => {}
+    }
+
+    let config = Config::load()?;
+    select_remotes(args.remote.as_deref(), args.pr_remote.as_deref().or(config.pr_remote.as_deref()), args.verbose)?;
+    if let Some(base) = args.base.clone().or_else(|| config.base_branch.clone()) {
+        let _ = BASE_BRANCH.set(base);
+    }
+    if config.use_pr_template || args.body_template.is_some() {
+        if let Some(template) = template::pr_template(args.body_template.as_deref())? {
+            let _ = PR_TEMPLATE.set(template);
+        }
+    }
+    apply_proxy(&config, args.verbose);
+    // init and preview work from the config and the local repository; only
+    // commands that talk to GitHub look up credentials
+    if !matches!(args.command, Some(Commands::Init { .. } | Commands::Preview { .. })) {
+        let profile = args.profile.clone()
+            .or_else(|| std::env::var("ALMIGHTY_PROFILE").ok())
+            .or_else(|| config.profile.clone());
+        if let Some(profile) = &profile {
+            auth::apply_profile(profile, args.verbose)?;
+        }
+        auth::authenticate(&config, args.verbose)?;
+        forge::select(config.forge);
+    }
+
+    let result = match args.command {
+        Some(Commands::Init { gitignore }) => init_repo(gitignore, args.dry_run, args.verbose),
+        Some(Commands::Doctor) => run_doctor(args.verbose),
+        Some(Commands::Verify) => verify_stack(&args),
+        Some(Commands::Status) => show_status(&args),
+        Some(Commands::Land { pr, through, all_green, allow_unreviewed, method, wait }) => {
+            land_stack(pr.or(through), all_green.then_some(allow_unreviewed), method, wait, &args)
+        }
+        Some(Commands::Abandon { local }) => abandon_stack(local, args.dry_run, args.verbose),
+        Some(Commands::RenameBranches) => rename_branches(args.dry_run, args.verbose),
+        Some(Commands::Reorder) => reorder_stack(&args),
+        Some(Commands::Backport { ref onto }) => backport_stack(onto, &args),
+        Some(Commands::Find { ref query }) => find_pr(query, false, &args),
+        Some(Commands::Url { ref query }) => find_pr(query, true, &args),
+        Some(Commands::Annotate { .. } | Commands::History { .. } | Commands::RestoreBackup { .. }) => {
+            unreachable!("handled before loading the config")
+        }
+        Some(Commands::IntegrateJj) => integrate_jj(args.dry_run, args.verbose),
+        Some(Commands::Stats { json }) => show_stats(json || args.format == OutputFormat::Json, args.verbose),
+        Some(Commands::Export { format, gist }) => export_stack(format, gist, args.verbose),
+        Some(Commands::Preview { ref change }) => preview_pr(change.as_deref(), args.verbose),
+        Some(Commands::Report { ref html, serve, port }) => write_html_report(html, serve, port, args.verbose),
+        None => push_stack(&args),
+    };
+
+    if args.timings {
+        print_call_report();
+    }
+    result
+}
+
+// Export the configured proxy to gh and jj's git transport. Proxy variables
+// already present in the environment take precedence over the config.
+fn apply_proxy(config: &Config, verbose: bool) {
+    let has_env = |names: &[&str]| names.iter().any(|name| std::env::var_os(name).is_some());
+
+    if let Some(proxy) = &config.proxy {
+        if !has_env(&["HTTPS_PROXY", "https_proxy"]) {
+            std::env::set_var("HTTPS_PROXY", proxy);
+        }
+        if !has_env(&["HTTP_PROXY", "http_proxy"]) {
+            std::env::set_var("HTTP_PROXY", proxy);
+        }
+    }
+    if !config.no_proxy.is_empty() && !has_env(&["NO_PROXY", "no_proxy"]) {
+        std::env::set_var("NO_PROXY", config.no_proxy.join(","));
+    }
+
+    if verbose {
+        if let Some(proxy) = std::env::var_os("HTTPS_PROXY").or_else(|| std::env::var_os("https_proxy")) {
+            eprintln!("[debug] Using proxy {}", proxy.to_string_lossy());
+        }
+    }
+}
+
+// Push the current stack and create/update its PRs (default command)
+fn push_stack(args: &Args) -> Result<()> {
     // Get repository info from jj remote
     let repo_info = get_repo_info(args.verbose)?;
     if args.verbose {
@@ -104,14 +442,34 @@ fn main() -> Result<()> {
     if args.verbose {
         eprintln!("Fetching from remote...");
     }
-    run_command(&["jj", "git", "fetch"], false, args.verbose)?;
+    fetch_remotes(args.verbose)?;
     
     // Load and migrate state
     let mut state = load_state()?;
     migrate_state(&mut state)?;
+    let mut config = load_config(args.verbose)?;
+    config.reviewers.extend(args.reviewers.iter().cloned());
+    config.labels.extend(args.labels.iter().cloned());
+    config.codeowners |= args.codeowners;
+    let code_owners = config.codeowners.then(codeowners::load).flatten();
+    let _remote_lock = acquire_remote_lock(&config, &repo_info, args.dry_run, args.verbose)?;
+
+    sync_default_branch(&mut state, &repo_info, args.dry_run, args.verbose)?;
+    let trunk_name = trunk(&state).to_string();
+    if BASE_BRANCH.get().is_some() && remote_commit_on(&trunk_name, pr_remote(), args.verbose)?.is_none() {
+        bail!(CodedError::new(ErrorCode::BaseBranchMissing, format!("Base branch {} doesn't exist on {}", trunk_name, pr_remote())));
+    }
+
+    if let Some(number) = args.base_pr {
+        track_base_pr(&mut state, number, &repo_info, args.verbose)?;
+    }
+    follow_base_pr(&mut state, &repo_info, args.dry_run, args.verbose)?;
 
     // Get current stack
-    let mut revisions = get_stack_revisions(args.verbose)?;
+    let mut revisions = get_stack_revisions(&stack_base(&state), args.verbose)?;
+    // Private commits (and anything built on them) never reach GitHub
+    let private = private_revset(&config, args.verbose);
+    let mut withheld = withhold_private_commits(&mut revisions, private.as_deref(), args.verbose)?;
     if revisions.is_empty() {
         if args.verbose {
             eprintln!("No revisions to push");
@@ -119,6 +477,86 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    // A change with several visible commits can't map to a single branch
+    let abandoned = resolve_divergent_changes(&revisions, args.divergence, args.dry_run, args.verbose)?;
+    if !abandoned.is_empty() {
+        if args.dry_run {
+            revisions.retain(|r| !abandoned.contains(&r.commit_id));
+        } else {
+            revisions = get_stack_revisions(&stack_base(&state), args.verbose)?;
+            withheld = withhold_private_commits(&mut revisions, private.as_deref(), args.verbose)?;
+        }
+    }
+
+    // In stateless mode the PR mappings travel with the commits themselves
+    if config.stateless {
+        recover_state_from_trailers(&mut state, &revisions, &config);
+    }
+
+    // Commits at the bottom that another pushed stack builds on stay with that
+    // stack; ours is based on its branch instead of duplicating their PRs
+    if state.base_pr.is_none() {
+        let shared = shared_stack_prefix(&revisions, &state, &config, args.verbose)?;
+        if shared > 0 {
+            let top = &revisions[shared - 1];
+            let branch = tracked_branch(&state, &config, &top.change_id);
+            let pr = view_pr(&repo_info, &branch, &["number", "state"], args.verbose);
+
+            if let (Some(number), Some("OPEN")) = (pr["number"].as_u64(), pr["state"].as_str()) {
+                eprintln!("Bottom {} revision(s) belong to another pushed stack; stacking on PR #{} ({})",
+                         shared, number, branch);
+                let shared_ids: Vec<String> = revisions.drain(..shared).map(|r| r.change_id).collect();
+                // Their PRs are managed from the other stack now, never closed as orphans of this one
+                state.prs.retain(|id, _| !shared_ids.iter().any(|s| id.starts_with(s.as_str()) || s.starts_with(id.as_str())));
+                state.base_pr = Some(BasePr { number: number as u32, branch });
+                record_action(format!("Stacked on shared PR #{}", number));
+            }
+        }
+    }
+    if revisions.is_empty() {
+        eprintln!("Every revision already belongs to another pushed stack; nothing to push");
+        return Ok(());
+    }
+
+    // Someone else's commits (e.g. unmerged work the stack was rebased onto)
+    // only get PRs in our name when asked to
+    let foreign = foreign_revisions(&revisions, &state, args.verbose)?;
+    if !foreign.is_empty() && !args.include_foreign {
+        eprintln!("⚠️  {} revision(s) were authored by someone else:", foreign.len());
+        for &i in &foreign {
+            eprintln!("  - {} ({})", revisions[i].description, &revisions[i].change_id[..8]);
+        }
+        if !args.exclude_foreign {
+            bail!(CodedError::new(ErrorCode::ForeignCommits,
+                "Re-run with --include-foreign to open PRs for them in your name, or --exclude-foreign to stack on their branch"));
+        }
+        exclude_foreign_revisions(&mut revisions, &foreign, &mut state, &repo_info, args.verbose)?;
+    }
+
+    // A huge stack usually means the base is wrong rather than a real stack
+    if revisions.len() > config.max_stack_depth {
+        eprintln!("⚠️  Stack has {} revisions (max_stack_depth is {})", revisions.len(), config.max_stack_depth);
+        eprintln!("   Check that your changes are based on an up-to-date {}", stack_base(&state));
+        if config.enforce_max_stack_depth && !args.force {
+            bail!(CodedError::new(ErrorCode::StackTooDeep, "Stack too deep; re-run with --force to push it anyway"));
+        }
+    }
+
+    // Strict mode enforces the team's description conventions before anything is pushed
+    if config.strict {
+        let missing: Vec<&Revision> = revisions.iter()
+            .filter(|rev| description::test_plan(&rev.body).is_none())
+            .collect();
+        if !missing.is_empty() {
+            eprintln!("⚠️  {} revision(s) have no test plan:", missing.len());
+            for rev in &missing {
+                eprintln!("  - {} ({})", rev.description, &rev.change_id[..8]);
+            }
+            eprintln!("   Add a `Test Plan:` or `## Testing` section to the description");
+            bail!(CodedError::new(ErrorCode::MissingTestPlan, "Strict mode: test plan required"));
+        }
+    }
+
     // Track operation for recovery
     let op_id = track_operation_start(&mut state, "push_stack", &revisions)?;
 
@@ -126,10 +564,29 @@ fn main() -> Result<()> {
     let squashed = detect_squashed_commits(&mut revisions, &state, args.verbose)?;
     let conflicts = check_for_conflicts(&mut revisions, args.verbose)?;
     let reordered = detect_reordered_stack(&revisions, &state)?;
-    let splits = detect_split_commits(&revisions, &state, args.verbose)?;
+    let mut splits = detect_split_commits(&revisions, &state, args.verbose)?;
+    let mut folds = detect_folded_changes(&revisions, &state, &splits, args.verbose)?;
+    let recreated = detect_recreated_changes(&revisions, &state, &splits, &folds, args.verbose)?;
     
     // Check for merged PRs and handle them
     let merged = detect_merged_prs(&mut revisions, &state, &repo_info, args.verbose)?;
+
+    // Changes that sat directly above a PR merged into main are now the bottom of the stack
+    let unblocked: Vec<String> = merged.iter()
+        .filter(|(_, _, base)| base.as_deref().is_none_or(|b| b == trunk_name))
+        .filter_map(|(_, change_id, _)| {
+            let pos = state.stack_order.iter()
+                .position(|id| id.starts_with(change_id.as_str()) || change_id.starts_with(id.as_str()))?;
+            state.stack_order.get(pos + 1).cloned()
+        })
+        .collect();
+
+    // Branches of merged PRs, deleted once their children have been retargeted
+    let mut merged_branches: Vec<String> = merged.iter()
+        .filter_map(|(_, change_id, _)| state.prs.get(change_id))
+        .map(|pr_info| pr_info.branch_name.clone())
+        .collect();
+
     if !merged.is_empty() {
         // Separate PRs that are still in stack from those that were merged into other PRs
         let in_stack: Vec<_> = merged.iter()
@@ -144,12 +601,42 @@ fn main() -> Result<()> {
 
         // Handle PRs that are still in the stack (need rebasing)
         if !in_stack.is_empty() {
-            handle_merged_prs(&in_stack, &mut revisions, args.verbose)?;
+            // PRs merged ahead of the ones below them follow out_of_order_merge;
+            // the bottom of the stack merging into main is always rebased away
+            let (out_of_order, in_order): (Vec<_>, Vec<_>) = in_stack.iter().cloned()
+                .partition(|(idx, _, base)| is_out_of_order_merge(&revisions, *idx, base.as_deref(), &config, &trunk_name));
+            let policy = config.out_of_order_merge;
+            let kept: HashSet<String> = match policy {
+                OutOfOrderMerge::Rebase => HashSet::new(),
+                OutOfOrderMerge::Retarget | OutOfOrderMerge::Warn => out_of_order.iter().map(|(_, id, _)| id.clone()).collect(),
+            };
+            if policy == OutOfOrderMerge::Warn {
+                // Their children still target these branches; deleting them
+                // would make GitHub close the children's PRs
+                let kept_branches: Vec<&String> = kept.iter()
+                    .filter_map(|change_id| state.prs.get(change_id))
+                    .map(|pr_info| &pr_info.branch_name)
+                    .collect();
+                merged_branches.retain(|branch| !kept_branches.contains(&branch));
+                for (idx, _, base) in &out_of_order {
+                    eprintln!("⚠️  PR #{} was merged into {} ahead of the PRs below it; leaving the stack as is (out_of_order_merge = \"warn\")",
+                             revisions[*idx].pr_number.unwrap_or_default(), base.as_deref().unwrap_or(&trunk_name));
+                }
+            }
+
+            let to_rebase = if kept.is_empty() { in_stack.clone() } else { in_order };
+            if !to_rebase.is_empty() {
+                backup_stack(&mut state, "rebasing over merged PRs", &revisions, args.dry_run, args.verbose)?;
+                handle_merged_prs(&to_rebase, &mut revisions, &config, &trunk_name, args.dry_run, args.verbose)?;
+            }
 
             // Handle out-of-order merges for PRs in stack
             for (_, change_id, base_branch) in &in_stack {
+                if policy == OutOfOrderMerge::Warn && kept.contains(change_id) {
+                    continue;
+                }
                 if let Some(ref base) = base_branch {
-                    if base.starts_with("push-") && base != "main" {
+                    if config.is_managed_branch(base) && base != &trunk_name {
                         // Track that this PR was merged into another PR branch
                         state.merged_into_pr.insert(change_id.clone(), base.clone());
                         if args.verbose {
@@ -163,16 +650,26 @@ fn main() -> Result<()> {
                 }
             }
 
-            // Re-fetch stack after rebasing
-            revisions = get_stack_revisions(args.verbose)?;
-            // Re-check for conflicts after rebase
-            check_for_conflicts(&mut revisions, args.verbose)?;
+            if args.dry_run {
+                // Nothing was rebased; drop the merged changes as the rebase would
+                revisions.retain(|r| r.pr_state.as_deref() != Some("MERGED") || kept.contains(&r.change_id));
+            } else if kept.len() < in_stack.len() {
+                // Re-fetch stack after rebasing
+                revisions = get_stack_revisions(&stack_base(&state), args.verbose)?;
+                withheld = withhold_private_commits(&mut revisions, private.as_deref(), args.verbose)?;
+                // Re-check for conflicts after rebase
+                check_for_conflicts(&mut revisions, args.verbose)?;
+                // Merged PRs left in place stay marked so they're neither pushed nor stacked on
+                for rev in revisions.iter_mut().filter(|r| kept.contains(&r.change_id)) {
+                    rev.pr_state = Some("MERGED".to_string());
+                }
+            }
         }
 
         // Handle PRs merged into other PRs but no longer in stack (just track them)
         for (_, change_id, base_branch) in &merged_into_others {
             if let Some(ref base) = base_branch {
-                if base.starts_with("push-") && base != "main" {
+                if config.is_managed_branch(base) && base != &trunk_name {
                     // Track that this PR was merged into another PR branch
                     state.merged_into_pr.insert(change_id.clone(), base.clone());
                     if args.verbose {
@@ -186,14 +683,40 @@ fn main() -> Result<()> {
         }
     }
 
+    // Drop changes whose PR was closed on GitHub (not merged) out of the stack
+    let closed = detect_closed_prs(&revisions, &state, &repo_info, &config, args.verbose)?;
+    if !closed.is_empty() {
+        backup_stack(&mut state, "rebasing over closed PRs", &revisions, args.dry_run, args.verbose)?;
+        handle_closed_prs(&closed, &revisions, &mut state, args.dry_run, args.verbose)?;
+
+        if args.dry_run {
+            let closed_ids: HashSet<&String> = closed.iter().map(|(_, change_id, _)| change_id).collect();
+            revisions.retain(|r| !closed_ids.contains(&r.change_id));
+        } else {
+            revisions = get_stack_revisions(&stack_base(&state), args.verbose)?;
+            withheld = withhold_private_commits(&mut revisions, private.as_deref(), args.verbose)?;
+            check_for_conflicts(&mut revisions, args.verbose)?;
+        }
+    }
+
     // Handle squashed commits
     if !squashed.is_empty() && args.verbose {
         eprintln!("Detected {} squashed commits", squashed.len());
     }
 
-    // Handle split commits if detected
+    // Move the PRs of split changes onto one of their pieces
     if !splits.is_empty() {
-        handle_split_commits(&splits, &mut revisions, &mut state, args.dry_run, args.verbose)?;
+        handle_split_commits(&mut splits, &revisions, &mut state, &config, args.verbose)?;
+    }
+
+    // Hand the PRs of squashed changes to the change they were folded into
+    if !folds.is_empty() {
+        handle_folded_changes(&mut folds, &mut state, args.verbose)?;
+    }
+
+    // Offer the PRs of abandoned changes to their look-alike replacements
+    if !recreated.is_empty() {
+        adopt_recreated_changes(&recreated, &mut state, args.dry_run)?;
     }
 
     // Handle reordered stack if detected
@@ -211,29 +734,102 @@ fn main() -> Result<()> {
             }
         }
         eprintln!("\nResolve conflicts and re-run almighty-push");
-        bail!("Conflicts detected");
+        bail!(CodedError::new(ErrorCode::Conflicts, "Conflicts detected"));
     }
     
+    if config.uses_paths() || code_owners.is_some() {
+        load_changed_paths(&mut revisions, args.verbose)?;
+    }
+    warn_broad_revisions(&revisions, &config, args.verbose)?;
+
+    // Changes that took over the PR of a split or folded change push to its branch
+    for rev in revisions.iter_mut() {
+        rev.branch_name = state.inherited_branches.iter()
+            .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
+            .map(|(_, branch)| branch.clone());
+    }
+
     // Push branches with force-push detection
-    push_branches(&mut revisions, args.dry_run, args.verbose)?;
+    push_branches(&mut revisions, &config.branch_prefix, &repo_info, args.no_force, args.dry_run, args.verbose)?;
+    if !args.dry_run {
+        verify_pushed_branches(&revisions, args.verbose)?;
+    }
 
+    let mut created_urls = Vec::new();
     if !args.no_pr {
+
         // Try to reopen previously closed PRs if they're back in the stack
         reopen_prs(&mut revisions, &state, &repo_info, args.dry_run, args.verbose)?;
 
         // Create/update PRs
-        create_or_update_prs(&mut revisions, &state, &repo_info, args.dry_run, args.verbose)?;
+        created_urls = create_or_update_prs(&mut revisions, &mut state, &repo_info, &config, args.force_bases, args.dry_run, args.verbose)?;
+
+        if config.stateless {
+            record_pr_trailers(&mut revisions, &stack_base(&state), args.dry_run, args.verbose)?;
+        }
+
+        if !splits.is_empty() {
+            link_split_prs(&splits, &revisions, &repo_info, args.dry_run, args.verbose)?;
+        }
+
+        let behind = check_mergeability(&mut revisions, &repo_info, args.verbose)?;
+        if let Some(how) = config.update_branch.filter(|_| !behind.is_empty()) {
+            if how == UpdateBranch::Rebase {
+                backup_stack(&mut state, "rebasing PRs behind their base", &revisions, args.dry_run, args.verbose)?;
+            }
+            update_behind_prs(&behind, &mut revisions, how, &repo_info, &config, args.dry_run, args.verbose)?;
+        }
+
+        fetch_check_status(&mut revisions, &repo_info, args.verbose);
 
         // Detect and fix PR dependency cycles
-        detect_and_fix_cycles(&revisions, &repo_info, args.dry_run, args.verbose)?;
+        detect_and_fix_cycles(&revisions, &repo_info, &trunk_name, args.dry_run, args.verbose)?;
+
+        // Update PR descriptions, or the stack comments, with stack info
+        if config.stack_comment {
+            update_stack_comments(&revisions, &repo_info, &config, args.dry_run, args.verbose)?;
+        } else {
+            update_pr_descriptions(&revisions, &repo_info, &config, args.dry_run, args.verbose)?;
+        }
+
+        apply_labels(&revisions, &mut state, &repo_info, &config, args.dry_run, args.verbose)?;
+        if let Some(label) = &config.blocked_label {
+            update_blocked_labels(&revisions, &mut state, &repo_info, label, &trunk_name, args.dry_run, args.verbose)?;
+        }
+        request_reviewers(&revisions, &mut state, &repo_info, &config, code_owners.as_ref(), args.dry_run, args.verbose)?;
+
+        if config.delete_merged_branches {
+            delete_merged_branches(&merged_branches, args.dry_run, args.verbose)?;
+        }
+
+        // Drafts whose parent just merged are now reviewable
+        if config.auto_ready_drafts && !unblocked.is_empty() {
+            ready_unblocked_drafts(&revisions, &unblocked, &repo_info, &trunk_name, args.dry_run, args.verbose)?;
+        }
 
-        // Update PR descriptions with stack info
-        update_pr_descriptions(&revisions, &repo_info, args.dry_run, args.verbose)?;
+        if !folds.is_empty() {
+            close_folded_prs(&folds, &mut state, &repo_info, args.delete_branches, args.dry_run, args.verbose)?;
+        }
 
         // Close orphaned PRs (including squashed ones)
-        close_orphaned_prs(&revisions, &mut state, &squashed, &repo_info, args.delete_branches, args.dry_run, args.verbose)?;
+        let orphans = find_orphaned_prs(&revisions, &withheld, &state, &squashed, &repo_info, &config, args.verbose)?;
+        if !orphans.is_empty() && confirm_close_prs(&orphans, &config, args.dry_run)? {
+            close_orphaned_prs(&orphans, &mut state, &repo_info, args.delete_branches, args.dry_run, args.verbose)?;
+        }
     }
     
+    if args.dry_run {
+        print_plan();
+        let plan = output::Plan { commands: PLAN.lock().unwrap().clone() };
+        if args.format == OutputFormat::Json {
+            output::print_json("plan", &plan)?;
+        }
+        if let Some(path) = &args.output {
+            output::write_report(path, &plan)?;
+        }
+        return Ok(());
+    }
+
     // Mark operation as successful
     track_operation_end(&mut state, &op_id, true)?;
 
@@ -250,123 +846,2511 @@ fn main() -> Result<()> {
             eprintln!("\nStack: {} PRs ({} open, {} merged)",
                      revisions.len(), open_count, merged_count);
         }
-
         for rev in &revisions {
-            if let Some(url) = &rev.pr_url {
-                println!("{}", url);
+            let (Some(pr_number), Some(icon)) = (rev.pr_number, output::checks_icon(rev.pr_checks.as_deref())) else { continue };
+            eprintln!("  {} #{}: {}", icon, pr_number, rev.description);
+        }
+        for rev in revisions.iter().filter(|r| r.pr_mergeable.as_deref() == Some("CONFLICTING")) {
+            eprintln!("⚠️  PR #{} conflicts with its base {}; rebase the stack onto the latest {} and push again",
+                     rev.pr_number.unwrap_or_default(), rev.base_branch.as_deref().unwrap_or_default(), trunk_name);
+        }
+
+        if args.format == OutputFormat::Text {
+            for rev in &revisions {
+                if let Some(url) = &rev.pr_url {
+                    println!("{}", url);
+                }
             }
         }
+
+        if let Some(which) = args.copy.or(config.copy) {
+            copy_pr_urls(&revisions, which, args.verbose);
+        }
+
+        if let Some(which) = args.open {
+            open_created_prs(&created_urls, which, args.verbose)?;
+        }
+    }
+
+    // Branches without a PR (--no-pr, or queued by max_open_prs) can still get one by hand
+    if args.format == OutputFormat::Text {
+        print_compare_urls(&revisions, &repo_info, base_branch(&state));
+    }
+
+    let report = output::RunReport {
+        repo: repo_info.clone(),
+        base: base_branch(&state).to_string(),
+        revisions: revisions.iter().map(output::RevisionReport::from).collect(),
+    };
+    if args.format == OutputFormat::Json {
+        output::print_json("report", &report)?;
+    }
+    if let Some(path) = &args.output {
+        output::write_report(path, &report)?;
     }
 
     Ok(())
 }
 
-// Lock management
-fn acquire_lock() -> Result<FileLock> {
-    FileLock::acquire()
+// Print a GitHub compare URL for each pushed branch that has no PR, which
+// opens the PR form against the branch below it in one click
+fn print_compare_urls(revisions: &[Revision], repo: &str, base: &str) {
+    let missing: Vec<usize> = (0..revisions.len())
+        .filter(|&i| revisions[i].pr_url.is_none() && revisions[i].branch_name.is_some())
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+
+    eprintln!("\n{} branch{} without a PR; open one from:", missing.len(), if missing.len() == 1 { "" } else { "es" });
+    for i in missing {
+        let rev = &revisions[i];
+        let pr_base = match (&rev.base_branch, i) {
+            (Some(base), _) => base.as_str(),
+            (None, 0) => base,
+            (None, _) => revisions[i - 1].branch_name.as_deref().unwrap_or(base),
+        };
+        println!("{}", compare_url(repo, pr_base, rev.branch_name.as_deref().unwrap_or_default()));
+    }
 }
 
-struct FileLock {
-    _file: File,
+// Page that opens a PR for `branch` against `base`; the head is qualified
+// with the fork's owner when PRs come from a fork
+fn compare_url(repo: &str, base: &str, branch: &str) -> String {
+    format!("{}/{}/compare/{}...{}?expand=1", forge::web_url(), repo, base, pr_head(branch))
 }
 
-impl FileLock {
-    fn acquire() -> Result<Self> {
-        let start = Instant::now();
-        loop {
-            match OpenOptions::new().write(true).create_new(true).open(LOCK_FILE) {
-                Ok(mut file) => {
-                    let pid = process::id();
-                    writeln!(file, "{}", pid)?;
-                    return Ok(Self { _file: file });
-                }
-                Err(_) if start.elapsed() > LOCK_TIMEOUT => {
-                    bail!("Failed to acquire lock after {} seconds", LOCK_TIMEOUT.as_secs());
-                }
-                Err(_) => {
-                    // Check if stale
-                    if let Ok(mut file) = File::open(LOCK_FILE) {
-                        let mut content = String::new();
-                        file.read_to_string(&mut content)?;
-                        if let Ok(_pid) = content.trim().parse::<u32>() {
-                            // Simple check - in production would verify process exists
-                            let age = fs::metadata(LOCK_FILE)?.modified()?;
-                            if SystemTime::now().duration_since(age)? > Duration::from_secs(600) {
-                                fs::remove_file(LOCK_FILE)?;
-                                continue;
-                            }
-                        }
-                    }
-                    std::thread::sleep(Duration::from_millis(100));
-                }
-            }
-        }
+// Open newly created PRs in the browser, like `gh pr create --web`
+fn open_created_prs(urls: &[String], which: OpenPrs, verbose: bool) -> Result<()> {
+    let urls = match which {
+        OpenPrs::First => &urls[..urls.len().min(1)],
+        OpenPrs::Last => &urls[urls.len().saturating_sub(1)..],
+        OpenPrs::All => urls,
+    };
+    for url in urls {
+        eprintln!("Opening {} in your browser", url);
+        open_in_browser(url, verbose)?;
     }
+    Ok(())
 }
 
-impl Drop for FileLock {
-    fn drop(&mut self) {
-        let _ = fs::remove_file(LOCK_FILE);
-    }
+// Open a URL with the system's default browser
+fn open_in_browser(url: &str, verbose: bool) -> Result<()> {
+    let opener: &[&str] = if cfg!(target_os = "macos") {
+        &["open"]
+    } else if cfg!(windows) {
+        // The empty argument is start's window title
+        &["cmd", "/C", "start", ""]
+    } else {
+        &["xdg-open"]
+    };
+    let mut command = opener.to_vec();
+    command.push(url);
+    run_command(&command, false, verbose).map(drop)
 }
 
-fn get_stack_revisions(verbose: bool) -> Result<Vec<Revision>> {
-    let output = run_command(&[
-        "jj", "log", "-r", "main@origin..@", "--no-graph",
-        "--template", r#"change_id ++ "|" ++ commit_id ++ "|" ++ if(description, description.first_line(), "(no description)") ++ "|" ++ if(conflict, "true", "false") ++ "|" ++ parents.map(|p| p.change_id()).join(",") ++ "\n""#
-    ], false, verbose)?;
+// Put the stack's PR URLs on the system clipboard
+fn copy_pr_urls(revisions: &[Revision], which: CopyUrls, verbose: bool) {
+    let urls: Vec<&str> = revisions.iter().filter_map(|r| r.pr_url.as_deref()).collect();
+    let text = match which {
+        CopyUrls::All => urls.join("\n"),
+        CopyUrls::Top => urls.last().map(|url| url.to_string()).unwrap_or_default(),
+    };
+    if text.is_empty() {
+        return;
+    }
 
-    let mut revisions = Vec::new();
-    let mut skipped_count = 0;
+    match copy_to_clipboard(&text, verbose) {
+        Ok(()) => eprintln!("Copied {} PR URL{} to the clipboard",
+                            text.lines().count(), if text.lines().count() == 1 { "" } else { "s" }),
+        Err(e) => eprintln!("⚠️  Could not copy PR URLs: {}", e),
+    }
+}
 
-    for line in output.lines() {
-        if line.trim().is_empty() { continue; }
-        let parts: Vec<&str> = line.split('|').collect();
-        if parts.len() >= 5 {
-            let change_id = parts[0].to_string();
-            if change_id == "zzzzzzzzzzzz" { continue; } // Skip root
+// Pipe text into the first clipboard tool available on this system
+fn copy_to_clipboard(text: &str, verbose: bool) -> Result<()> {
+    const TOOLS: &[&[&str]] = &[
+        &["pbcopy"],
+        &["wl-copy"],
+        &["xclip", "-selection", "clipboard"],
+        &["xsel", "--clipboard", "--input"],
+        &["clip.exe"],
+    ];
+
+    for tool in TOOLS {
+        let Ok(mut child) = Command::new(tool[0]).args(&tool[1..])
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn() else { continue };
+        if verbose {
+            eprintln!("[debug] Copying with {}", tool.join(" "));
+        }
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+    bail!("no clipboard tool found (pbcopy, wl-copy, xclip, xsel or clip.exe)")
+}
 
-            let parent_ids = if parts[4].is_empty() {
-                Vec::new()
-            } else {
-                parts[4].split(',').map(|s| s.to_string()).collect()
-            };
+// First-run setup: detect the GitHub repository, write a starter config and
+// an empty state file at the workspace root, then run the doctor checks
+fn init_repo(gitignore: bool, dry_run: bool, verbose: bool) -> Result<()> {
+    let root = run_command(&["jj", "root"], false, verbose)
+        .context("Not inside a jj repository (run `jj git init --colocate` first)")?;
+    let root = PathBuf::from(root.trim());
+    let cwd = std::env::current_dir()?;
+    if fs::canonicalize(&root)? != fs::canonicalize(&cwd)? {
+        bail!(CodedError::new(ErrorCode::WrongDirectory, format!(
+            "almighty-push keeps its files in the directory it runs from; run init (and almighty-push) from {}", root.display())));
+    }
 
-            let description = parts[2].to_string();
+    let repo = get_repo_info(verbose)?;
+    eprintln!("Repository: {}", repo);
 
-            // Skip commits without descriptions as jj won't push them
-            if description == "(no description)" {
-                skipped_count += 1;
-                if verbose {
-                    eprintln!("  Skipping commit {} with no description", &change_id[..8]);
-                }
-                continue;
-            }
+    let action = if dry_run { "Would write" } else { "Wrote" };
+    if Path::new(config::CONFIG_FILE).exists() {
+        eprintln!("{} already exists, leaving it as is", config::CONFIG_FILE);
+    } else {
+        if !dry_run {
+            fs::write(config::CONFIG_FILE, config::starter_config(&repo))?;
+        }
+        eprintln!("{} {}", action, config::CONFIG_FILE);
+    }
 
-            revisions.push(Revision {
-                change_id,
-                commit_id: parts[1].to_string(),
-                description,
-                has_conflicts: parts[3] == "true",
-                parent_change_ids: parent_ids,
-                branch_name: None,
-                pr_number: None,
-                pr_url: None,
-                pr_state: None,
-            });
+    if Path::new(".almighty").exists() {
+        eprintln!(".almighty already exists, leaving it as is");
+    } else {
+        if !dry_run {
+            write_state(&State { version: STATE_VERSION, ..State::default() })?;
         }
+        eprintln!("{} .almighty", action);
     }
 
-    if skipped_count > 0 {
-        eprintln!("⚠️  Skipped {} commit(s) without descriptions", skipped_count);
+    if gitignore {
+        let existing = fs::read_to_string(".gitignore").unwrap_or_default();
+        let missing: Vec<&str> = [".almighty", ".almighty.lock", ".almighty.tmp", ".almighty-cache", ".almighty-cache.*.tmp"].into_iter()
+            .filter(|entry| !existing.lines().any(|line| line.trim().trim_start_matches('/') == *entry))
+            .collect();
+        if missing.is_empty() {
+            eprintln!(".gitignore already ignores almighty-push's files");
+        } else {
+            if !dry_run {
+                let mut content = existing;
+                if !content.is_empty() && !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                content.push_str(&format!("\n# almighty-push\n{}\n", missing.join("\n")));
+                fs::write(".gitignore", content)?;
+            }
+            eprintln!("{} {} to .gitignore", if dry_run { "Would add" } else { "Added" }, missing.join(", "));
+        }
     }
 
-    revisions.reverse(); // Bottom to top order
-    Ok(revisions)
+    eprintln!();
+    run_doctor(verbose)
 }
 
-// Detect squashed commits by checking jj op log
-fn detect_squashed_commits(revisions: &mut [Revision], _state: &State, verbose: bool) -> Result<HashSet<String>> {
+// Check tooling, repository detection and base branch protection compatibility
+fn run_doctor(verbose: bool) -> Result<()> {
+    let mut problems = 0;
+
+    // gh is only required when the forge is gh; otherwise export --gist uses it
+    let needs_gh = Config::load().map(|config| config.forge == config::ForgeKind::Gh).unwrap_or(false);
+    for tool in [["jj", "--version"], ["gh", "--version"]] {
+        match run_command(&tool, false, verbose) {
+            Ok(output) => println!("✓ {}", output.lines().next().unwrap_or(tool[0]).trim()),
+            Err(_) if tool[0] == "gh" && !needs_gh => {
+                println!("⚠️  gh not found in PATH; everything goes through the API except export --gist, which needs it");
+            }
+            Err(_) => {
+                println!("✗ {} not found in PATH", tool[0]);
+                problems += 1;
+            }
+        }
+    }
+
+    match auth::resolve_token(verbose) {
+        Ok((source, _)) => {
+            println!("✓ GitHub token: {}", source);
+            match forge::forge().token_scopes(verbose)? {
+                Some(scopes) if scopes.is_empty() => println!("⚠️  Token has no OAuth scopes"),
+                Some(scopes) => {
+                    println!("✓ Token scopes: {}", scopes.join(", "));
+                    if !scopes.iter().any(|s| s == "repo" || s == "public_repo") {
+                        println!("⚠️  Token lacks the repo scope needed to push branches and edit PRs");
+                    }
+                }
+                None => println!("✓ Token scopes: n/a (fine-grained or app token)"),
+            }
+        }
+        Err(e) => {
+            println!("✗ {}", e);
+            problems += 1;
+        }
+    }
+
+    let repo = match get_repo_info(verbose) {
+        Ok(repo) => {
+            println!("✓ Repository: {}", repo);
+            repo
+        }
+        Err(e) => {
+            println!("✗ {}", e);
+            bail!(CodedError::new(ErrorCode::DoctorProblems, format!("Doctor found {} problem(s)", problems + 1)));
+        }
+    };
+
+    let state = load_state()?;
+    let merge_method = Config::load().map(|config| config.merge_method).unwrap_or_default();
+    let warnings = check_branch_protection(&repo, trunk(&state), merge_method, verbose)?;
+    if warnings.is_empty() {
+        println!("✓ Branch protection on {} is compatible with stacked PRs", trunk(&state));
+    }
+    for warning in &warnings {
+        println!("⚠️  {}", warning);
+    }
+
+    if problems > 0 {
+        bail!(CodedError::new(ErrorCode::DoctorProblems, format!("Doctor found {} problem(s)", problems)));
+    }
+    Ok(())
+}
+
+// Load .almighty.toml, with `{user}` in branch_prefix replaced by the GitHub
+// login so each user's branches live in their own namespace. Branches named
+// with jj's own push prefix count as managed, and unless branch_prefix is
+// configured it is the prefix new branches get.
+fn load_config(verbose: bool) -> Result<Config> {
+    let mut config = Config::load()?;
+    let jj_prefix = jj_push_prefix(verbose);
+    if config.branch_prefix == DEFAULT_BRANCH_PREFIX {
+        config.branch_prefix = jj_prefix.to_string();
+    } else if !config.managed_prefixes.iter().any(|prefix| prefix == jj_prefix) {
+        config.managed_prefixes.push(jj_prefix.to_string());
+    }
+    if config.branch_prefix.contains("{user}") {
+        let login = current_login(verbose)?.context("branch_prefix contains {user} but the GitHub login could not be determined")?;
+        config.branch_prefix = config.branch_prefix.replace("{user}", &login);
+    }
+    Ok(config)
+}
+
+// Prefix `jj git push --change` gives the bookmarks it creates
+// (git.push-bookmark-prefix), looked up once per run
+fn jj_push_prefix(verbose: bool) -> &'static str {
+    static PREFIX: OnceLock<String> = OnceLock::new();
+    PREFIX.get_or_init(|| {
+        run_command(&["jj", "config", "get", "git.push-bookmark-prefix"], false, verbose).ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty() && !value.contains(char::is_whitespace))
+            .unwrap_or_else(|| DEFAULT_BRANCH_PREFIX.to_string())
+    })
+}
+
+// GitHub login of the authenticated user, looked up once per run; None for
+// tokens without a user, such as GitHub App installations
+fn current_login(verbose: bool) -> Result<Option<String>> {
+    static LOGIN: OnceLock<Option<String>> = OnceLock::new();
+    if let Some(login) = LOGIN.get() {
+        return Ok(login.clone());
+    }
+    let user = forge::forge().rest("GET", "user", serde_json::Value::Null, verbose).unwrap_or_default();
+    let login = user["login"].as_str().map(str::to_string);
+    Ok(LOGIN.get_or_init(|| login).clone())
+}
+
+// Author of the open PR for a branch, when that is someone other than us
+fn other_owner(repo: &str, branch: &str, verbose: bool) -> Result<Option<(u32, String)>> {
+    let Some(login) = current_login(verbose)? else { return Ok(None) };
+    let pr = view_pr(repo, branch, &["number", "state", "author"], verbose);
+    Ok(Some(&pr).filter(|pr| pr["state"] == "OPEN")
+        .and_then(|pr| Some((pr["number"].as_u64()? as u32, pr["author"]["login"].as_str()?.to_string())))
+        .filter(|(_, author)| !author.eq_ignore_ascii_case(&login)))
+}
+
+// Read-only consistency check for CI: PR bases follow the stack, PRs carry the
+// local commits, the stack is linear and the state file matches GitHub. Issues
+// go to stdout (one per line, or as JSON), and any issue fails the command.
+fn verify_stack(args: &Args) -> Result<()> {
+    let repo = get_repo_info(args.verbose)?;
+    let state = load_state()?;
+    let config = load_config(args.verbose)?;
+    let mut revisions = get_stack_revisions(&stack_base(&state), args.verbose)?;
+    let withheld = withhold_private_commits(&mut revisions, private_revset(&config, args.verbose).as_deref(), args.verbose)?;
+    let mut issues = Vec::new();
+    let mut issue = |check, rev: Option<&Revision>, pr_number, message: String| issues.push(output::Issue {
+        check,
+        change_id: rev.map(|r| r.change_id.clone()),
+        pr_number,
+        message,
+    });
+
+    // The stack itself: one commit per change, each on top of the previous one
+    let mut seen = HashSet::new();
+    for (i, rev) in revisions.iter().enumerate() {
+        if !seen.insert(&rev.change_id) {
+            issue("divergence", Some(rev), None, format!("{} has several visible commits", &rev.change_id[..8]));
+        }
+        if rev.has_conflicts {
+            issue("conflict", Some(rev), None, format!("{} has conflicts", &rev.change_id[..8]));
+        }
+        if rev.parent_change_ids.len() > 1 && i > 0 {
+            issue("linearity", Some(rev), None, format!("{} is a merge commit", &rev.change_id[..8]));
+        } else if i > 0 && rev.parent_change_ids.first() != Some(&revisions[i - 1].change_id) {
+            issue("linearity", Some(rev), None, format!("{} does not sit on {}", &rev.change_id[..8], &revisions[i - 1].change_id[..8]));
+        }
+    }
+
+    let tracked = |change_id: &str| state.prs.iter()
+        .find(|(id, _)| id.starts_with(change_id) || change_id.starts_with(id.as_str()))
+        .map(|(_, info)| info);
+    let branch_for = |rev: &Revision| tracked_branch(&state, &config, &rev.change_id);
+
+    // Only the stack's branches and the ones the state tracks are looked up
+    let mut branches: Vec<String> = revisions.iter().map(branch_for).collect();
+    for info in state.prs.values() {
+        if !branches.contains(&info.branch_name) {
+            branches.push(info.branch_name.clone());
+        }
+    }
+    let prs = get_existing_prs(&repo, &branches, args.verbose)?;
+    let open: Vec<u32> = prs.values().filter(|pr| pr.2 == "OPEN").map(|pr| pr.0).collect();
+    let heads = fetch_prs(&repo, &open, &["headRefOid"], args.verbose)?;
+
+    let mut below = base_branch(&state).to_string();
+    for rev in &revisions {
+        let info = tracked(&rev.change_id);
+        let branch = branch_for(rev);
+        let Some((number, _, pr_state, base)) = prs.get(&branch) else {
+            match info {
+                Some(info) => issue("state", Some(rev), Some(info.pr_number),
+                                    format!("state maps {} to PR #{}, which has no branch {}", &rev.change_id[..8], info.pr_number, branch)),
+                None if config.max_open_prs.is_none() => issue("pr", Some(rev), None, format!("{} has no PR", &rev.change_id[..8])),
+                None => {}
+            }
+            below = branch;
+            continue;
+        };
+        let number = *number;
+
+        if let Some(info) = info.filter(|info| info.pr_number != number) {
+            issue("state", Some(rev), Some(number), format!("state maps {} to PR #{}, but {} belongs to PR #{}",
+                  &rev.change_id[..8], info.pr_number, branch, number));
+        }
+        if pr_state == "OPEN" {
+            let expected = state.base_overrides.iter()
+                .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
+                .map_or(below.as_str(), |(_, base)| base.as_str());
+            if base != expected {
+                issue("base", Some(rev), Some(number), format!("PR #{} targets {} instead of {}", number, base, expected));
+            }
+            let head = heads.get(&number).and_then(|pr| pr["headRefOid"].as_str()).unwrap_or_default();
+            if head != rev.commit_id {
+                issue("push", Some(rev), Some(number), format!("PR #{} is at {}, the stack at {}",
+                      number, &head[..12.min(head.len())], &rev.commit_id[..12.min(rev.commit_id.len())]));
+            }
+            below = branch;
+        } else {
+            issue("pr", Some(rev), Some(number), format!("PR #{} is {} but {} is still in the stack",
+                  number, pr_state.to_lowercase(), &rev.change_id[..8]));
+        }
+    }
+
+    // PRs the state still tracks for changes that have left the stack
+    for (change_id, info) in &state.prs {
+        let in_stack = revisions.iter().map(|r| &r.change_id).chain(&withheld)
+            .any(|id| id.starts_with(change_id.as_str()) || change_id.starts_with(id.as_str()));
+        let open = prs.get(&info.branch_name).is_some_and(|pr| pr.2 == "OPEN");
+        if !in_stack && open && !state.merged_prs.contains(change_id) {
+            issue("state", None, Some(info.pr_number), format!("PR #{} is open but {} is no longer in the stack",
+                  info.pr_number, &change_id[..8.min(change_id.len())]));
+        }
+    }
+
+    let report = output::VerifyReport { repo, base: base_branch(&state).to_string(), issues };
+    if args.format == OutputFormat::Json {
+        output::print_json("verify", &report)?;
+    } else {
+        for issue in &report.issues {
+            println!("{}\t{}\t{}\t{}", issue.check,
+                     issue.change_id.as_deref().map_or("-", |id| &id[..8.min(id.len())]),
+                     issue.pr_number.map_or("-".to_string(), |n| format!("#{}", n)), issue.message);
+        }
+    }
+    if let Some(path) = &args.output {
+        output::write_report(path, &report)?;
+    }
+
+    if !report.issues.is_empty() {
+        bail!(CodedError::new(ErrorCode::VerifyFailed, format!("Verify found {} problem(s)", report.issues.len())));
+    }
+    eprintln!("✓ {} revision(s) and their PRs are consistent", revisions.len());
+    Ok(())
+}
+
+// Print the stack as GitHub sees it: each revision's branch, PR, checks and
+// review. Only reads from jj and GitHub; nothing is pushed or edited.
+fn show_status(args: &Args) -> Result<()> {
+    let repo = get_repo_info(args.verbose)?;
+    let state = load_state()?;
+    let config = load_config(args.verbose)?;
+    let revisions = stack_with_prs(&repo, &state, &config, args.verbose)?;
+
+    let report = output::RunReport {
+        repo,
+        base: base_branch(&state).to_string(),
+        revisions: revisions.iter().map(output::RevisionReport::from).collect(),
+    };
+    if args.format == OutputFormat::Json {
+        return output::print_json("status", &report);
+    }
+    if revisions.is_empty() {
+        eprintln!("No revisions in the stack");
+    }
+    for rev in &revisions {
+        let mut pr_state = rev.pr_state.as_deref().map_or("no PR".to_string(), str::to_lowercase);
+        if rev.pr_mergeable.as_deref() == Some("CONFLICTING") {
+            pr_state.push_str(", conflicts");
+        }
+        let checks = match (output::checks_icon(rev.pr_checks.as_deref()), &rev.pr_checks) {
+            (Some(icon), Some(checks)) => format!("{} {}", icon, checks.to_lowercase()),
+            _ => "-".to_string(),
+        };
+        println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                 &rev.change_id[..8.min(rev.change_id.len())],
+                 rev.branch_name.as_deref().unwrap_or("-"),
+                 rev.pr_number.map_or("-".to_string(), |n| format!("#{}", n)),
+                 pr_state,
+                 checks,
+                 rev.pr_review.as_deref().map_or("-".to_string(), |review| review.to_lowercase().replace('_', " ")),
+                 rev.base_branch.as_deref().unwrap_or("-"),
+                 rev.description);
+    }
+    Ok(())
+}
+
+// Merge the stack's PRs from the bottom: the bottom open PR, every PR up to
+// `up_to`, or with `all_green` the run of PRs from the bottom that are ready.
+// Which PRs land is decided up front; each PR after the first is retargeted to
+// the base as the one below it merges, and the rest of the stack is restacked
+// with a single push at the end.
+fn land_stack(through: Option<u32>, all_green: Option<bool>, method: Option<MergeMethod>, wait: bool, args: &Args) -> Result<()> {
+    let repo = get_repo_info(args.verbose)?;
+    let config = load_config(args.verbose)?;
+    let state = load_state()?;
+    let trunk_name = trunk(&state).to_string();
+    let revisions = stack_with_prs(&repo, &state, &config, args.verbose)?;
+
+    let open: Vec<&Revision> = revisions.iter().filter(|rev| rev.pr_state.as_deref() == Some("OPEN")).collect();
+    if open.is_empty() {
+        bail!(CodedError::new(ErrorCode::LandFailed, "No open PR in the stack to land"));
+    }
+    let batch: Vec<&Revision> = match (through, all_green) {
+        (Some(target), _) => {
+            let Some(end) = open.iter().position(|rev| rev.pr_number == Some(target)) else {
+                bail!(CodedError::new(ErrorCode::LandFailed, format!("PR #{} is not an open PR of this stack", target)));
+            };
+            open[..=end].to_vec()
+        }
+        (None, Some(allow_unreviewed)) => {
+            let ready = open.iter().position(|rev| not_ready_reason(rev, allow_unreviewed).is_some()).unwrap_or(open.len());
+            if let Some(rev) = open.get(ready) {
+                eprintln!("PR #{} ({}) isn't ready to land: {}", rev.pr_number.unwrap_or_default(), rev.description,
+                         not_ready_reason(rev, allow_unreviewed).unwrap_or_default());
+            }
+            open[..ready].to_vec()
+        }
+        (None, None) => open[..1].to_vec(),
+    };
+    if batch.is_empty() {
+        eprintln!("No PRs are ready to land");
+        return Ok(());
+    }
+
+    // Held while merging and retargeting, so no other run moves these PRs between
+    // the head check and the merge; push_stack takes them again to restack
+    let lock = acquire_lock()?;
+    let remote_lock = acquire_remote_lock(&config, &repo, args.dry_run, args.verbose)?;
+    let landing = Landing { repo: &repo, trunk: &trunk_name, method: method.unwrap_or(config.merge_method), wait, dry_run: args.dry_run, verbose: args.verbose };
+    let mut landed = 0;
+    let mut result = Ok(());
+    for (i, rev) in batch.iter().enumerate() {
+        let mut rev = (*rev).clone();
+        // The PR below has just merged; this one still targets its branch
+        if i > 0 {
+            if let Err(e) = retarget_for_landing(&rev, &landing) {
+                result = Err(e);
+                break;
+            }
+            rev.base_branch = Some(trunk_name.clone());
+        }
+        if let Err(e) = land_pr(&rev, &revisions, &landing) {
+            result = Err(e);
+            break;
+        }
+        landed += 1;
+    }
+
+    drop(remote_lock);
+    drop(lock);
+
+    if args.dry_run {
+        if landed > 0 {
+            eprintln!("Would then rebase the rest of the stack onto {} and push", trunk_name);
+        }
+        print_plan();
+        return result;
+    }
+    // Whatever merged is restacked away, even when a later PR couldn't land,
+    // unless a merge is still unconfirmed and the base may not contain it yet
+    let unconfirmed = result.as_ref().is_err_and(|e: &anyhow::Error| {
+        e.downcast_ref::<CodedError>().is_some_and(|e| e.code == ErrorCode::MergeNotConfirmed)
+    });
+    if landed > 0 && !unconfirmed {
+        eprintln!("Landed {} PR(s); rebasing the rest of the stack onto {}", landed, trunk_name);
+        push_stack(args)?;
+    }
+    result
+}
+
+// Point a PR whose parent PR just landed at the base, and give GitHub time to
+// recompute whether it merges before it is merged itself
+fn retarget_for_landing(rev: &Revision, landing: &Landing) -> Result<()> {
+    let Some(number) = rev.pr_number else { return Ok(()) };
+    let (repo, trunk, verbose) = (landing.repo, landing.trunk, landing.verbose);
+    forge_mutation(&["gh", "pr", "edit", &number.to_string(), "-R", repo, "--base", trunk], false, landing.dry_run, verbose,
+                   |forge| forge.edit_pr(repo, number, &PrEdit { base: Some(trunk), ..PrEdit::default() }, verbose))?;
+    if landing.dry_run {
+        return Ok(());
+    }
+    record_action(format!("Retargeted PR #{} to {}", number, trunk));
+    for _ in 0..MERGEABLE_POLLS {
+        std::thread::sleep(MERGEABLE_POLL_INTERVAL);
+        if view_pr(repo, &number.to_string(), &["mergeable"], verbose)["mergeable"].as_str().is_some_and(|m| m != "UNKNOWN") {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Why `land --all-green` stops at a PR, or None when it is approved, its
+// checks pass and it merges cleanly. A PR without a review decision (the base
+// branch doesn't require reviews) only counts with `allow_unreviewed`, and one
+// without checks never does.
+fn not_ready_reason(rev: &Revision, allow_unreviewed: bool) -> Option<String> {
+    match rev.pr_review.as_deref() {
+        Some("APPROVED") => {}
+        None if allow_unreviewed => {}
+        None => return Some("it has no review decision (--allow-unreviewed lands it anyway)".to_string()),
+        Some(review) => return Some(review.to_lowercase().replace('_', " ")),
+    }
+    match rev.pr_checks.as_deref() {
+        Some("SUCCESS") => {}
+        None => return Some("no checks have reported on it".to_string()),
+        Some(checks) => return Some(format!("checks are {}", checks.to_lowercase())),
+    }
+    (rev.pr_mergeable.as_deref() == Some("CONFLICTING")).then(|| "conflicts with its base".to_string())
+}
+
+// How `land` merges: where, by which method, and whether it waits for checks
+struct Landing<'a> {
+    repo: &'a str,
+    trunk: &'a str,
+    method: MergeMethod,
+    wait: bool,
+    dry_run: bool,
+    verbose: bool,
+}
+
+// Merge the bottom PR of the stack into `trunk` and wait until GitHub reports
+// it merged. The merge names the local commit, so GitHub refuses it if the PR
+// has moved on since.
+fn land_pr(rev: &Revision, revisions: &[Revision], landing: &Landing) -> Result<()> {
+    let Landing { repo, trunk, method, dry_run, verbose, .. } = *landing;
+    let Some(number) = rev.pr_number else { return Ok(()) };
+    let refused = |reason: String| CodedError::new(ErrorCode::LandFailed, format!("Can't land PR #{}: {}", number, reason));
+    let base = rev.base_branch.as_deref().unwrap_or_default();
+    if base != trunk {
+        bail!(refused(format!("it targets {} instead of {}", base, trunk)));
+    }
+    let pr = forge::forge().view_pr(repo, &number.to_string(), &["headRefOid"], verbose)
+        .map_err(|e| refused(format!("couldn't read its head commit: {:#}", e)))?
+        .unwrap_or_default();
+    let Some(head) = pr["headRefOid"].as_str() else {
+        bail!(refused("GitHub didn't report its head commit".to_string()));
+    };
+    if head != rev.commit_id {
+        bail!(refused("its branch on GitHub isn't at the local commit; push the stack first".to_string()));
+    }
+    wait_for_required_checks(number, landing)?;
+
+    eprintln!("{} PR #{} ({}) into {} ({})", if dry_run { "Would land" } else { "Landing" },
+             number, rev.description, trunk, method.as_str());
+    let mut body = serde_json::json!({ "merge_method": method.as_str(), "sha": rev.commit_id });
+    // Squash commits end with the same trailers as the PR body, tying them to their change
+    if method == MergeMethod::Squash {
+        let stack_prs: Vec<u32> = revisions.iter().filter_map(|r| r.pr_number).collect();
+        let message = format!("{}\n\n{}", description::without_trailers(&rev.body),
+                              description::merge_trailers(&rev.change_id, &stack_prs));
+        body["commit_title"] = serde_json::json!(format!("{} (#{})", rev.description, number));
+        body["commit_message"] = serde_json::json!(message.trim_start());
+    }
+    let endpoint = format!("repos/{}/pulls/{}/merge", repo, number);
+    forge_mutation(&["gh", "pr", "merge", &number.to_string(), "-R", repo, &format!("--{}", method.as_str()),
+                     "--match-head-commit", &rev.commit_id], false, dry_run, verbose,
+                   |forge| forge.rest("PUT", &endpoint, body.clone(), verbose).map(drop))
+        .map_err(|e| refused(format!("{:#}", e)))?;
+    if dry_run {
+        return Ok(());
+    }
+    record_action(format!("Landed PR #{} into {}", number, trunk));
+
+    let timeout = if landing.wait { LAND_CHECKS_TIMEOUT } else { LAND_MERGE_TIMEOUT };
+    let started = Instant::now();
+    loop {
+        if view_pr(repo, &number.to_string(), &["state"], verbose)["state"].as_str() == Some("MERGED") {
+            eprintln!("✓ PR #{} merged", number);
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            bail!(CodedError::new(ErrorCode::MergeNotConfirmed, format!(
+                "GitHub accepted the merge of PR #{} but hasn't reported it merged after {} seconds", number, timeout.as_secs())));
+        }
+        std::thread::sleep(LAND_MERGE_POLL_INTERVAL);
+    }
+}
+
+// A status check or check run on a PR's head commit
+struct PrCheck {
+    name: String,
+    // passing, failing or pending
+    outcome: &'static str,
+    // Required by branch protection or a ruleset for this PR
+    required: bool,
+}
+
+// Refuse to land PR `number` until every check the base branch requires has
+// passed on its head commit. Failing checks refuse at once; with `--wait`,
+// pending and missing ones are polled until they pass or LAND_CHECKS_TIMEOUT.
+fn wait_for_required_checks(number: u32, landing: &Landing) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        let problems = required_check_problems(number, landing)?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let failing = problems.iter().any(|(_, problem)| *problem == "failing");
+        let timed_out = started.elapsed() >= LAND_CHECKS_TIMEOUT;
+        if failing || !landing.wait || timed_out || landing.dry_run {
+            eprintln!("Required checks on PR #{}:", number);
+            for (name, problem) in &problems {
+                eprintln!("  ✗ {}: {}", name, problem);
+            }
+            let mut message = format!("PR #{} has {} required check(s) not passing", number, problems.len());
+            if timed_out {
+                message.push_str(&format!(" after waiting {} minutes", LAND_CHECKS_TIMEOUT.as_secs() / 60));
+            }
+            bail!(CodedError::new(ErrorCode::RequiredChecks, message));
+        }
+        eprintln!("  Waiting for {} required check(s) on PR #{}...", problems.len(), number);
+        std::thread::sleep(LAND_CHECKS_POLL_INTERVAL);
+    }
+}
+
+// (check, failing/pending/missing) for every required check of PR `number`
+// that hasn't passed. Required checks come from the base branch's protection
+// rules, when readable, and from GitHub marking a check required for the PR;
+// one required by protection that never reported is missing.
+fn required_check_problems(number: u32, landing: &Landing) -> Result<Vec<(String, &'static str)>> {
+    let checks = pr_checks(landing.repo, number, landing.verbose)?;
+    let mut required: Vec<String> = required_status_checks(landing.repo, landing.trunk, landing.verbose);
+    for check in checks.iter().filter(|check| check.required) {
+        if !required.contains(&check.name) {
+            required.push(check.name.clone());
+        }
+    }
+
+    let mut problems = Vec::new();
+    for name in required {
+        // A check that ran several times reports each run; any passing one counts
+        let runs: Vec<&PrCheck> = checks.iter().filter(|check| check.name == name).collect();
+        let outcome = if runs.is_empty() {
+            "missing"
+        } else if runs.iter().any(|run| run.outcome == "passing") {
+            continue;
+        } else if runs.iter().any(|run| run.outcome == "pending") {
+            "pending"
+        } else {
+            "failing"
+        };
+        problems.push((name, outcome));
+    }
+    Ok(problems)
+}
+
+// Checks and statuses on the head commit of PR `number`
+fn pr_checks(repo: &str, number: u32, verbose: bool) -> Result<Vec<PrCheck>> {
+    let (owner, name) = repo.split_once('/').with_context(|| format!("Invalid repository {}", repo))?;
+    let query = "query($owner: String!, $name: String!, $number: Int!) { repository(owner: $owner, name: $name) { \
+                 pullRequest(number: $number) { commits(last: 1) { nodes { commit { statusCheckRollup { contexts(first: 100) { nodes { \
+                 ... on CheckRun { name status conclusion isRequired(pullRequestNumber: $number) } \
+                 ... on StatusContext { context state isRequired(pullRequestNumber: $number) } } } } } } } } } }";
+    let data = forge::forge().graphql(query, serde_json::json!({ "owner": owner, "name": name, "number": number }), verbose)
+        .with_context(|| format!("Failed to read the checks of PR #{}", number))?;
+    let contexts = &data["repository"]["pullRequest"]["commits"]["nodes"][0]["commit"]["statusCheckRollup"]["contexts"]["nodes"];
+
+    Ok(contexts.as_array().into_iter().flatten().filter_map(|context| {
+        let required = context["isRequired"].as_bool().unwrap_or(false);
+        // Check runs report a status until they complete, then a conclusion
+        if let Some(name) = context["name"].as_str() {
+            let outcome = match (context["status"].as_str(), context["conclusion"].as_str()) {
+                (Some("COMPLETED"), Some("SUCCESS" | "NEUTRAL" | "SKIPPED")) => "passing",
+                (Some("COMPLETED"), _) => "failing",
+                _ => "pending",
+            };
+            return Some(PrCheck { name: name.to_string(), outcome, required });
+        }
+        let outcome = match context["state"].as_str()? {
+            "SUCCESS" => "passing",
+            "PENDING" | "EXPECTED" => "pending",
+            _ => "failing",
+        };
+        Some(PrCheck { name: context["context"].as_str()?.to_string(), outcome, required })
+    }).collect())
+}
+
+// Names of the status checks `branch`'s protection rules require. Reading them
+// needs admin access; without it only the checks GitHub marks as required on
+// the PR itself are known.
+fn required_status_checks(repo: &str, branch: &str, verbose: bool) -> Vec<String> {
+    let endpoint = format!("repos/{}/branches/{}/protection/required_status_checks", repo, forge::encode_path_segment(branch));
+    let checks = match forge::forge().rest("GET", &endpoint, serde_json::Value::Null, verbose) {
+        Ok(checks) => checks,
+        Err(e) => {
+            if verbose {
+                eprintln!("[debug] Could not read required checks of {}: {:#}", branch, e);
+            }
+            return Vec::new();
+        }
+    };
+    let mut names: Vec<String> = checks["contexts"].as_array().into_iter().flatten()
+        .filter_map(|context| context.as_str().map(str::to_string))
+        .collect();
+    for check in checks["checks"].as_array().into_iter().flatten() {
+        if let Some(context) = check["context"].as_str().filter(|context| !names.iter().any(|name| name == context)) {
+            names.push(context.to_string());
+        }
+    }
+    names
+}
+
+// The stack with each revision's branch and PR as found on GitHub, and for
+// open PRs their mergeability, review decision and checks
+fn stack_with_prs(repo: &str, state: &State, config: &Config, verbose: bool) -> Result<Vec<Revision>> {
+    let mut revisions = get_stack_revisions(&stack_base(state), verbose)?;
+
+    for rev in &mut revisions {
+        rev.branch_name = Some(tracked_branch(state, config, &rev.change_id));
+    }
+    let prs = get_existing_prs(repo, &stack_branches(&revisions, config), verbose)?;
+    for rev in &mut revisions {
+        let Some((number, url, pr_state, base)) = rev.branch_name.as_ref().and_then(|branch| prs.get(branch)) else { continue };
+        rev.pr_number = Some(*number);
+        rev.pr_url = Some(url.clone());
+        rev.pr_state = Some(pr_state.clone());
+        rev.base_branch = Some(base.clone());
+    }
+
+    let open: Vec<u32> = revisions.iter()
+        .filter(|rev| rev.pr_state.as_deref() == Some("OPEN"))
+        .filter_map(|rev| rev.pr_number)
+        .collect();
+    // A failed lookup stops the caller rather than passing for "no checks, no review"
+    let details = fetch_prs(repo, &open, &["mergeable", "reviewDecision", CHECKS_FIELD], verbose)
+        .context("Failed to fetch the PRs' checks and reviews")?;
+    for rev in &mut revisions {
+        let Some(pr) = rev.pr_number.and_then(|number| details.get(&number)) else { continue };
+        rev.pr_mergeable = pr["mergeable"].as_str().map(str::to_string);
+        rev.pr_review = pr["reviewDecision"].as_str().map(str::to_string);
+        rev.pr_checks = checks_state(pr);
+    }
+    Ok(revisions)
+}
+
+// Read the base branch's protection rules and repository merge settings and
+// report anything that conflicts with the stacked PR workflow
+fn check_branch_protection(repo: &str, branch: &str, merge_method: MergeMethod, verbose: bool) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+
+    let forge = forge::forge();
+    let settings = forge.rest("GET", &format!("repos/{}", repo), serde_json::Value::Null, verbose).unwrap_or_default();
+    let allow_merge = settings["allow_merge_commit"].as_bool().unwrap_or(true);
+    let allow_squash = settings["allow_squash_merge"].as_bool().unwrap_or(true);
+    let allow_rebase = settings["allow_rebase_merge"].as_bool().unwrap_or(true);
+
+    // `land` merges with the configured method, which the repository must allow
+    let allowed = match merge_method {
+        MergeMethod::Squash => allow_squash,
+        MergeMethod::Rebase => allow_rebase,
+        MergeMethod::Merge => allow_merge,
+    };
+    if !allowed {
+        warnings.push(format!(
+            "merge_method is {} but the repository doesn't allow {} merges; land will fail",
+            merge_method.as_str(), merge_method.as_str()
+        ));
+    }
+
+    let protection = match forge.rest("GET", &format!("repos/{}/branches/{}/protection", repo, forge::encode_path_segment(branch)), serde_json::Value::Null, verbose) {
+        Ok(json) if json.get("url").is_some() => json,
+        result => {
+            if result.is_err_and(|e| format!("{:#}", e).contains("Branch not protected")) {
+                if verbose {
+                    eprintln!("  {} has no branch protection rules", branch);
+                }
+            } else {
+                warnings.push(format!(
+                    "Could not read branch protection for {} (admin access is required)", branch
+                ));
+            }
+            return Ok(warnings);
+        }
+    };
+
+    let enabled = |key: &str| protection[key]["enabled"].as_bool().unwrap_or(false);
+
+    if enabled("required_linear_history") && !allow_squash && !allow_rebase {
+        warnings.push(format!(
+            "{} requires linear history but the repository only allows merge commits; stacked PRs cannot be merged",
+            branch
+        ));
+    } else if enabled("required_linear_history") && merge_method == MergeMethod::Merge {
+        warnings.push(format!(
+            "{} requires linear history but merge_method is merge; set merge_method to squash or rebase",
+            branch
+        ));
+    }
+
+    let reviews = &protection["required_pull_request_reviews"];
+    if reviews["dismiss_stale_reviews"].as_bool().unwrap_or(false) {
+        warnings.push(
+            "Stale reviews are dismissed on push; restacking after a merge resets approvals on every PR above it".to_string()
+        );
+    }
+    if reviews["require_last_push_approval"].as_bool().unwrap_or(false) {
+        warnings.push(
+            "The last push must be approved by someone else; every restack needs a fresh approval".to_string()
+        );
+    }
+
+    if enabled("required_signatures") {
+        let signing = run_command(&["jj", "config", "get", "signing.behavior"], true, verbose)?;
+        if !matches!(signing.trim(), "own" | "force") {
+            warnings.push(
+                "Signed commits are required but jj is not configured to sign them (set signing.behavior)".to_string()
+            );
+        }
+    }
+
+    if protection["required_status_checks"]["strict"].as_bool().unwrap_or(false) {
+        warnings.push(format!(
+            "PRs must be up to date with {} before merging; each PR above a merged one needs a re-push before it can land",
+            branch
+        ));
+    }
+
+    Ok(warnings)
+}
+
+// Lock management
+fn acquire_lock() -> Result<FileLock> {
+    FileLock::acquire()
+}
+
+struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    fn acquire() -> Result<Self> {
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(LOCK_FILE) {
+                Ok(mut file) => {
+                    let pid = process::id();
+                    writeln!(file, "{}", pid)?;
+                    return Ok(Self { _file: file });
+                }
+                Err(_) if start.elapsed() > LOCK_TIMEOUT => {
+                    bail!(CodedError::new(ErrorCode::LockTimeout,
+                        format!("Failed to acquire lock after {} seconds", LOCK_TIMEOUT.as_secs())));
+                }
+                Err(_) => {
+                    // Check if stale
+                    if let Ok(mut file) = File::open(LOCK_FILE) {
+                        let mut content = String::new();
+                        file.read_to_string(&mut content)?;
+                        if let Ok(_pid) = content.trim().parse::<u32>() {
+                            // Simple check - in production would verify process exists
+                            let age = fs::metadata(LOCK_FILE)?.modified()?;
+                            if SystemTime::now().duration_since(age)? > Duration::from_secs(600) {
+                                fs::remove_file(LOCK_FILE)?;
+                                continue;
+                            }
+                        }
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(LOCK_FILE);
+    }
+}
+
+// Take the cross-machine lock when remote_lock is enabled. Dry runs change
+// nothing, so they don't need it.
+fn acquire_remote_lock(config: &Config, repo: &str, dry_run: bool, verbose: bool) -> Result<Option<RemoteLock>> {
+    if !config.remote_lock || dry_run {
+        return Ok(None);
+    }
+    RemoteLock::acquire(repo, verbose).map(Some)
+}
+
+// Lock shared by every machine pushing to a repository as the same jj user: a
+// ref under refs/almighty-push/locks/ pointing at a commit whose message names
+// the holder. GitHub refuses to create a ref that exists, so taking it is atomic.
+struct RemoteLock {
+    repo: String,
+    ref_name: String,
+    commit: String,
+    verbose: bool,
+}
+
+impl RemoteLock {
+    fn acquire(repo: &str, verbose: bool) -> Result<Self> {
+        let user = run_command(&["jj", "config", "get", "user.email"], true, verbose)?;
+        let user: String = user.trim().chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '-' })
+            .collect();
+        let ref_name = format!("almighty-push/locks/{}", if user.is_empty() { "default" } else { &user });
+
+        let host = Command::new("hostname").output().ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| "unknown host".to_string());
+        let holder = format!("almighty-push lock held by {} (pid {})", host, process::id());
+        let forge = forge::forge();
+        let head = forge.rest("GET", &format!("repos/{}/commits/HEAD", repo), serde_json::Value::Null, verbose)?;
+        let tree = head["commit"]["tree"]["sha"].as_str().context("Could not read the default branch's tree")?;
+
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            let commit = lock_commit(repo, tree, &holder, None, verbose)?;
+            let created = forge.rest("POST", &format!("repos/{}/git/refs", repo),
+                                     serde_json::json!({ "ref": format!("refs/{}", ref_name), "sha": commit }), verbose);
+            match created {
+                Ok(_) => return Ok(Self { repo: repo.to_string(), ref_name, commit, verbose }),
+                Err(e) if !e.to_string().contains("already exists") => return Err(e),
+                Err(_) => {}
+            }
+
+            // Someone holds it; take it over if it was taken long enough ago to be stale
+            let lock = forge.rest("GET", &format!("repos/{}/git/ref/{}", repo, ref_name), serde_json::Value::Null, verbose).unwrap_or_default();
+            let current = lock["object"]["sha"].as_str().unwrap_or_default();
+            let held = forge.rest("GET", &format!("repos/{}/git/commits/{}", repo, current), serde_json::Value::Null, verbose).unwrap_or_default();
+            let other_holder = held["message"].as_str().unwrap_or_default().trim();
+            let age = held["committer"]["date"].as_str().and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+                .and_then(|date| (chrono::Utc::now() - date.with_timezone(&chrono::Utc)).to_std().ok());
+
+            if age.is_some_and(|age| age > REMOTE_LOCK_STALE) {
+                // Fast-forward only: if another machine took it over first, this fails
+                let commit = lock_commit(repo, tree, &holder, Some(current), verbose)?;
+                let updated = forge.rest("PATCH", &format!("repos/{}/git/refs/{}", repo, ref_name),
+                                         serde_json::json!({ "sha": commit, "force": false }), verbose).unwrap_or_default();
+                if updated["object"]["sha"] == commit.as_str() {
+                    eprintln!("⚠️  Took over a stale remote lock ({})", other_holder);
+                    return Ok(Self { repo: repo.to_string(), ref_name, commit, verbose });
+                }
+                continue;
+            }
+
+            if start.elapsed() > LOCK_TIMEOUT {
+                bail!(CodedError::new(ErrorCode::RemoteLockTimeout, format!(
+                    "Failed to acquire the remote lock after {} seconds ({})", LOCK_TIMEOUT.as_secs(), other_holder)));
+            }
+            if !waiting {
+                eprintln!("Waiting for the remote lock: {}", other_holder);
+                waiting = true;
+            }
+            std::thread::sleep(Duration::from_secs(5));
+        }
+    }
+}
+
+// A commit recording the lock holder, on the default branch's tree
+fn lock_commit(repo: &str, tree: &str, holder: &str, parent: Option<&str>, verbose: bool) -> Result<String> {
+    let commit = forge::forge().rest("POST", &format!("repos/{}/git/commits", repo), serde_json::json!({
+        "message": holder, "tree": tree, "parents": parent.into_iter().collect::<Vec<_>>(),
+    }), verbose)?;
+    commit["sha"].as_str().map(str::to_string).context("GitHub did not return the lock commit")
+}
+
+impl Drop for RemoteLock {
+    fn drop(&mut self) {
+        // Only release it if it's still ours, i.e. nobody took it over as stale
+        let forge = forge::forge();
+        let lock = forge.rest("GET", &format!("repos/{}/git/ref/{}", self.repo, self.ref_name), serde_json::Value::Null, self.verbose)
+            .unwrap_or_default();
+        if lock["object"]["sha"] == self.commit.as_str() {
+            let _ = forge.rest("DELETE", &format!("repos/{}/git/refs/{}", self.repo, self.ref_name), serde_json::Value::Null, self.verbose);
+        }
+    }
+}
+
+// Scrap the whole stack: close its PRs, delete its branches and forget it
+fn abandon_stack(local: bool, dry_run: bool, verbose: bool) -> Result<()> {
+    let repo_info = get_repo_info(verbose)?;
+    let _lock = acquire_lock()?;
+    fetch_remotes(verbose)?;
+
+    let mut state = load_state()?;
+    migrate_state(&mut state)?;
+    let config = load_config(verbose)?;
+    let _remote_lock = acquire_remote_lock(&config, &repo_info, dry_run, verbose)?;
+
+    let mut revisions = get_stack_revisions(&stack_base(&state), verbose)?;
+    if revisions.is_empty() {
+        eprintln!("No revisions in the stack");
+        return Ok(());
+    }
+    for rev in &mut revisions {
+        rev.branch_name = Some(tracked_branch(&state, &config, &rev.change_id));
+    }
+
+    let existing_prs = get_existing_prs(&repo_info, &stack_branches(&revisions, &config), verbose)?;
+    let open_prs: Vec<(&Revision, u32)> = revisions.iter()
+        .filter_map(|rev| {
+            let pr = existing_prs.get(rev.branch_name.as_ref()?)?;
+            (pr.2 == "OPEN").then_some((rev, pr.0))
+        })
+        .collect();
+    // Revisions that were never pushed have no branch to delete
+    let mut branches = Vec::new();
+    for branch in revisions.iter().filter_map(|rev| rev.branch_name.as_deref()) {
+        if revision_exists(&format!("{}@{}", branch, remote()), verbose)? {
+            branches.push(branch);
+        }
+    }
+
+    eprintln!("Abandoning stack of {} revision{}:", revisions.len(), if revisions.len() == 1 { "" } else { "s" });
+    for rev in &revisions {
+        match open_prs.iter().find(|(r, _)| r.change_id == rev.change_id) {
+            Some((_, pr_number)) => eprintln!("  - #{}: {}", pr_number, rev.description),
+            None => eprintln!("  - {} ({})", rev.description, &rev.change_id[..8]),
+        }
+    }
+
+    if dry_run {
+        eprintln!("Would close {} PR(s) and delete {} branch(es){}",
+                 open_prs.len(), branches.len(), if local { ", then abandon the local changes" } else { "" });
+        return Ok(());
+    }
+
+    if config.confirm_close_prs && std::io::stdin().is_terminal() && !prompt_yes_no("Abandon this stack?")? {
+        eprintln!("Nothing abandoned");
+        return Ok(());
+    }
+
+    let op_id = track_operation_start(&mut state, "abandon_stack", &revisions)?;
+
+    for (rev, pr_number) in &open_prs {
+        eprintln!("Closing PR #{}", pr_number);
+        let comment = "This PR was closed because the stack it belongs to was abandoned";
+        if let Err(e) = forge::forge().close_pr(&repo_info, *pr_number, Some(comment), verbose) {
+            eprintln!("⚠️  Could not close PR #{}: {:#}", pr_number, e);
+            continue;
+        }
+        record_action(format!("Closed PR #{}", pr_number));
+        if verbose {
+            eprintln!("  Closed PR for {}", &rev.change_id[..8]);
+        }
+    }
+
+    for branch in &branches {
+        delete_branch(branch, false, verbose)?;
+    }
+
+    if local {
+        let revset = revisions.iter().map(|r| r.change_id.as_str()).collect::<Vec<_>>().join(" | ");
+        run_command(&["jj", "abandon", &revset], false, verbose)?;
+        eprintln!("Abandoned {} local change(s)", revisions.len());
+    }
+
+    track_operation_end(&mut state, &op_id, true)?;
+    save_state(&mut state, &[])?;
+    Ok(())
+}
+
+// Copy the stack onto a release branch with `jj duplicate`, push the copies
+// and open a parallel stack of PRs against the branch, cross-linked with the
+// originals. Re-running reuses earlier copies, so conflict resolutions and
+// other fixes made to them are pushed as they are.
+fn backport_stack(onto: &str, args: &Args) -> Result<()> {
+    let (dry_run, verbose) = (args.dry_run, args.verbose);
+    let repo_info = get_repo_info(verbose)?;
+    let _lock = acquire_lock()?;
+    fetch_remotes(verbose)?;
+
+    let mut state = load_state()?;
+    migrate_state(&mut state)?;
+    let config = load_config(args.verbose)?;
+    let _remote_lock = acquire_remote_lock(&config, &repo_info, dry_run, verbose)?;
+
+    let target = format!("{}@{}", onto, pr_remote());
+    if !revision_exists(&target, verbose)? {
+        bail!(CodedError::new(ErrorCode::BaseBranchMissing, format!("No remote branch {} to backport onto", onto)));
+    }
+    let revisions = get_stack_revisions(&stack_base(&state), verbose)?;
+    if revisions.is_empty() {
+        eprintln!("No revisions to backport");
+        return Ok(());
+    }
+
+    let op_id = track_operation_start(&mut state, "backport", &revisions)?;
+    let same = |a: &str, b: &str| a.starts_with(b) || b.starts_with(a);
+
+    // Copies are reused bottom-up for as long as the chain below them was
+    // reused too; anything above a fresh copy is copied again
+    let mut backports = state.backports.get(onto).cloned().unwrap_or_default();
+    let mut previous = target.clone();
+    let mut chain_intact = true;
+    for rev in &revisions {
+        let earlier = backports.iter().position(|b| same(&b.original_change_id, &rev.change_id));
+        if let Some(index) = earlier {
+            let copy = backports[index].change_id.clone();
+            if chain_intact && revision_exists(&copy, verbose)? {
+                previous = copy;
+                continue;
+            }
+            if revision_exists(&copy, verbose)? {
+                run_mutation(&["jj", "abandon", &copy], false, dry_run, verbose)?;
+            }
+            backports.remove(index);
+        }
+        chain_intact = false;
+
+        eprintln!("{} \"{}\" onto {}", if dry_run { "Would copy" } else { "Copying" }, rev.description, previous);
+        let output = run_mutation(&["jj", "duplicate", &rev.change_id, "--destination", &previous], false, dry_run, verbose)?;
+        if dry_run {
+            continue;
+        }
+        let copy = duplicated_change_id(&output)
+            .with_context(|| format!("Could not find the copy of {} in jj's output", &rev.change_id[..8]))?;
+        record_action(format!("Copied {} onto {} as {}", &rev.change_id[..8], onto, copy));
+        backports.push(Backport { original_change_id: rev.change_id.clone(), change_id: copy.clone(), pr_number: None });
+        previous = copy;
+    }
+
+    if dry_run {
+        eprintln!("Would push the copies and open PRs against {}", onto);
+        print_plan();
+        return Ok(());
+    }
+    state.backports.insert(onto.to_string(), backports.clone());
+
+    let mut copies = get_revisions(&format!("{}..{}", target, previous), verbose)?;
+    let conflicted: Vec<&Revision> = copies.iter().filter(|r| r.has_conflicts).collect();
+    if !conflicted.is_empty() {
+        eprintln!("\n⚠️  {} cop{} conflict with {}:", conflicted.len(), if conflicted.len() == 1 { "y has" } else { "ies have" }, onto);
+        for rev in &conflicted {
+            eprintln!("  - {} ({})", rev.description, &rev.change_id[..8]);
+        }
+        eprintln!("\nResolve them (`jj edit <change>`) and re-run backport");
+        track_operation_end(&mut state, &op_id, false)?;
+        write_state(&state)?;
+        bail!(CodedError::new(ErrorCode::Conflicts, "Backport has conflicts"));
+    }
+
+    push_branches(&mut copies, &config.branch_prefix, &repo_info, args.no_force, false, verbose)?;
+    verify_pushed_branches(&copies, verbose)?;
+
+    let existing_prs = get_existing_prs(&repo_info, &stack_branches(&copies, &config), verbose)?;
+    for i in 0..copies.len() {
+        let copy = &copies[i];
+        let branch = copy.branch_name.clone().unwrap_or_default();
+        let base = match i {
+            0 => onto.to_string(),
+            _ => copies[i - 1].branch_name.clone().unwrap_or_default(),
+        };
+        let Some(entry) = backports.iter_mut().find(|b| same(&b.change_id, &copy.change_id)) else { continue };
+        let original_pr = state.prs.iter()
+            .find(|(id, _)| same(id, &entry.original_change_id))
+            .map(|(_, info)| info.pr_number);
+
+        if let Some(pr) = existing_prs.get(&branch) {
+            entry.pr_number = Some(pr.0);
+            if pr.2 == "OPEN" && pr.3 != base {
+                match forge::forge().edit_pr(&repo_info, pr.0, &PrEdit { base: Some(&base), ..PrEdit::default() }, verbose) {
+                    Ok(()) => record_action(format!("Retargeted PR #{} from {} to {}", pr.0, pr.3, base)),
+                    Err(e) => eprintln!("⚠️  Could not retarget PR #{} to {}: {:#}", pr.0, base, e),
+                }
+            }
+            continue;
+        }
+
+        let title = format!("[{}] {}", onto, copy.description);
+        let body = match original_pr {
+            Some(number) => format!("Backport of #{} to `{}`.\n\n{}", number, onto, copy.body),
+            None => format!("Backport to `{}`.\n\n{}", onto, copy.body),
+        };
+        let head = pr_head(&branch);
+        let pr = NewPullRequest { head: &head, base: &base, title: &title, body: &body };
+        let url = forge::create_pr(forge::forge(), &repo_info, &pr, verbose)?;
+        let number = forge::pr_number_from_url(&url)
+            .with_context(|| format!("Could not parse PR number from {}", url))?;
+        entry.pr_number = Some(number);
+        eprintln!("  Opened #{} for \"{}\"", number, copy.description);
+        record_action(format!("Opened backport PR #{} against {}", number, onto));
+
+        if let Some(original) = original_pr {
+            let comment = format!("Backported to `{}` in #{}.", onto, number);
+            if let Err(e) = forge::forge().comment_pr(&repo_info, original, &comment, verbose) {
+                eprintln!("⚠️  Could not link PR #{} from #{}: {:#}", number, original, e);
+            }
+        }
+        println!("{}", url.trim());
+    }
+
+    state.backports.insert(onto.to_string(), backports);
+    track_operation_end(&mut state, &op_id, true)?;
+    write_state(&state)
+}
+
+fn revision_exists(revset: &str, verbose: bool) -> Result<bool> {
+    let output = run_command(&["jj", "log", "-r", revset, "--no-graph", "--template", "commit_id", "--limit", "1"], true, verbose)?;
+    Ok(!output.trim().is_empty() && !output.contains("Error:"))
+}
+
+// Change ID of the new commit in `jj duplicate` output
+// ("Duplicated 7b7ddb3f4b4a as nmzmmopx 3f6c2c1e title")
+fn duplicated_change_id(output: &str) -> Option<String> {
+    output.lines()
+        .find_map(|line| line.trim().strip_prefix("Duplicated ")?.split_once(" as "))
+        .and_then(|(_, copy)| copy.split_whitespace().next())
+        .map(String::from)
+}
+
+// Migrate existing PRs to the current branch naming scheme. GitHub can't change a
+// PR's head branch, so each open PR is recreated from the new branch with
+// cross-links in both directions.
+fn rename_branches(dry_run: bool, verbose: bool) -> Result<()> {
+    let repo_info = get_repo_info(verbose)?;
+    let _lock = acquire_lock()?;
+    fetch_remotes(verbose)?;
+
+    let mut state = load_state()?;
+    migrate_state(&mut state)?;
+    let config = load_config(verbose)?;
+    let _remote_lock = acquire_remote_lock(&config, &repo_info, dry_run, verbose)?;
+    let revisions = get_stack_revisions(&stack_base(&state), verbose)?;
+
+    let tracked = |rev: &Revision| state.prs.iter()
+        .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
+        .map(|(id, info)| (id.clone(), info.clone()));
+
+    let to_rename: Vec<_> = revisions.iter()
+        .filter_map(|rev| {
+            let (id, info) = tracked(rev)?;
+            let new_branch = branch_name_for(&config.branch_prefix, &rev.change_id);
+            (info.branch_name != new_branch).then_some((rev, id, info, new_branch))
+        })
+        .collect();
+
+    if to_rename.is_empty() {
+        eprintln!("All branches already use the {} prefix", config.branch_prefix);
+        return Ok(());
+    }
+
+    for (_, _, info, new_branch) in &to_rename {
+        eprintln!("{} {} -> {} (PR #{})",
+                 if dry_run { "Would rename" } else { "Renaming" },
+                 info.branch_name, new_branch, info.pr_number);
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    let op_id = track_operation_start(&mut state, "rename_branches", &revisions)?;
+
+    // Work bottom to top so every new PR can be based on its parent's new branch.
+    // Each new PR is saved to the state file as soon as it exists, so a failure
+    // partway leaves the PRs already moved mapped to their new numbers.
+    let mut old_branches = Vec::new();
+    for (i, rev) in revisions.iter().enumerate() {
+        let Some((id, info, new_branch)) = to_rename.iter()
+            .find(|(r, _, _, _)| r.change_id == rev.change_id)
+            .map(|(_, id, info, new_branch)| (id, info, new_branch)) else { continue };
+
+        // The parent's branch as it stands: renamed above, or kept where it
+        // wasn't renamed (no open PR, or a branch inherited from another change)
+        let base = if i == 0 {
+            base_branch(&state).to_string()
+        } else {
+            tracked_branch(&state, &config, &revisions[i - 1].change_id)
+        };
+
+        push_bookmark(new_branch, &rev.change_id, false, verbose)?;
+
+        let old_pr = view_pr(&repo_info, &info.pr_number.to_string(), &["state", "title", "body"], verbose);
+        if old_pr["state"].as_str() != Some("OPEN") {
+            if verbose {
+                eprintln!("  Skipping PR #{} (not open)", info.pr_number);
+            }
+            continue;
+        }
+
+        let head = pr_head(new_branch);
+        let pr = NewPullRequest {
+            head: &head,
+            base: &base,
+            title: old_pr["title"].as_str().unwrap_or(&rev.description),
+            body: old_pr["body"].as_str().unwrap_or_default(),
+        };
+        let url = forge::create_pr(forge::forge(), &repo_info, &pr, verbose)?;
+        let new_number = forge::pr_number_from_url(&url)
+            .with_context(|| format!("Could not parse PR number from {}", url))?;
+
+        let forge = forge::forge();
+        let replaces = format!("Replaces #{} (branch renamed from `{}`)", info.pr_number, info.branch_name);
+        if let Err(e) = forge.comment_pr(&repo_info, new_number, &replaces, verbose) {
+            eprintln!("⚠️  Could not link PR #{} to #{}: {:#}", new_number, info.pr_number, e);
+        }
+        let moved = format!("Moved to #{} (branch renamed to `{}`)", new_number, new_branch);
+        if let Err(e) = forge.close_pr(&repo_info, info.pr_number, Some(&moved), verbose) {
+            eprintln!("⚠️  Could not close PR #{}: {:#}", info.pr_number, e);
+        }
+        eprintln!("  PR #{} is now #{}", info.pr_number, new_number);
+        record_action(format!("Recreated PR #{} as #{} on {}", info.pr_number, new_number, new_branch));
+
+        state.prs.insert(id.clone(), PrInfo {
+            pr_number: new_number,
+            pr_url: url.trim().to_string(),
+            branch_name: new_branch.clone(),
+            commit_id: rev.commit_id.clone(),
+            change_id: Some(id.clone()),
+            base_branch: Some(base),
+        });
+        write_state(&state)?;
+        old_branches.push(info.branch_name.clone());
+    }
+
+    // Only drop the old branches once every PR has moved, so GitHub doesn't
+    // close children whose base branch disappears. A branch left behind is
+    // harmless, so failures only warn.
+    for old_branch in &old_branches {
+        if let Err(e) = delete_branch(old_branch, false, verbose) {
+            eprintln!("⚠️  Could not delete the old branch {}: {:#}", old_branch, e);
+        }
+    }
+
+    track_operation_end(&mut state, &op_id, true)?;
+    write_state(&state)?;
+    Ok(())
+}
+
+// Report metrics for managed PRs from state and GitHub
+fn show_stats(json: bool, verbose: bool) -> Result<()> {
+    let repo_info = get_repo_info(verbose)?;
+    let state = load_state()?;
+    let config = load_config(verbose)?;
+
+    let prs = list_pull_requests(&repo_info, false,
+        "number state createdAt mergedAt headRefName author { login } reviews(first: 100) { nodes { author { login } submittedAt } }",
+        verbose)?;
+
+    let parse_time = |value: &serde_json::Value| {
+        value.as_str().and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+    };
+
+    let mut created_per_week: BTreeMap<String, usize> = BTreeMap::new();
+    let mut merged_per_week: BTreeMap<String, usize> = BTreeMap::new();
+    let mut merge_hours_by_level: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+    let mut review_latency_hours = Vec::new();
+    let mut open_prs = 0;
+
+    for pr in prs.iter().filter(|pr| pr["headRefName"].as_str().is_some_and(|h| config.is_managed_branch(h))) {
+        let Some(created) = parse_time(&pr["createdAt"]) else { continue };
+        let week = |t: chrono::DateTime<chrono::FixedOffset>| {
+            let week = t.iso_week();
+            format!("{}-W{:02}", week.year(), week.week())
+        };
+        *created_per_week.entry(week(created)).or_default() += 1;
+
+        if pr["state"].as_str() == Some("OPEN") {
+            open_prs += 1;
+        }
+
+        if let Some(merged) = parse_time(&pr["mergedAt"]) {
+            *merged_per_week.entry(week(merged)).or_default() += 1;
+            let level = pr["number"].as_u64()
+                .and_then(|n| state.pr_levels.get(&(n as u32)))
+                .copied()
+                .unwrap_or(0);
+            let hours = (merged - created).num_minutes() as f64 / 60.0;
+            merge_hours_by_level.entry(level).or_default().push(hours);
+        }
+
+        // First review by someone other than the author
+        let author = pr["author"]["login"].as_str();
+        let first_review = pr["reviews"]["nodes"].as_array().into_iter().flatten()
+            .filter(|review| review["author"]["login"].as_str() != author)
+            .filter_map(|review| parse_time(&review["submittedAt"]))
+            .min();
+        if let Some(reviewed) = first_review {
+            review_latency_hours.push((reviewed - created).num_minutes() as f64 / 60.0);
+        }
+    }
+
+    let average = |values: &[f64]| if values.is_empty() { 0.0 } else { values.iter().sum::<f64>() / values.len() as f64 };
+    let stack_depth = state.stack_order.len();
+
+    if json {
+        let report = serde_json::json!({
+            "stack_depth": stack_depth,
+            "open_prs": open_prs,
+            "created_per_week": created_per_week,
+            "merged_per_week": merged_per_week,
+            "avg_hours_to_merge_by_level": merge_hours_by_level.iter()
+                .map(|(level, hours)| (level.to_string(), average(hours)))
+                .collect::<BTreeMap<_, _>>(),
+            "avg_review_latency_hours": average(&review_latency_hours),
+        });
+        return output::print_json("stats", &report);
+    }
+
+    println!("Current stack depth: {} ({} open PRs)", stack_depth, open_prs);
+    println!("Average review latency: {:.1}h ({} reviewed PRs)", average(&review_latency_hours), review_latency_hours.len());
+
+    println!("\n{:<10} {:>8} {:>8}", "Week", "Created", "Merged");
+    let weeks: BTreeSet<&String> = created_per_week.keys().chain(merged_per_week.keys()).collect();
+    for week in weeks.into_iter().rev().take(8).collect::<Vec<_>>().into_iter().rev() {
+        println!("{:<10} {:>8} {:>8}", week,
+                 created_per_week.get(week).unwrap_or(&0), merged_per_week.get(week).unwrap_or(&0));
+    }
+
+    println!("\n{:<6} {:>8} {:>14}", "Level", "Merged", "Avg to merge");
+    for (level, hours) in &merge_hours_by_level {
+        println!("{:<6} {:>8} {:>13.1}h", level, hours.len(), average(hours));
+    }
+
+    Ok(())
+}
+
+// List recorded operations, or the details of one
+fn show_history(id: Option<&str>) -> Result<()> {
+    let state = load_state()?;
+
+    if let Some(id) = id {
+        let op = state.operations.iter()
+            .find(|op| op.id == id)
+            .with_context(|| format!("No operation with id {}", id))?;
+
+        println!("Operation: {}", op.id);
+        println!("Type:      {}", op.op_type);
+        println!("Started:   {}", op.timestamp);
+        println!("Result:    {}", if op.success { "succeeded" } else { "failed or interrupted" });
+        println!("\nRevisions affected:");
+        for change_id in &op.changes_affected {
+            println!("  {}", change_id);
+        }
+        println!("\nActions:");
+        if op.actions.is_empty() {
+            println!("  (none)");
+        }
+        for action in &op.actions {
+            println!("  {}", action);
+        }
+        if !op.calls.is_empty() {
+            println!("\nCalls:");
+            for (category, count) in &op.calls {
+                println!("  {:<24} {:>4}", category, count);
+            }
+        }
+        return Ok(());
+    }
+
+    if state.operations.is_empty() {
+        eprintln!("No operations recorded");
+    }
+    for op in state.operations.iter().rev() {
+        println!("{}  {}  {:<16} {}  {} revision(s), {} action(s)",
+                 op.id, op.timestamp, op.op_type,
+                 if op.success { "✓" } else { "✗" },
+                 op.changes_affected.len(), op.actions.len());
+    }
+    Ok(())
+}
+
+// Render the stack (titles, PR links, states, diff stats) for sharing outside GitHub
+fn export_stack(format: ExportFormat, gist: bool, verbose: bool) -> Result<()> {
+    let repo_info = get_repo_info(verbose)?;
+    let state = load_state()?;
+    let config = load_config(verbose)?;
+    let revisions = get_stack_revisions(&stack_base(&state), verbose)?;
+    if revisions.is_empty() {
+        bail!("No revisions in the stack");
+    }
+    let branches: Vec<String> = revisions.iter().map(|rev| tracked_branch(&state, &config, &rev.change_id)).collect();
+    let existing_prs = get_existing_prs(&repo_info, &branches, verbose)?;
+
+    let document = match format {
+        ExportFormat::Markdown => {
+            let top = &revisions[revisions.len() - 1];
+            let mut doc = format!("# Stack: {}\n\n", top.description);
+            doc.push_str(&format!("Repository: `{}` · {} revision{} · exported {}\n\n",
+                                  repo_info, revisions.len(), if revisions.len() == 1 { "" } else { "s" },
+                                  chrono::Utc::now().format("%Y-%m-%d %H:%M UTC")));
+            doc.push_str("| # | Change | Commit | PR | State | Diff |\n");
+            doc.push_str("|---|--------|--------|----|-------|------|\n");
+
+            for (i, rev) in revisions.iter().enumerate() {
+                let (pr, pr_state) = match existing_prs.get(&tracked_branch(&state, &config, &rev.change_id)) {
+                    Some(pr) => (format!("[#{}]({})", pr.0, pr.1), pr.2.to_lowercase()),
+                    None => ("—".to_string(), "no PR".to_string()),
+                };
+                let stat = run_command(&["jj", "diff", "--stat", "-r", &rev.change_id], true, verbose)?;
+                let summary = stat.lines().last().unwrap_or_default().trim().to_string();
+
+                doc.push_str(&format!("| {} | {} (`{}`) | {} | {} | {} | {} |\n",
+                                      i + 1, rev.description.replace('|', "\\|"), &rev.change_id[..8],
+                                      commit_link(&repo_info, &rev.commit_id), pr, pr_state, summary));
+            }
+
+            let nodes: Vec<(String, Option<&str>)> = revisions.iter()
+                .map(|rev| match existing_prs.get(&tracked_branch(&state, &config, &rev.change_id)) {
+                    Some(pr) => (format!("#{}: {}", pr.0, rev.description), Some(pr.2.as_str())),
+                    None => (rev.description.clone(), None),
+                })
+                .collect();
+            doc.push_str("\n## Dependencies\n\n");
+            doc.push_str(&mermaid_graph(base_branch(&state), &nodes, None));
+            doc
+        }
+    };
+
+    if !gist {
+        print!("{}", document);
+        return Ok(());
+    }
+
+    let path = std::env::temp_dir().join(format!("almighty-stack-{}.md", process::id()));
+    fs::write(&path, &document)?;
+    let output = run_command(&[
+        "gh", "gist", "create", &path.to_string_lossy(),
+        "--desc", &format!("Stack overview for {}", repo_info)
+    ], false, verbose);
+    let _ = fs::remove_file(&path);
+
+    match output?.lines().find(|l| l.starts_with("https://")) {
+        Some(url) => println!("{}", url),
+        None => bail!("Could not find the gist URL in gh output"),
+    }
+    Ok(())
+}
+
+// Print the title, base and body almighty-push would generate for a revision,
+// through $PAGER when stdout is a terminal
+fn preview_pr(change: Option<&str>, verbose: bool) -> Result<()> {
+    let repo_info = get_repo_info(verbose)?;
+    let state = load_state()?;
+    let config = load_config(verbose)?;
+    let mut revisions = get_stack_revisions(&stack_base(&state), verbose)?;
+    if revisions.is_empty() {
+        bail!("No revisions in the stack");
+    }
+
+    let index = match change {
+        Some(id) => revisions.iter()
+            .position(|r| r.change_id.starts_with(id))
+            .with_context(|| format!("No revision in the stack matches {}", id))?,
+        None => revisions.len() - 1,
+    };
+
+    // PRs as the state file last recorded them, so previews work offline
+    for rev in revisions.iter_mut() {
+        let tracked = state.prs.iter()
+            .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()));
+        if let Some((id, info)) = tracked {
+            rev.pr_number = Some(info.pr_number);
+            rev.pr_url = Some(info.pr_url.clone());
+            rev.pr_state = Some(if state.merged_prs.contains(id) { "MERGED" } else { "OPEN" }.to_string());
+        }
+        rev.branch_name = Some(tracked_branch(&state, &config, &rev.change_id));
+    }
+
+    let base = match index {
+        0 => base_branch(&state).to_string(),
+        _ => revisions[index - 1].branch_name.clone().unwrap_or_default(),
+    };
+    revisions[index].base_branch = Some(base.clone());
+    if config.uses_paths() {
+        load_changed_paths(&mut revisions[index..=index], verbose)?;
+    }
+    let rev = &revisions[index];
+    let mut preview = format!("Title: {}\n", rev.description);
+    preview.push_str(&format!("Head:  {}\n", rev.branch_name.as_deref().unwrap_or_default()));
+    preview.push_str(&format!("Base:  {}\n", base));
+    match rev.pr_number {
+        Some(n) => preview.push_str(&format!("PR:    #{}\n", n)),
+        None => preview.push_str("PR:    (new)\n"),
+    }
+    preview.push('\n');
+    if config.stack_comment {
+        preview.push_str(&format!("{}\n\nStack comment:\n\n{}", author_pr_body(rev), stack_section(&revisions, index, &repo_info, &config)));
+    } else {
+        preview.push_str(&stack_pr_body(&revisions, index, &repo_info, &config));
+    }
+
+    page_output(&preview)
+}
+
+// Reorder the stack by editing a list of its revisions (like `git rebase -i`),
+// rebase them into the new order, then push so branches and PR bases follow
+fn reorder_stack(args: &Args) -> Result<()> {
+    let mut state = load_state()?;
+    let revisions = get_stack_revisions(&stack_base(&state), args.verbose)?;
+    if revisions.len() < 2 {
+        eprintln!("Nothing to reorder");
+        return Ok(());
+    }
+
+    let order = edit_stack_order(&revisions)?;
+    let unchanged = order.iter().enumerate().take_while(|(position, &i)| *position == i).count();
+    if unchanged == revisions.len() {
+        eprintln!("Stack order unchanged");
+        return Ok(());
+    }
+
+    backup_stack(&mut state, "reordering the stack", &revisions, args.dry_run, args.verbose)?;
+
+    // Rebase one revision at a time onto the one now below it; revisions
+    // before the first moved one are already in place
+    let mut dest = match unchanged {
+        0 => revisions[0].parent_change_ids.first().cloned().context("The bottom of the stack has no parent")?,
+        n => revisions[order[n - 1]].change_id.clone(),
+    };
+    for &i in &order[unchanged..] {
+        let rev = &revisions[i];
+        run_mutation(&["jj", "rebase", "-r", &rev.change_id, "-d", &dest], false, args.dry_run, args.verbose)?;
+        dest = rev.change_id.clone();
+    }
+
+    // An undescribed working-copy commit on top of the stack stays on top
+    let working_copy = run_command(&["jj", "log", "-r", "@", "--no-graph", "--template", "change_id"], false, args.verbose)?;
+    if !revisions.iter().any(|r| r.change_id == working_copy.trim()) {
+        run_mutation(&["jj", "rebase", "-r", "@", "-d", &dest], false, args.dry_run, args.verbose)?;
+    }
+
+    if args.dry_run {
+        print_plan();
+        return Ok(());
+    }
+    record_action(format!("Reordered stack: {}", order.iter()
+        .map(|&i| &revisions[i].change_id[..8.min(revisions[i].change_id.len())])
+        .collect::<Vec<_>>().join(" → ")));
+
+    eprintln!("Stack reordered; pushing...");
+    push_stack(args)
+}
+
+// Resolve a change ID or PR number to the other, from the state file first
+// and GitHub or jj when it doesn't know, then print what was found (or only
+// the URL, for `url`)
+fn find_pr(query: &str, url_only: bool, args: &Args) -> Result<()> {
+    let state = load_state()?;
+    let config = load_config(args.verbose)?;
+    let repo = get_repo_info(args.verbose)?;
+    let query = query.trim().trim_start_matches('#');
+
+    let tracked = match query.parse::<u32>() {
+        Ok(number) => state.prs.iter().find(|(_, info)| info.pr_number == number),
+        Err(_) => state.prs.iter().find(|(id, _)| id.starts_with(query) || query.starts_with(id.as_str())),
+    };
+    // The PR number, or failing that its branch, is what GitHub is asked about
+    let (change_id, target) = match (tracked, query.parse::<u32>()) {
+        (Some((change_id, info)), _) => (Some(change_id.clone()), info.pr_number.to_string()),
+        (None, Ok(number)) => (None, number.to_string()),
+        (None, Err(_)) => {
+            let output = run_command(&["jj", "log", "-r", query, "--no-graph", "--template", r#"change_id ++ "\n""#], true, args.verbose)?;
+            let Some(change_id) = output.lines().next().filter(|_| !output.contains("Error:")).map(str::to_string) else {
+                bail!("No change or PR matches {}", query);
+            };
+            let branch = state.inherited_branches.get(&change_id).cloned()
+                .unwrap_or_else(|| branch_name_for(&config.branch_prefix, &change_id));
+            (Some(change_id), branch)
+        }
+    };
+
+    let pr = view_pr(&repo, &target, &["number", "url", "state", "headRefName"], args.verbose);
+    let stored = tracked.map(|(_, info)| info);
+    let Some(number) = pr["number"].as_u64().map(|n| n as u32).or(stored.map(|info| info.pr_number)) else {
+        bail!("No PR found for {}", query);
+    };
+    let branch = pr["headRefName"].as_str().map(str::to_string).or(stored.map(|info| info.branch_name.clone()));
+    // A PR found by number maps back to a change through its branch name
+    let change_id = change_id.or_else(|| branch.as_deref()
+        .filter(|branch| config.is_managed_branch(branch))
+        .and_then(|branch| branch.strip_prefix(config.branch_prefix.as_str()))
+        .map(str::to_string));
+
+    let found = output::PrLookup {
+        change_id,
+        branch,
+        pr_number: number,
+        pr_url: pr["url"].as_str().map(str::to_string).or(stored.map(|info| info.pr_url.clone())),
+        pr_state: pr["state"].as_str().map(str::to_string),
+    };
+    if url_only {
+        println!("{}", found.pr_url.as_deref().context("The PR's URL is unknown")?);
+    } else if args.format == OutputFormat::Json {
+        output::print_json("find", &found)?;
+    } else {
+        println!("change: {}", found.change_id.as_deref().unwrap_or("-"));
+        println!("branch: {}", found.branch.as_deref().unwrap_or("-"));
+        println!("pr:     #{}", found.pr_number);
+        println!("url:    {}", found.pr_url.as_deref().unwrap_or("-"));
+        println!("state:  {}", found.pr_state.as_deref().unwrap_or("unknown (GitHub unreachable)"));
+    }
+    Ok(())
+}
+
+// PR label of each tracked change ("#12", "#12 merged"), straight from the
+// state file so `jj log` stays fast
+fn annotate(for_template: bool) -> Result<()> {
+    let state = load_state()?;
+    let mut labels: Vec<(&str, String)> = state.prs.iter()
+        .map(|(change_id, info)| {
+            let same = |id: &String| id.starts_with(change_id.as_str()) || change_id.starts_with(id.as_str());
+            let status = if state.merged_prs.iter().any(same) {
+                " merged"
+            } else if state.closed_prs.iter().any(same) {
+                " closed"
+            } else {
+                ""
+            };
+            (&change_id[..12.min(change_id.len())], format!("#{}{}", info.pr_number, status))
+        })
+        .collect();
+    labels.sort();
+
+    if !for_template {
+        for (change_id, label) in &labels {
+            println!("{}  {}", change_id, label);
+        }
+        return Ok(());
+    }
+
+    // coalesce() picks the label of the one condition that matches
+    let cases: String = labels.iter()
+        .map(|(change_id, label)| format!(
+            "if(change_id.short({}).starts_with(\"{}\"), label(\"almighty_pr\", \"{}\")), ",
+            change_id.len(), change_id, label))
+        .collect();
+    println!("coalesce({}\"\")", cases);
+    Ok(())
+}
+
+// Install `jj prs`: `jj log` with each change's PR next to its change ID. The
+// alias asks `annotate --for-template` for the labels on every call, so they
+// follow the state file without reinstalling. Needs jj's `util exec`.
+fn integrate_jj(dry_run: bool, verbose: bool) -> Result<()> {
+    let script = concat!(
+        r#"jj log --config "template-aliases.almighty_pr=$(cd "$(jj root)" && almighty-push annotate --for-template)" "#,
+        r#"-T 'separate(" ", format_short_change_id(change_id), almighty_pr, description.first_line()) ++ "
+"' "$@""#,
+    );
+    let alias = serde_json::to_string(&["util", "exec", "--", "sh", "-c", script, "jj-prs"])?;
+
+    // A fallback keeps custom templates that use almighty_pr working outside the alias
+    run_mutation(&["jj", "config", "set", "--repo", "template-aliases.almighty_pr", "'\"\"'"], false, dry_run, verbose)?;
+    run_mutation(&["jj", "config", "set", "--repo", "aliases.prs", &alias], false, dry_run, verbose)?;
+    if dry_run {
+        print_plan();
+        return Ok(());
+    }
+
+    eprintln!("Added `jj prs` to this repository's jj config: `jj log` with each change's PR");
+    eprintln!("Use almighty_pr in your own templates with: jj log --config \"template-aliases.almighty_pr=$(almighty-push annotate --for-template)\"");
+    Ok(())
+}
+
+// Record the current jj operation before an automatic rebase, so that
+// `restore-backup` can return to it. jj moves bookmarks along with rewritten
+// commits, so bookmarks on the old commits wouldn't survive the rebase.
+fn backup_stack(state: &mut State, reason: &str, revisions: &[Revision], dry_run: bool, verbose: bool) -> Result<()> {
+    if dry_run {
+        return Ok(());
+    }
+
+    let output = run_command(&["jj", "op", "log", "--limit", "1", "--no-graph", "--template", r#"id ++ "\n""#], false, verbose)?;
+    let operation = output.lines().next().unwrap_or_default().trim().to_string();
+    let mut id = format!("backup-{}", SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs());
+    if state.backups.iter().any(|b| b.id == id) {
+        id = format!("{}-{}", id, state.backups.len());
+    }
+
+    if verbose {
+        eprintln!("  Backed up the stack as {} before {}", id, reason);
+    }
+    record_action(format!("Backed up stack as {} before {}", id, reason));
+    state.backups.push(Backup {
+        id,
+        operation,
+        reason: reason.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        commits: revisions.iter().map(|r| (r.change_id.clone(), r.commit_id.clone())).collect(),
+    });
+    if state.backups.len() > MAX_BACKUPS {
+        state.backups.drain(..state.backups.len() - MAX_BACKUPS);
+    }
+    // Saved now: the rebase that follows may stop the run (on conflicts, say)
+    // before the state is written, and that is when the backup is needed
+    write_state(state)
+}
+
+// Return the repository to the jj operation recorded by a backup (the latest
+// by default). Everything done after it, by almighty-push or by hand, is undone.
+fn restore_backup(id: Option<&str>, list: bool, dry_run: bool, verbose: bool) -> Result<()> {
+    let state = load_state()?;
+    let Some(latest) = state.backups.last() else {
+        eprintln!("No backups recorded");
+        return Ok(());
+    };
+
+    if list {
+        for backup in state.backups.iter().rev() {
+            println!("{}  {}  before {} ({} revisions)", backup.id, backup.timestamp, backup.reason, backup.commits.len());
+        }
+        return Ok(());
+    }
+
+    let backup = match id {
+        Some(id) => state.backups.iter().find(|b| b.id == id)
+            .with_context(|| format!("No backup {} (see restore-backup --list)", id))?,
+        None => latest,
+    };
+    eprintln!("{} the repository to {} (taken before {}, {})",
+             if dry_run { "Would restore" } else { "Restoring" }, backup.id, backup.reason, backup.timestamp);
+    run_mutation(&["jj", "op", "restore", &backup.operation], false, dry_run, verbose)?;
+    if dry_run {
+        print_plan();
+        return Ok(());
+    }
+
+    for (change_id, commit_id) in &backup.commits {
+        eprintln!("  {} at {}", &change_id[..8.min(change_id.len())], &commit_id[..12.min(commit_id.len())]);
+    }
+    eprintln!("Run almighty-push to push the restored stack");
+    Ok(())
+}
+
+// Let the user reorder the stack's revisions in their editor. Returns the
+// indices of `revisions` in the new order, bottom first.
+fn edit_stack_order(revisions: &[Revision]) -> Result<Vec<usize>> {
+    let mut todo = String::from(
+        "# Reorder the lines to reorder the stack; the first line is the bottom,\n\
+         # next to the base. Lines starting with # are ignored. Don't add or remove\n\
+         # lines; delete everything to abort.\n\n");
+    for rev in revisions {
+        todo.push_str(&format!("{} {}\n", &rev.change_id[..12.min(rev.change_id.len())], rev.description));
+    }
+
+    let path = std::env::temp_dir().join(format!("almighty-reorder-{}.txt", process::id()));
+    fs::write(&path, todo)?;
+    let editor = ["JJ_EDITOR", "VISUAL", "EDITOR"].iter()
+        .find_map(|var| std::env::var(var).ok().filter(|e| !e.trim().is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let status = Command::new("sh")
+        .args(["-c", &format!("{} \"$0\"", editor), &path.to_string_lossy()])
+        .status()
+        .with_context(|| format!("Failed to run editor: {}", editor));
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    if !status?.success() {
+        bail!("Editor exited with an error; stack left as it was");
+    }
+
+    let mut order = Vec::new();
+    for line in edited?.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let id = line.split_whitespace().next().unwrap_or_default();
+        let index = revisions.iter()
+            .position(|r| r.change_id.starts_with(id))
+            .with_context(|| format!("{} is not a revision in the stack", id))?;
+        if order.contains(&index) {
+            bail!("{} is listed twice", id);
+        }
+        order.push(index);
+    }
+    if order.is_empty() {
+        bail!("Reorder aborted");
+    }
+    if order.len() != revisions.len() {
+        bail!("Every revision must stay in the list ({} of {} listed); use `jj abandon` to drop one",
+              order.len(), revisions.len());
+    }
+    Ok(order)
+}
+
+// Write text to stdout, or to $PAGER when stdout is a terminal
+fn page_output(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").ok().filter(|p| !p.trim().is_empty());
+    let Some(pager) = pager.filter(|_| std::io::stdout().is_terminal()) else {
+        print!("{}", text);
+        return Ok(());
+    };
+
+    let mut child = Command::new("sh")
+        .args(["-c", &pager])
+        .stdin(process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run pager: {}", pager))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything (e.g. `q` in less)
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+// Generate a static HTML dashboard of the stack, optionally serving it locally
+fn write_html_report(dir: &Path, serve: bool, port: u16, verbose: bool) -> Result<()> {
+    let repo_info = get_repo_info(verbose)?;
+    let state = load_state()?;
+    let config = load_config(verbose)?;
+    let revisions = stack_with_prs(&repo_info, &state, &config, verbose)?;
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>Stack report: {}</title>\n", html_escape(&repo_info)));
+    html.push_str("<style>\n\
+        body { font-family: sans-serif; margin: 2em; }\n\
+        table { border-collapse: collapse; margin-bottom: 2em; }\n\
+        th, td { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }\n\
+        .open { color: #1a7f37; } .merged { color: #8250df; } .closed { color: #cf222e; }\n\
+        .passing { color: #1a7f37; } .failing { color: #cf222e; } .pending { color: #9a6700; }\n\
+        </style>\n</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n<p>Generated {}</p>\n",
+                           html_escape(&repo_info), chrono::Utc::now().format("%Y-%m-%d %H:%M UTC")));
+
+    html.push_str("<h2>Stack</h2>\n<table>\n<tr><th>#</th><th>Change</th><th>Commit</th><th>Title</th><th>PR</th><th>State</th><th>Checks</th></tr>\n");
+    for (i, rev) in revisions.iter().enumerate().rev() {
+        let (link, pr_state, check) = match (rev.pr_number, &rev.pr_url, &rev.pr_state) {
+            (Some(number), Some(url), Some(pr_state)) => (
+                format!("<a href=\"{}\">#{}</a>", html_escape(url), number),
+                pr_state.to_lowercase(),
+                match rev.pr_checks.as_deref() {
+                    Some("SUCCESS") => "passing",
+                    Some("FAILURE" | "ERROR") => "failing",
+                    Some(_) => "pending",
+                    None => "none",
+                },
+            ),
+            _ => ("—".to_string(), "no PR".to_string(), "none"),
+        };
+        html.push_str(&format!(
+            "<tr><td>{}</td><td><code>{}</code></td><td><a href=\"{}/{}/commit/{}\"><code>{}</code></a></td><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td class=\"{}\">{}</td></tr>\n",
+            i + 1, &rev.change_id[..8], forge::web_url(), html_escape(&repo_info), rev.commit_id, &rev.commit_id[..7.min(rev.commit_id.len())],
+            html_escape(&rev.description), link, pr_state, pr_state, check, check
+        ));
+    }
+    html.push_str("</table>\n");
+
+    html.push_str("<h2>History</h2>\n<table>\n<tr><th>Operation</th><th>Started</th><th>Type</th><th>Result</th><th>Actions</th></tr>\n");
+    for op in state.operations.iter().rev() {
+        html.push_str(&format!(
+            "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(&op.id), html_escape(&op.timestamp), html_escape(&op.op_type),
+            if op.success { "✓" } else { "✗" },
+            op.actions.iter().map(|a| html_escape(a)).collect::<Vec<_>>().join("<br>")
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    fs::create_dir_all(dir)?;
+    let index = dir.join("index.html");
+    fs::write(&index, &html)?;
+    eprintln!("Wrote {}", index.display());
+
+    // A single response with the page just written: enough to open it in a
+    // browser, with nothing left running. Use a real web server for dashboards.
+    if serve {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+            .with_context(|| format!("Failed to listen on port {}", port))?;
+        eprintln!("Serving the report once at http://127.0.0.1:{}/", port);
+        let (mut stream, _) = listener.accept()?;
+        let mut request = [0u8; 1024];
+        let _ = stream.read(&mut request);
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+               html.len(), html)?;
+    }
+    Ok(())
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Start stacking on top of another PR's head branch
+fn track_base_pr(state: &mut State, number: u32, repo: &str, verbose: bool) -> Result<()> {
+    let pr = forge::forge().view_pr(repo, &number.to_string(), &["headRefName", "state"], verbose)?.unwrap_or_default();
+
+    if pr["state"].as_str() != Some("OPEN") {
+        bail!(CodedError::new(ErrorCode::BasePrNotOpen, format!("PR #{} is not open", number)));
+    }
+    let branch = pr["headRefName"].as_str().context("PR has no head branch")?.to_string();
+
+    if state.base_pr.as_ref().is_none_or(|base| base.number != number) {
+        eprintln!("Stacking on PR #{} ({})", number, branch);
+    }
+    state.base_pr = Some(BasePr { number, branch });
+    Ok(())
+}
+
+// Once the PR we stack on merges, move the stack onto main and stop tracking it
+fn follow_base_pr(state: &mut State, repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    let Some(base_pr) = state.base_pr.clone() else { return Ok(()) };
+
+    let pr = view_pr(repo, &base_pr.number.to_string(), &["state"], verbose);
+
+    match pr["state"].as_str().unwrap_or_default() {
+        "MERGED" => {
+            let destination = format!("{}@{}", trunk(state), pr_remote());
+            eprintln!("Base PR #{} merged, moving the stack onto {}", base_pr.number, trunk(state));
+            let revisions = get_stack_revisions(&stack_base(state), verbose)?;
+            backup_stack(state, "moving the stack off a merged base PR", &revisions, dry_run, verbose)?;
+            let roots = format!("roots({}@{}..@)", base_pr.branch, pr_remote());
+            run_mutation(&["jj", "rebase", "-s", &roots, "-d", &destination], false, dry_run, verbose)?;
+            if dry_run {
+                return Ok(());
+            }
+            record_action(format!("Rebased stack from {} onto {}", base_pr.branch, destination));
+            state.base_pr = None;
+        }
+        "CLOSED" => {
+            eprintln!("⚠️  Base PR #{} was closed without merging; the stack still sits on {}",
+                     base_pr.number, base_pr.branch);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Show every divergent change in the stack and keep one commit per change,
+// chosen interactively or by policy; the others are abandoned. Returns the
+// commit IDs abandoned.
+fn resolve_divergent_changes(revisions: &[Revision], policy: DivergencePolicy, dry_run: bool, verbose: bool) -> Result<Vec<String>> {
+    let mut copies: BTreeMap<&str, Vec<&Revision>> = BTreeMap::new();
+    for rev in revisions {
+        copies.entry(rev.change_id.as_str()).or_default().push(rev);
+    }
+    copies.retain(|_, revs| revs.len() > 1);
+
+    let mut abandoned = Vec::new();
+    for (change_id, revs) in copies {
+        eprintln!("⚠️  Change {} is divergent ({} commits):", &change_id[..8.min(change_id.len())], revs.len());
+
+        // (commit id, unix timestamp, "<short id> <age> <title>")
+        let mut candidates = Vec::new();
+        for rev in &revs {
+            let output = run_command(&[
+                "jj", "log", "-r", &rev.commit_id, "--no-graph",
+                "--template", r#"committer.timestamp().format("%s") ++ "|" ++ commit_id.short() ++ " " ++ committer.timestamp().ago() ++ " " ++ description.first_line()"#
+            ], false, verbose)?;
+            let (timestamp, summary) = output.trim().split_once('|').unwrap_or(("0", output.trim()));
+            candidates.push((rev.commit_id.clone(), timestamp.parse::<i64>().unwrap_or(0), summary.to_string()));
+        }
+        for (i, (_, _, summary)) in candidates.iter().enumerate() {
+            eprintln!("  {}) {}", i + 1, summary);
+        }
+
+        let keep = match policy {
+            DivergencePolicy::Newest => (0..candidates.len()).max_by_key(|&i| candidates[i].1).unwrap_or(0),
+            DivergencePolicy::Oldest => (0..candidates.len()).min_by_key(|&i| candidates[i].1).unwrap_or(0),
+            DivergencePolicy::Ask if std::io::stdin().is_terminal() => prompt_choice(candidates.len())?,
+            DivergencePolicy::Ask | DivergencePolicy::Abort => {
+                bail!(CodedError::new(ErrorCode::DivergentChange, format!(
+                    "Divergent change {}; pick a survivor with `jj abandon` or re-run with --divergence newest|oldest",
+                    &change_id[..8.min(change_id.len())])));
+            }
+        };
+
+        let kept = &candidates[keep];
+        eprintln!("  {} {}", if dry_run { "Would keep" } else { "Keeping" }, kept.2);
+        for (i, (commit_id, _, _)) in candidates.iter().enumerate() {
+            if i == keep {
+                continue;
+            }
+            run_mutation(&["jj", "abandon", commit_id], false, dry_run, verbose)?;
+            abandoned.push(commit_id.clone());
+        }
+        record_action(format!("Resolved divergent change {}: kept {}, abandoned {}",
+                              &change_id[..8.min(change_id.len())], &kept.0[..12.min(kept.0.len())],
+                              candidates.iter().enumerate().filter(|(i, _)| *i != keep)
+                                  .map(|(_, c)| &c.0[..12.min(c.0.len())]).collect::<Vec<_>>().join(", ")));
+    }
+
+    Ok(abandoned)
+}
+
+// Ask for a number between 1 and `count`, returning its zero-based index
+fn prompt_choice(count: usize) -> Result<usize> {
+    loop {
+        eprint!("Keep which commit? [1-{}] ", count);
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            bail!("No commit chosen");
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => return Ok(n - 1),
+            _ => eprintln!("Enter a number between 1 and {}", count),
+        }
+    }
+}
+
+// Rebuild PR mappings missing from state (e.g. on a fresh clone) from the
+// `Pull-Request:` trailers written in stateless mode
+fn recover_state_from_trailers(state: &mut State, revisions: &[Revision], config: &Config) {
+    for rev in revisions {
+        let Some((pr_number, pr_url)) = description::pr_trailer(&rev.body) else { continue };
+        let known = state.prs.keys().any(|id| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()));
+        if known {
+            continue;
+        }
+        state.prs.insert(rev.change_id.clone(), PrInfo {
+            pr_number,
+            pr_url,
+            branch_name: branch_name_for(&config.branch_prefix, &rev.change_id),
+            commit_id: rev.commit_id.clone(),
+            change_id: Some(rev.change_id.clone()),
+            base_branch: None,
+        });
+    }
+}
+
+// Stateless mode: add a `Pull-Request:` trailer to every revision whose PR
+// isn't recorded in its description yet. Rewriting a description rewrites
+// its descendants too, so their branches are pushed again afterwards.
+fn record_pr_trailers(revisions: &mut [Revision], base: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    let mut lowest = None;
+    for (i, rev) in revisions.iter().enumerate() {
+        let (Some(pr_number), Some(pr_url)) = (rev.pr_number, &rev.pr_url) else { continue };
+        if description::pr_trailer(&rev.body).is_some_and(|(n, _)| n == pr_number) {
+            continue;
+        }
+
+        if verbose || dry_run {
+            eprintln!("{} PR #{} in the description of {}",
+                     if dry_run { "Would record" } else { "Recording" }, pr_number, &rev.change_id[..8]);
+        }
+        let message = description::with_trailer(&rev.description, &rev.body, description::PR_TRAILER, pr_url.trim());
+        run_mutation(&["jj", "describe", "-r", &rev.change_id, "-m", &message], false, dry_run, verbose)?;
+        lowest.get_or_insert(i);
+    }
+
+    let Some(lowest) = lowest else { return Ok(()) };
+
+    let mut push = vec!["jj", "git", "push", "--remote", remote()];
+    for rev in &revisions[lowest..] {
+        if let Some(branch) = &rev.branch_name {
+            push.extend(["-b", branch.as_str()]);
+        }
+    }
+    run_mutation(&push, false, dry_run, verbose)?;
+
+    if !dry_run {
+        // Pick up the rewritten commits (and bodies) for the PR descriptions
+        let current = get_stack_revisions(base, verbose)?;
+        for rev in revisions.iter_mut() {
+            if let Some(new) = current.iter().find(|r| r.change_id == rev.change_id) {
+                rev.commit_id = new.commit_id.clone();
+                rev.body = new.body.clone();
+            }
+        }
+        record_action(format!("Recorded PR trailers from {}", &revisions[lowest].change_id[..8]));
+    }
+    Ok(())
+}
+
+// Number of revisions at the bottom of the stack that are pushed, unchanged,
+// as part of another stack: a managed remote branch outside our stack
+// descends from each of them
+fn shared_stack_prefix(revisions: &[Revision], state: &State, config: &Config, verbose: bool) -> Result<usize> {
+    let ours = |id: &str| revisions.iter().any(|r| r.change_id.starts_with(id) || id.starts_with(r.change_id.as_str()));
+
+    for (i, rev) in revisions.iter().enumerate() {
+        let branch = tracked_branch(state, config, &rev.change_id);
+        if remote_commit(&branch, verbose)?.as_deref() != Some(rev.commit_id.as_str()) {
+            return Ok(i);
+        }
+
+        let output = run_command(&[
+            "jj", "log", "-r", &format!("{}+:: & remote_bookmarks()", rev.commit_id), "--no-graph",
+            "--template", r#"change_id ++ "|" ++ remote_bookmarks.map(|b| b.name()).join(",") ++ "\n""#
+        ], true, verbose)?;
+        let foreign = output.lines().any(|line| {
+            let Some((change_id, names)) = line.split_once('|') else { return false };
+            !ours(change_id) && names.split(',').any(|name| config.is_managed_branch(name))
+        });
+        if !foreign {
+            return Ok(i);
+        }
+    }
+
+    Ok(revisions.len())
+}
+
+// Positions of untracked revisions whose author isn't the jj user; revisions
+// that already have PRs were included before and stay included
+fn foreign_revisions(revisions: &[Revision], state: &State, verbose: bool) -> Result<Vec<usize>> {
+    let (Some(bottom), Some(top)) = (revisions.first(), revisions.last()) else { return Ok(Vec::new()) };
+    let revset = format!("({}::{}) & ~mine()", bottom.change_id, top.change_id);
+    let output = run_command(&["jj", "log", "-r", &revset, "--no-graph", "--template", r#"change_id ++ "\n""#], false, verbose)?;
+    let foreign: HashSet<&str> = output.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+
+    Ok((0..revisions.len())
+        .filter(|&i| foreign.contains(revisions[i].change_id.as_str()))
+        .filter(|&i| !state.prs.keys().any(|id| id.starts_with(&revisions[i].change_id) || revisions[i].change_id.starts_with(id.as_str())))
+        .collect())
+}
+
+// Drop other authors' revisions from the bottom of the stack and base ours on
+// the open PR of the branch pointing at the topmost of them
+fn exclude_foreign_revisions(revisions: &mut Vec<Revision>, foreign: &[usize], state: &mut State, repo: &str, verbose: bool) -> Result<()> {
+    let count = foreign.len();
+    if foreign.iter().enumerate().any(|(position, &i)| position != i) {
+        bail!(CodedError::new(ErrorCode::ForeignCommits,
+            "--exclude-foreign only works when the other authors' commits are at the bottom of the stack"));
+    }
+
+    let top = &revisions[count - 1];
+    let output = run_command(&[
+        "jj", "log", "-r", &top.commit_id, "--no-graph",
+        "--template", &format!(r#"remote_bookmarks.filter(|b| b.remote() == "{}").map(|b| b.name()).join("\n")"#, remote())
+    ], false, verbose)?;
+
+    for branch in output.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let pr = view_pr(repo, branch, &["number", "state"], verbose);
+        if let (Some(number), Some("OPEN")) = (pr["number"].as_u64(), pr["state"].as_str()) {
+            eprintln!("Leaving {} revision(s) by other authors out; stacking on PR #{} ({})", count, number, branch);
+            revisions.drain(..count);
+            state.base_pr = Some(BasePr { number: number as u32, branch: branch.to_string() });
+            record_action(format!("Stacked on PR #{} by another author", number));
+            return Ok(());
+        }
+    }
+
+    bail!(CodedError::new(ErrorCode::ForeignCommits, format!(
+        "No branch with an open PR points at {} ({}); use --include-foreign, or ask its author to push it",
+        top.description, &top.change_id[..8])))
+}
+
+// Branch set with --base or base_branch, standing in for the default branch
+static BASE_BRANCH: OnceLock<String> = OnceLock::new();
+
+// Pull request template PR bodies are built from (--body-template, else the
+// repository's own, unless use_pr_template is off)
+static PR_TEMPLATE: OnceLock<String> = OnceLock::new();
+
+// Branch the stack lands on: the configured base, else the remote default
+// branch (main unless detected otherwise)
+fn trunk(state: &State) -> &str {
+    BASE_BRANCH.get().map(String::as_str)
+        .or(state.default_branch.as_deref())
+        .unwrap_or("main")
+}
+
+// Branch the bottom PR of the stack targets
+fn base_branch(state: &State) -> &str {
+    state.base_pr.as_ref().map_or(trunk(state), |base| base.branch.as_str())
+}
+
+// Detect the remote default branch. When it was renamed since the last run,
+// point stored bases at the new name and retarget open PRs still based on the
+// old branch once it no longer exists.
+fn sync_default_branch(state: &mut State, repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    let info = forge::forge().rest("GET", &format!("repos/{}", repo), serde_json::Value::Null, verbose).unwrap_or_default();
+    let Some(current) = info["default_branch"].as_str().filter(|name| !name.is_empty()) else {
+        return Ok(()); // Couldn't tell; keep what we had
+    };
+
+    let previous = state.default_branch.as_deref().unwrap_or("main").to_string();
+    if previous != current && state.default_branch.is_some() {
+        eprintln!("Default branch renamed from {} to {}", previous, current);
+
+        for info in state.prs.values_mut() {
+            if info.base_branch.as_deref() == Some(previous.as_str()) {
+                info.base_branch = Some(current.to_string());
+            }
+        }
+        for base in state.base_overrides.values_mut() {
+            if *base == previous {
+                *base = current.to_string();
+            }
+        }
+
+        if remote_commit_on(&previous, pr_remote(), verbose)?.is_none() {
+            let open = list_pull_requests(repo, true, "number baseRefName", verbose).unwrap_or_default();
+            let retarget = open.iter().filter(|pr| pr["baseRefName"] == previous.as_str()).filter_map(|pr| pr["number"].as_u64());
+            for number in retarget {
+                let number = number as u32;
+                eprintln!("  {} PR #{} from {} to {}", if dry_run { "Would retarget" } else { "Retargeting" },
+                         number, previous, current);
+                forge_mutation(&["gh", "pr", "edit", &number.to_string(), "-R", repo, "--base", current], true, dry_run, verbose,
+                               |forge| forge.edit_pr(repo, number, &PrEdit { base: Some(current), ..PrEdit::default() }, verbose))?;
+                record_action(format!("Retargeted PR #{} from {} to {}", number, previous, current));
+            }
+        }
+    }
+
+    state.default_branch = Some(current.to_string());
+    Ok(())
+}
+
+// Revset the stack is computed above
+fn stack_base(state: &State) -> String {
+    format!("{}@{}", base_branch(state), pr_remote())
+}
+
+// Revset of commits that must never be pushed: jj's git.private-commits plus never_push
+fn private_revset(config: &Config, verbose: bool) -> Option<String> {
+    let jj_private = run_command(&["jj", "config", "get", "git.private-commits"], false, verbose).ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty());
+    let revsets: Vec<String> = jj_private.into_iter().chain(config.never_push.clone())
+        .map(|revset| format!("({})", revset))
+        .collect();
+    (!revsets.is_empty()).then(|| revsets.join(" | "))
+}
+
+// Drop private commits from the stack, along with every revision above the
+// lowest one: pushing those would push the private commit as their ancestor.
+// Returns the change IDs of the revisions held back that aren't private.
+fn withhold_private_commits(revisions: &mut Vec<Revision>, private: Option<&str>, verbose: bool) -> Result<Vec<String>> {
+    let (Some(private), Some(bottom), Some(top)) = (private, revisions.first(), revisions.last()) else { return Ok(Vec::new()) };
+    let revset = format!("({}::{}) & ({})", bottom.change_id, top.change_id, private);
+    let output = run_command(&["jj", "log", "-r", &revset, "--no-graph", "--template", r#"change_id ++ "\n""#], false, verbose)?;
+    let private_ids: HashSet<&str> = output.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+
+    let Some(lowest) = revisions.iter().position(|r| private_ids.contains(r.change_id.as_str())) else { return Ok(Vec::new()) };
+    let held = revisions.split_off(lowest);
+    let blocked: Vec<&Revision> = held.iter().filter(|r| !private_ids.contains(r.change_id.as_str())).collect();
+
+    if verbose {
+        eprintln!("  Not pushing {} private commit(s)", held.len() - blocked.len());
+    }
+    if !blocked.is_empty() {
+        let private_rev = &held[0];
+        eprintln!("⚠️  {} revision(s) above private commit \"{}\" ({}) aren't pushed, since that would publish it:",
+                 blocked.len(), private_rev.description, &private_rev.change_id[..8]);
+        for rev in &blocked {
+            eprintln!("  - {} ({})", rev.description, &rev.change_id[..8]);
+        }
+        eprintln!("   Move it to the top of the stack to push them: jj rebase -r {} -d {}",
+                 &private_rev.change_id[..8], &held[held.len() - 1].change_id[..8]);
+    }
+    Ok(blocked.iter().map(|r| r.change_id.clone()).collect())
+}
+
+fn get_stack_revisions(base: &str, verbose: bool) -> Result<Vec<Revision>> {
+    get_revisions(&format!("{}..@", base), verbose)
+}
+
+fn get_revisions(revset: &str, verbose: bool) -> Result<Vec<Revision>> {
+    let log = run_command(&["jj", "log", "-r", revset, "--no-graph", "--template", stack::LOG_TEMPLATE], false, verbose)?;
+    // Full descriptions can span lines, so they come from a second query
+    let descriptions = run_command(&[
+        "jj", "log", "-r", revset, "--no-graph", "--template", stack::DESCRIPTION_TEMPLATE
+    ], false, verbose)?;
+
+    let stack = Stack::from_jj_output(&log, &descriptions, verbose);
+    if stack.skipped() > 0 {
+        eprintln!("⚠️  Skipped {} commit(s) without descriptions", stack.skipped());
+    }
+    Ok(stack.into_revisions())
+}
+
+// Nudge toward reviewable PRs: warn about revisions that touch more areas or
+// change more lines than configured, with `jj split` commands to break them up
+fn warn_broad_revisions(revisions: &[Revision], config: &Config, verbose: bool) -> Result<()> {
+    for rev in revisions {
+        let short = &rev.change_id[..8.min(rev.change_id.len())];
+
+        if let Some(max) = config.max_areas_per_pr {
+            let touched: Vec<(&String, &Vec<String>)> = config.areas.iter()
+                .filter(|(_, globs)| paths::any_match(globs, &rev.changed_paths))
+                .collect();
+            if touched.len() > max {
+                eprintln!("⚠️  {} \"{}\" spans {} areas ({}; max_areas_per_pr is {}); consider splitting it:",
+                         short, rev.description, touched.len(),
+                         touched.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", "), max);
+                for (_, globs) in touched.iter().take(touched.len() - 1) {
+                    let fileset = globs.iter().map(|glob| format!("glob:\"{}\"", glob)).collect::<Vec<_>>().join(" | ");
+                    eprintln!("    jj split -r {} '{}'", short, fileset);
+                }
+            }
+        }
+
+        if let Some(max) = config.max_lines_per_pr {
+            let stat = run_command(&["jj", "diff", "--stat", "-r", &rev.commit_id], true, verbose)?;
+            let (total, mut files) = diff_stat_lines(&stat);
+            if total > max {
+                files.sort_by_key(|(_, lines)| std::cmp::Reverse(*lines));
+                eprintln!("⚠️  {} \"{}\" changes {} lines (max_lines_per_pr is {}); consider `jj split -r {}`. Largest files: {}",
+                         short, rev.description, total, max, short,
+                         files.iter().take(3).map(|(path, lines)| format!("{} ({})", path, lines)).collect::<Vec<_>>().join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Total changed lines and per-file counts from `jj diff --stat` output
+fn diff_stat_lines(stat: &str) -> (usize, Vec<(String, usize)>) {
+    let files: Vec<(String, usize)> = stat.lines()
+        .filter_map(|line| {
+            let (path, counts) = line.rsplit_once('|')?;
+            let lines = counts.split_whitespace().next()?.parse().ok()?;
+            Some((path.trim().to_string(), lines))
+        })
+        .collect();
+    (files.iter().map(|(_, lines)| lines).sum(), files)
+}
+
+// Fill in the files each revision changes, for path-based rules
+fn load_changed_paths(revisions: &mut [Revision], verbose: bool) -> Result<()> {
+    for rev in revisions {
+        let output = run_command(&["jj", "diff", "--name-only", "-r", &rev.commit_id], false, verbose)?;
+        rev.changed_paths = output.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect();
+    }
+    Ok(())
+}
+
+// Detect squashed commits by checking jj op log
+fn detect_squashed_commits(revisions: &mut [Revision], _state: &State, verbose: bool) -> Result<HashSet<String>> {
     let mut squashed = HashSet::new();
 
     // Check operation log for squash operations
@@ -375,17 +3359,10 @@ fn detect_squashed_commits(revisions: &mut [Revision], _state: &State, verbose:
         "--template", r#"description ++ "\n""#
     ], true, verbose)?;
 
-    for line in output.lines() {
-        if line.contains("squash") || line.contains("abandon") {
-            // Extract change IDs from operation description
-            for word in line.split_whitespace() {
-                if word.len() >= 8 && word.chars().all(|c| c.is_alphanumeric()) {
-                    // Check if this looks like a change ID that's not in current stack
-                    if !revisions.iter().any(|r| r.change_id.starts_with(word)) {
-                        squashed.insert(word.to_string());
-                    }
-                }
-            }
+    for word in stack::squashed_change_ids(&output) {
+        // Only change IDs that are no longer in the current stack
+        if !revisions.iter().any(|r| r.change_id.starts_with(word)) {
+            squashed.insert(word.to_string());
         }
     }
 
@@ -428,50 +3405,139 @@ fn migrate_state(state: &mut State) -> Result<()> {
     Ok(())
 }
 
-fn push_branches(revisions: &mut [Revision], dry_run: bool, verbose: bool) -> Result<()> {
+fn push_branches(revisions: &mut [Revision], prefix: &str, repo: &str, no_force: bool, dry_run: bool, verbose: bool) -> Result<()> {
     eprintln!("Pushing {} branches...", revisions.len());
     
     for rev in revisions {
-        let branch_name = format!("push-{}", &rev.change_id[..12.min(rev.change_id.len())]);
+        let own_branch = branch_name_for(prefix, &rev.change_id);
+        let branch_name = rev.branch_name.clone().unwrap_or_else(|| own_branch.clone());
         rev.branch_name = Some(branch_name.clone());
+
+        // A merged PR left in the stack (see out_of_order_merge) keeps its branch as merged
+        if rev.pr_state.as_deref() == Some("MERGED") {
+            continue;
+        }
+
+        // Check if we need to force push, and show what it would throw away
+        let needs_force = check_needs_force_push(&branch_name, &rev.commit_id, verbose)?;
+        if needs_force {
+            let discarded = discarded_remote_commits(&branch_name, &rev.commit_id, verbose)?;
+            eprintln!("⚠️  {}@{} has diverged; force-pushing replaces {} remote commit(s):",
+                     branch_name, remote(), discarded.len());
+            for commit in &discarded {
+                eprintln!("    {}", commit);
+            }
+            if no_force {
+                bail!(CodedError::new(ErrorCode::ForcePushRefused, format!(
+                    "Refusing to force-push {} (--no-force); fetch and reconcile the remote changes first", branch_name)));
+            }
+            // In a shared repository the branch may be another user's PR for the same change
+            if let Some((number, owner)) = other_owner(repo, &branch_name, verbose)? {
+                bail!(CodedError::new(ErrorCode::BranchOwnedByOther, format!(
+                    "{} is the branch of PR #{} by @{}; refusing to force-push over it", branch_name, number, owner)));
+            }
+        }
         
+        if prefix != jj_push_prefix(verbose) || branch_name != own_branch {
+            // `jj git push --change` only knows jj's own branch name for the change, so manage the bookmark ourselves
+            push_bookmark(&branch_name, &rev.change_id, dry_run, verbose)?;
+        } else if needs_force {
+            if verbose {
+                eprintln!("  Force pushing {} (remote has diverged)", branch_name);
+            }
+            // jj automatically force pushes when needed, no --force flag required
+            run_mutation(&["jj", "git", "push", "--remote", remote(), "-b", &branch_name], false, dry_run, verbose)?;
+        } else {
+            // Try to push normally
+            let output = run_mutation(&["jj", "git", "push", "--remote", remote(), "--change", &rev.change_id], true, dry_run, verbose)?;
+            if !dry_run && !stack::push_updated_bookmark(&output) {
+                // Try pushing by branch if change push failed
+                run_command(&["jj", "git", "push", "--remote", remote(), "-b", &branch_name], true, verbose)?;
+            }
+        }
+
         if !dry_run {
-            // Check if we need to force push
-            let needs_force = check_needs_force_push(&branch_name, &rev.commit_id, verbose)?;
+            record_action(format!("Pushed {} ({})", branch_name, &rev.commit_id[..12.min(rev.commit_id.len())]));
+        }
+    }
+    
+    Ok(())
+}
 
-            if needs_force {
-                if verbose {
-                    eprintln!("  Force pushing {} (remote has diverged)", branch_name);
-                }
-                // jj automatically force pushes when needed, no --force flag required
-                run_command(&["jj", "git", "push", "-b", &branch_name], false, verbose)?;
-            } else {
-                // Try to push normally
-                let output = run_command(&["jj", "git", "push", "--change", &rev.change_id], true, verbose)?;
-                if !output.contains("Creating") && !output.contains("Moving") {
-                    // Try pushing by branch if change push failed
-                    run_command(&["jj", "git", "push", "-b", &branch_name], true, verbose)?;
-                }
+// Check that every remote branch now points at its revision's commit rather
+// than trusting the push output; branches that don't are re-pushed before giving up
+fn verify_pushed_branches(revisions: &[Revision], verbose: bool) -> Result<()> {
+    const ATTEMPTS: usize = 3;
+
+    for rev in revisions.iter().filter(|r| r.pr_state.as_deref() != Some("MERGED")) {
+        let Some(branch_name) = &rev.branch_name else { continue };
+
+        for attempt in 1..=ATTEMPTS {
+            let pushed = remote_commit(branch_name, verbose)?;
+            if pushed.as_deref() == Some(rev.commit_id.as_str()) {
+                break;
+            }
+
+            let found = pushed.as_deref().map_or("nothing".to_string(), |c| c[..12.min(c.len())].to_string());
+            if attempt == ATTEMPTS {
+                bail!(CodedError::new(ErrorCode::PushNotVerified, format!(
+                    "{}@{} points at {} instead of {} after {} attempts to push it",
+                    branch_name, remote(), found, &rev.commit_id[..12.min(rev.commit_id.len())], ATTEMPTS)));
             }
+            eprintln!("⚠️  {}@{} points at {}, expected {}; pushing again",
+                     branch_name, remote(), found, &rev.commit_id[..12.min(rev.commit_id.len())]);
+            push_bookmark(branch_name, &rev.change_id, false, verbose)?;
         }
     }
-    
+
     Ok(())
 }
 
-// Check if force push is needed
-fn check_needs_force_push(branch_name: &str, local_commit: &str, verbose: bool) -> Result<bool> {
-    // Check if branch exists on remote
+// Commit a remote branch points at, as last seen by jj
+fn remote_commit(branch_name: &str, verbose: bool) -> Result<Option<String>> {
+    remote_commit_on(branch_name, remote(), verbose)
+}
+
+fn remote_commit_on(branch_name: &str, remote: &str, verbose: bool) -> Result<Option<String>> {
     let output = run_command(&[
-        "jj", "log", "-r", &format!("{}@origin", branch_name),
+        "jj", "log", "-r", &format!("{}@{}", branch_name, remote),
         "--no-graph", "--template", "commit_id", "--limit", "1"
     ], true, verbose)?;
+    let commit = output.trim();
+    if commit.is_empty() || commit.contains("Error:") || commit.contains("doesn't exist") {
+        return Ok(None);
+    }
+    Ok(Some(commit.to_string()))
+}
+
+// Point a bookmark at a change and push it, creating the remote branch if needed
+fn push_bookmark(branch_name: &str, change_id: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    run_mutation(&["jj", "bookmark", "set", branch_name, "-r", change_id, "--allow-backwards"], false, dry_run, verbose)?;
+    run_mutation(&["jj", "git", "push", "--remote", remote(), "-b", branch_name, "--allow-new"], false, dry_run, verbose)?;
+    Ok(())
+}
+
+// Commits on the remote branch that aren't in the local commit's ancestry,
+// i.e. what a force push would drop ("<short id> <first line>")
+fn discarded_remote_commits(branch_name: &str, local_commit: &str, verbose: bool) -> Result<Vec<String>> {
+    let output = run_command(&[
+        "jj", "log", "-r", &format!("{}..{}@{}", local_commit, branch_name, remote()), "--no-graph",
+        "--template", r#"commit_id.short() ++ " " ++ if(description, description.first_line(), "(no description)") ++ "\n""#
+    ], true, verbose)?;
 
-    if output.trim().is_empty() || output.contains("doesn't exist") || output.contains("Error:") {
-        return Ok(false); // New branch or doesn't exist on remote
+    if output.contains("Error:") {
+        return Ok(Vec::new());
     }
+    Ok(output.lines().filter(|l| !l.trim().is_empty()).map(|l| l.to_string()).collect())
+}
+
+// Check if force push is needed
+fn check_needs_force_push(branch_name: &str, local_commit: &str, verbose: bool) -> Result<bool> {
+    // Check if branch exists on remote
+    let Some(remote_commit) = remote_commit(branch_name, verbose)? else {
+        return Ok(false); // New branch or doesn't exist on remote
+    };
 
-    let remote_commit = output.trim();
     if remote_commit == local_commit {
         return Ok(false); // Same commit
     }
@@ -486,46 +3552,68 @@ fn check_needs_force_push(branch_name: &str, local_commit: &str, verbose: bool)
     Ok(output.trim().is_empty() || output.contains("Error:"))
 }
 
-fn create_or_update_prs(revisions: &mut [Revision], state: &State, repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
+fn create_or_update_prs(revisions: &mut [Revision], state: &mut State, repo: &str, config: &Config, force_bases: bool, dry_run: bool, verbose: bool) -> Result<Vec<String>> {
     eprintln!("Managing pull requests...");
 
     // Get existing PRs
-    let existing_prs = get_existing_prs(repo, verbose)?;
+    let existing_prs = get_existing_prs(repo, &stack_branches(revisions, config), verbose)?;
+    let mut new_prs = Vec::new();
+    let mut retargets = Vec::new();
+    let mut open_prs = 0;
 
     // First pass: determine base branches
+    let trunk_name = trunk(state).to_string();
     let mut base_branches = Vec::new();
+    // Merged PRs left in the stack aren't stacked on, unless out_of_order_merge only warns
+    let skip_merged = config.out_of_order_merge != OutOfOrderMerge::Warn;
     for i in 0..revisions.len() {
-        let base = if i == 0 {
-            "main".to_string()
-        } else {
-            // Check if the previous revision was merged into another PR branch
-            // This handles the case where PRs are merged into each other rather than main
-            let prev_change_id = &revisions[i-1].change_id;
-            if let Some(merged_into_branch) = state.merged_into_pr.iter()
-                .find(|(id, _)| id.starts_with(prev_change_id) || prev_change_id.starts_with(id.as_str()))
-                .map(|(_, branch)| branch.clone()) {
-                // The previous PR was merged into another branch, use that as the base
-                merged_into_branch
-            } else if revisions[i].parent_change_ids.len() > 1 {
-                // Handle merge commits with multiple parents
-                let primary_parent = &revisions[i].parent_change_ids[0];
-                if let Some(parent_rev) = revisions.iter().find(|r| r.change_id == *primary_parent) {
-                    parent_rev.branch_name.clone().unwrap_or_else(|| "main".to_string())
+        let below = (0..i).rev()
+            .find(|&j| !(skip_merged && revisions[j].pr_state.as_deref() == Some("MERGED")));
+        let base = match below {
+            None => base_branch(state).to_string(),
+            // Merged revisions in between are skipped
+            Some(j) if j + 1 < i => revisions[j].branch_name.clone().unwrap_or_else(|| base_branch(state).to_string()),
+            Some(_) => {
+                // Check if the previous revision was merged into another PR branch
+                // This handles the case where PRs are merged into each other rather than main
+                let prev_change_id = &revisions[i-1].change_id;
+                if let Some(merged_into_branch) = state.merged_into_pr.iter()
+                    .find(|(id, _)| id.starts_with(prev_change_id) || prev_change_id.starts_with(id.as_str()))
+                    .map(|(_, branch)| branch.clone()) {
+                    // The previous PR was merged into another branch, use that as the base
+                    merged_into_branch
+                } else if revisions[i].parent_change_ids.len() > 1 {
+                    // Handle merge commits with multiple parents
+                    let primary_parent = &revisions[i].parent_change_ids[0];
+                    if let Some(parent_rev) = revisions.iter().find(|r| r.change_id == *primary_parent) {
+                        parent_rev.branch_name.clone().unwrap_or_else(|| trunk_name.clone())
+                    } else {
+                        revisions[i-1].branch_name.as_ref().unwrap().clone()
+                    }
                 } else {
                     revisions[i-1].branch_name.as_ref().unwrap().clone()
                 }
-            } else {
-                revisions[i-1].branch_name.as_ref().unwrap().clone()
             }
         };
         base_branches.push(base);
     }
+    // A PR can only use a branch of its own repository as its base, so PRs
+    // from a fork all target the base branch and show the stack in their body
+    if FORK_OWNER.get().is_some() {
+        base_branches.fill(base_branch(state).to_string());
+    }
 
     // Collect PR info from previous revisions to avoid borrow conflicts
     let prev_pr_info: Vec<(Option<u32>, Option<String>)> = revisions.iter()
         .map(|r| (r.pr_number, r.pr_state.clone()))
         .collect();
 
+    let pr_regex = regex::Regex::new(r"\(#(\d+)\)").unwrap();
+
+    // PRs below each revision and those named in descriptions, looked up together
+    let prs = PrCache::fetch(repo, prev_pr_info.iter().filter_map(|(number, _)| *number)
+        .chain(revisions.iter().filter_map(|rev| pr_regex.captures(&rev.description)?[1].parse().ok())), PR_CACHE_FIELDS, verbose);
+
     // Second pass: create/update PRs
     for (i, rev) in revisions.iter_mut().enumerate() {
         let branch_name = rev.branch_name.as_ref().context("No branch name")?;
@@ -534,7 +3622,6 @@ fn create_or_update_prs(revisions: &mut [Revision], state: &State, repo: &str, d
         // Check if this commit represents a PR that was merged into another PR
         // This happens when PRs are merged into each other rather than main
         // The merged commit will have the PR number in its description (e.g., "second (#31)")
-        let pr_regex = regex::Regex::new(r"\(#(\d+)\)").unwrap();
         let mut skip_pr_creation = false;
 
         // First check if this is the HEAD of an existing PR
@@ -543,17 +3630,12 @@ fn create_or_update_prs(revisions: &mut [Revision], state: &State, repo: &str, d
             // Check if the previous revision has a PR and if this commit is now its HEAD
             if let Some(prev_pr_num) = prev_pr_info[i-1].0 {
                 // Check if this commit is the current HEAD of that PR's branch
-                let pr_head_output = run_command(&[
-                    "gh", "pr", "view", &prev_pr_num.to_string(),
-                    "-R", repo,
-                    "--json", "headRefName", "-q", ".headRefName"
-                ], true, verbose)?;
-
-                let pr_branch = pr_head_output.trim();
+                let prev_pr = prs.get(prev_pr_num, &["headRefName"], verbose);
+                let pr_branch = prev_pr["headRefName"].as_str().unwrap_or_default();
                 if !pr_branch.is_empty() {
                     // Check if this commit is the HEAD of that branch
                     let branch_head = run_command(&[
-                        "jj", "log", "-r", &format!("{}@origin", pr_branch),
+                        "jj", "log", "-r", &format!("{}@{}", pr_branch, remote()),
                         "--no-graph", "--template", "change_id", "--limit", "1"
                     ], true, verbose)?;
 
@@ -577,13 +3659,9 @@ fn create_or_update_prs(revisions: &mut [Revision], state: &State, repo: &str, d
                 if let Some(pr_num_str) = captures.get(1) {
                     if let Ok(pr_num) = pr_num_str.as_str().parse::<u32>() {
                         // Check if this PR was merged
-                        let pr_status = run_command(&[
-                            "gh", "pr", "view", &pr_num.to_string(),
-                            "-R", repo,
-                            "--json", "state,mergedAt", "-q", ".state"
-                        ], true, verbose)?;
+                        let pr_status = prs.get(pr_num, &["state"], verbose);
 
-                        if pr_status.trim() == "MERGED" {
+                        if pr_status["state"] == "MERGED" {
                             skip_pr_creation = true;
                             rev.pr_number = Some(pr_num);
                             rev.pr_state = Some("MERGED".to_string());
@@ -597,176 +3675,867 @@ fn create_or_update_prs(revisions: &mut [Revision], state: &State, repo: &str, d
             }
         }
 
-        if skip_pr_creation {
+        if skip_pr_creation {
+            continue;
+        }
+
+        // Check if PR exists by branch name
+        if let Some(pr) = existing_prs.get(branch_name) {
+            rev.pr_number = Some(pr.0);
+            rev.pr_url = Some(pr.1.clone());
+            rev.pr_state = Some(pr.2.clone());
+            rev.base_branch = Some(base_branch.clone());
+            if pr.2 == "OPEN" {
+                open_prs += 1;
+            }
+
+            // A base that differs from the one we last applied was changed by hand on GitHub
+            let last_applied = state.prs.iter()
+                .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
+                .and_then(|(_, info)| info.base_branch.clone());
+            let overridden = last_applied.as_ref().is_some_and(|b| b != &pr.3);
+
+            // Update base if needed and PR is open
+            if pr.2 == "OPEN" && &pr.3 != base_branch && overridden && !force_bases {
+                eprintln!("  Keeping manually set base {} for PR #{} (--force-bases resets it to {})",
+                         pr.3, pr.0, base_branch);
+                state.base_overrides.insert(rev.change_id.clone(), pr.3.clone());
+                rev.base_branch = last_applied;
+            } else {
+                state.base_overrides.remove(&rev.change_id);
+                if pr.2 == "OPEN" && &pr.3 != base_branch {
+                    if verbose || dry_run {
+                        eprintln!("  {} PR #{} base from {} to {}",
+                                 if dry_run { "Would update" } else { "Updating" }, pr.0, pr.3, base_branch);
+                    }
+                    retargets.push((pr.0, pr.3.clone(), base_branch.clone()));
+                }
+            }
+        }
+        // Also check if we have a PR for this change ID in state (might have different branch name)
+        else if let Some(existing_pr) = state.prs.iter()
+            .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
+            .map(|(_, info)| info) {
+
+            // PR exists in state but not found by branch name - might have been renamed
+            rev.pr_number = Some(existing_pr.pr_number);
+            rev.pr_url = Some(existing_pr.pr_url.clone());
+
+            if verbose {
+                eprintln!("  Found existing PR #{} for change {}", existing_pr.pr_number, &rev.change_id[..8]);
+            }
+        } else if config.max_open_prs.is_some_and(|max| open_prs >= max) {
+            // Keep the branch pushed; its PR opens once PRs below it merge
+            eprintln!("  Queued {} (max_open_prs reached)", &rev.change_id[..8]);
+        } else {
+            open_prs += 1;
+            // Create new PR
+            let title = &rev.description;
+
+            // Build PR body with merge commit info if applicable. With
+            // stack_comment this body is the only one almighty-push writes.
+            let mut body = if config.stack_comment {
+                format!("{}\n\n", author_pr_body(rev))
+            } else {
+                format!("Change ID: {}\n\n", rev.change_id)
+            };
+
+            if rev.parent_change_ids.len() > 1 {
+                body.push_str("**Note**: This is a merge commit with multiple parents:\n");
+                for (idx, parent_id) in rev.parent_change_ids.iter().enumerate() {
+                    if idx == 0 {
+                        body.push_str(&format!("- Primary: `{}`\n", &parent_id[..12.min(parent_id.len())]));
+                    } else {
+                        body.push_str(&format!("- Additional: `{}`\n", &parent_id[..12.min(parent_id.len())]));
+                    }
+                }
+                body.push('\n');
+            }
+
+            new_prs.push(NewPr {
+                index: i,
+                branch: branch_name.clone(),
+                head: pr_head(branch_name),
+                base: base_branch.clone(),
+                title: title.clone(),
+                body: template::limit_body(&body),
+            });
+        }
+    }
+
+    // Base edits don't depend on each other; a dry run keeps its plan in order
+    let limit = if dry_run { 1 } else { config.max_concurrency };
+    let retargeted = map_concurrently(&retargets, limit, |(number, _, base)| {
+        forge_mutation(&["gh", "pr", "edit", &number.to_string(), "-R", repo, "--base", base], true, dry_run, verbose,
+                       |forge| forge.edit_pr(repo, *number, &PrEdit { base: Some(base), ..PrEdit::default() }, verbose))
+    });
+    for (result, (number, from, to)) in retargeted.into_iter().zip(&retargets) {
+        result?;
+        record_action(format!("Retargeted PR #{} from {} to {}", number, from, to));
+    }
+
+    if dry_run {
+        for pr in &new_prs {
+            eprintln!("  Would create PR for {} (base {})", pr.branch, pr.base);
+            run_mutation(&create_pr_args(pr, repo), false, dry_run, verbose)?;
+        }
+        return Ok(Vec::new());
+    }
+
+    // Every base branch was pushed before this point, so no creation depends on
+    // another and they can all run concurrently
+    let created = create_prs_concurrently(&new_prs, repo, config.max_concurrency, verbose)?;
+    let mut created_urls = Vec::new();
+    for (new_pr, url) in new_prs.iter().zip(created) {
+        let rev = &mut revisions[new_pr.index];
+        rev.base_branch = Some(new_pr.base.clone());
+        rev.pr_number = forge::pr_number_from_url(&url);
+        rev.pr_url = Some(url.clone());
+        created_urls.push(url);
+        if let Some(pr_number) = rev.pr_number {
+            state.pr_levels.insert(pr_number, new_pr.index);
+            record_action(format!("Created PR #{} for {}", pr_number, new_pr.branch));
+            if verbose {
+                eprintln!("  Created PR #{} for {}", pr_number, new_pr.branch);
+            }
+        }
+    }
+
+    Ok(created_urls)
+}
+
+struct NewPr {
+    index: usize,
+    branch: String,
+    head: String,
+    base: String,
+    title: String,
+    body: String,
+}
+
+fn create_pr_args<'a>(pr: &'a NewPr, repo: &'a str) -> [&'a str; 13] {
+    [
+        "gh", "pr", "create",
+        "-R", repo,
+        "--head", &pr.head,
+        "--base", &pr.base,
+        "--title", &pr.title,
+        "--body", &pr.body,
+    ]
+}
+
+impl NewPr {
+    fn request(&self) -> NewPullRequest<'_> {
+        NewPullRequest { head: &self.head, base: &self.base, title: &self.title, body: &self.body }
+    }
+}
+
+// Create PRs with at most `limit` requests in flight, returning the URL of
+// each in the order given
+fn create_prs_concurrently(new_prs: &[NewPr], repo: &str, limit: usize, verbose: bool) -> Result<Vec<String>> {
+    map_concurrently(new_prs, limit, |pr| forge::create_pr(forge::forge(), repo, &pr.request(), verbose))
+        .into_iter()
+        .collect()
+}
+
+// Run `task` on every item on at most `limit` threads, returning the results
+// in the order of `items`. Each thread takes the next item as soon as it is
+// done with one, so a slow request doesn't hold up a whole batch.
+fn map_concurrently<T: Sync, R: Send>(items: &[T], limit: usize, task: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..limit.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                let result = task(item);
+                *results[i].lock().unwrap() = Some(result);
+            });
+        }
+    });
+    // A panicking thread fails the scope above, so every item has a result here
+    results.into_iter().map(|result| result.into_inner().unwrap().unwrap()).collect()
+}
+
+// Detect and fix PR dependency cycles
+fn detect_and_fix_cycles(revisions: &[Revision], repo: &str, trunk: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    let mut dependencies = HashMap::new();
+    for (i, rev) in revisions.iter().enumerate() {
+        if let Some(pr_num) = rev.pr_number {
+            if i > 0 {
+                if let Some(prev_pr) = revisions[i-1].pr_number {
+                    dependencies.insert(pr_num, prev_pr);
+                }
+            }
+        }
+    }
+
+    // Simple cycle detection using visited set
+    for &start in dependencies.keys() {
+        let mut visited = HashSet::new();
+        let mut current = start;
+
+        while let Some(&next) = dependencies.get(&current) {
+            if !visited.insert(current) {
+                // Cycle detected
+                if verbose {
+                    eprintln!("  Cycle detected involving PR #{}", current);
+                }
+                // Break cycle by updating base to the default branch
+                forge_mutation(&["gh", "pr", "edit", &current.to_string(), "-R", repo, "--base", trunk], true, dry_run, verbose,
+                               |forge| forge.edit_pr(repo, current, &PrEdit { base: Some(trunk), ..PrEdit::default() }, verbose))?;
+                break;
+            }
+            current = next;
+        }
+    }
+
+    Ok(())
+}
+
+fn update_pr_descriptions(revisions: &[Revision], repo: &str, config: &Config, dry_run: bool, verbose: bool) -> Result<()> {
+    eprintln!("Updating PR descriptions...");
+
+    // Merged and closed PRs are left alone
+    let open: Vec<(usize, u32)> = revisions.iter().enumerate()
+        .filter(|(_, rev)| rev.pr_state.as_deref().is_none_or(|state| state == "OPEN"))
+        .filter_map(|(i, rev)| Some((i, rev.pr_number?)))
+        .collect();
+    let current_bodies = PrCache::fetch(repo, open.iter().map(|&(_, number)| number), &["body"], verbose);
+    // Each PR's body is updated independently; a dry run prints its diffs in order
+    let limit = if dry_run { 1 } else { config.max_concurrency };
+    let results = map_concurrently(&open, limit, |&(i, pr_number)| -> Result<()> {
+        // Without the current body, text written on GitHub couldn't be kept
+        let Some(current) = current_bodies.get(pr_number, &["body"], verbose)["body"].as_str().map(str::to_string) else {
+            eprintln!("⚠️  Could not read the body of PR #{}, leaving it as it is", pr_number);
+            return Ok(());
+        };
+        let mut body = stack_pr_body(revisions, i, repo, config);
+        if !config.checklists.is_empty() {
+            body = checklist::keep_checked(&body, current.trim_end());
+        }
+        // Only the managed block is ours; bodies from before the markers are replaced whole
+        let body = template::limit_body(&template::replace_managed(&current, &body).unwrap_or(body));
+        if body.trim_end() == current.trim_end() {
+            if dry_run {
+                eprintln!("PR #{} body unchanged", pr_number);
+            }
+            return Ok(());
+        }
+
+        if dry_run {
+            eprintln!("Would update PR #{} body:\n{}", pr_number, line_diff(current.trim_end(), body.trim_end()));
+        }
+        forge_mutation(&["gh", "pr", "edit", &pr_number.to_string(), "-R", repo, "--body", &body], true, dry_run, verbose,
+                       |forge| forge.edit_pr(repo, pr_number, &PrEdit { body: Some(&body), ..PrEdit::default() }, verbose))
+    });
+    results.into_iter().collect()
+}
+
+// With stack_comment, keep the stack overview in one comment per open PR,
+// found again by its marker, instead of in the PR body
+fn update_stack_comments(revisions: &[Revision], repo: &str, config: &Config, dry_run: bool, verbose: bool) -> Result<()> {
+    eprintln!("Updating stack comments...");
+
+    let open: Vec<(usize, u32)> = revisions.iter().enumerate()
+        .filter(|(_, rev)| rev.pr_state.as_deref().is_none_or(|state| state == "OPEN"))
+        .filter_map(|(i, rev)| Some((i, rev.pr_number?)))
+        .collect();
+    let limit = if dry_run { 1 } else { config.max_concurrency };
+    let results = map_concurrently(&open, limit, |&(i, pr_number)| -> Result<()> {
+        let body = template::limit_body(&format!("{}\n{}", template::STACK_COMMENT_MARKER, stack_section(revisions, i, repo, config)));
+        // The comment is posted by the first run, so it is on the first page
+        let comments = forge::forge().rest("GET", &format!("repos/{}/issues/{}/comments?per_page=100", repo, pr_number),
+                                           serde_json::Value::Null, verbose)?;
+        let existing = comments.as_array().into_iter().flatten()
+            .find(|comment| comment["body"].as_str().is_some_and(|text| text.starts_with(template::STACK_COMMENT_MARKER)));
+
+        match existing {
+            Some(comment) if comment["body"].as_str().map(str::trim_end) == Some(body.trim_end()) => {
+                if dry_run {
+                    eprintln!("PR #{} stack comment unchanged", pr_number);
+                }
+                Ok(())
+            }
+            Some(comment) => {
+                let Some(id) = comment["id"].as_u64() else { return Ok(()) };
+                if dry_run {
+                    let current = comment["body"].as_str().unwrap_or_default();
+                    eprintln!("Would update PR #{} stack comment:\n{}", pr_number, line_diff(current.trim_end(), body.trim_end()));
+                }
+                let endpoint = format!("repos/{}/issues/comments/{}", repo, id);
+                forge_mutation(&["gh", "api", "-X", "PATCH", &endpoint, "-f", &format!("body={}", body)], true, dry_run, verbose,
+                               |forge| forge.rest("PATCH", &endpoint, serde_json::json!({ "body": body }), verbose).map(drop))
+            }
+            None => forge_mutation(&["gh", "pr", "comment", &pr_number.to_string(), "-R", repo, "--body", &body], true, dry_run, verbose,
+                                   |forge| forge.comment_pr(repo, pr_number, &body, verbose)),
+        }
+    });
+    results.into_iter().collect()
+}
+
+// Look up the combined CI check state of every open PR's head commit in one
+// batched query. Check status is informational, so a failed lookup leaves it unknown.
+fn fetch_check_status(revisions: &mut [Revision], repo: &str, verbose: bool) {
+    let numbers: Vec<u32> = revisions.iter()
+        .filter(|rev| rev.pr_state.as_deref() == Some("OPEN"))
+        .filter_map(|rev| rev.pr_number)
+        .collect();
+    if numbers.is_empty() {
+        return;
+    }
+    let prs = match fetch_prs(repo, &numbers, &[CHECKS_FIELD], verbose) {
+        Ok(prs) => prs,
+        Err(e) => {
+            if verbose {
+                eprintln!("[debug] Could not fetch check status: {:#}", e);
+            }
+            return;
+        }
+    };
+    for rev in revisions.iter_mut() {
+        let Some(pr) = rev.pr_number.and_then(|number| prs.get(&number)) else { continue };
+        rev.pr_checks = checks_state(pr);
+    }
+}
+
+// GraphQL selection of the combined check state of a PR's head commit, for fetch_prs
+const CHECKS_FIELD: &str = "commits(last: 1) { nodes { commit { statusCheckRollup { state } } } }";
+
+// The check state in a PR fetched with CHECKS_FIELD; None when the commit has no checks
+fn checks_state(pr: &serde_json::Value) -> Option<String> {
+    pr["commits"]["nodes"][0]["commit"]["statusCheckRollup"]["state"].as_str().map(str::to_string)
+}
+
+// Ask GitHub whether each open PR merges cleanly into its base, so conflicts
+// introduced upstream show up before a reviewer finds the merge button greyed out.
+// Freshly pushed PRs report UNKNOWN until GitHub has checked; those are polled again.
+// Returns the revisions whose PR is behind a base that must be merged up to date.
+fn check_mergeability(revisions: &mut [Revision], repo: &str, verbose: bool) -> Result<Vec<usize>> {
+    let mut behind = Vec::new();
+    for poll in 0..MERGEABLE_POLLS {
+        let mut pending = false;
+        let unchecked = |rev: &Revision| rev.pr_state.as_deref() == Some("OPEN")
+            && rev.pr_mergeable.as_deref().is_none_or(|m| m == "UNKNOWN");
+        let prs = PrCache::fetch(repo, revisions.iter().filter(|rev| unchecked(rev)).filter_map(|rev| rev.pr_number), PR_CACHE_FIELDS, verbose);
+        for (i, rev) in revisions.iter_mut().enumerate() {
+            let Some(pr_number) = rev.pr_number else { continue };
+            if !unchecked(rev) {
+                continue;
+            }
+
+            let json = prs.get(pr_number, &["mergeable", "mergeStateStatus"], verbose);
+            let Some(mergeable) = json["mergeable"].as_str() else { continue };
+            pending |= mergeable == "UNKNOWN";
+            rev.pr_mergeable = Some(mergeable.to_string());
+            // BEHIND is only reported when branch protection requires up-to-date branches
+            if json["mergeStateStatus"].as_str() == Some("BEHIND") {
+                behind.push(i);
+            }
+        }
+
+        if !pending || poll + 1 == MERGEABLE_POLLS {
+            break;
+        }
+        if verbose {
+            eprintln!("  Waiting for GitHub to check mergeability...");
+        }
+        std::thread::sleep(MERGEABLE_POLL_INTERVAL);
+    }
+    behind.sort_unstable();
+    Ok(behind)
+}
+
+// Bring PRs that are behind their base up to date, per update_branch. The API
+// merges the base into the PR branch on GitHub; the next run replaces that merge
+// with the rebased revision. A local rebase that would introduce conflicts is undone.
+fn update_behind_prs(behind: &[usize], revisions: &mut [Revision], how: UpdateBranch, repo: &str, config: &Config, dry_run: bool, verbose: bool) -> Result<()> {
+    match how {
+        UpdateBranch::Api => {
+            for &i in behind {
+                let rev = &revisions[i];
+                let Some(pr_number) = rev.pr_number else { continue };
+                eprintln!("{} PR #{} with {}", if dry_run { "Would update" } else { "Updating" },
+                         pr_number, rev.base_branch.as_deref().unwrap_or_default());
+                let endpoint = format!("repos/{}/pulls/{}/update-branch", repo, pr_number);
+                let plan = ["gh", "api", "-X", "PUT", &endpoint, "-f", &format!("expected_head_sha={}", rev.commit_id)];
+                if let Err(e) = forge_mutation(&plan, false, dry_run, verbose, |forge| {
+                    forge.rest("PUT", &endpoint, serde_json::json!({ "expected_head_sha": rev.commit_id }), verbose).map(drop)
+                }) {
+                    eprintln!("⚠️  Could not update PR #{}: {}", pr_number, e);
+                } else if !dry_run {
+                    record_action(format!("Updated PR #{} with its base", pr_number));
+                }
+            }
+        }
+        UpdateBranch::Rebase => {
+            // Rebasing the lowest PR that is behind carries everything above it along
+            let Some(&lowest) = behind.first() else { return Ok(()) };
+            let rev = &revisions[lowest];
+            let Some(base) = rev.base_branch.clone() else { return Ok(()) };
+            let destination = format!("{}@{}", base, pr_remote());
+            eprintln!("{} {} and the revisions above it onto {} (PR #{} is behind its base)",
+                     if dry_run { "Would rebase" } else { "Rebasing" }, &rev.change_id[..8],
+                     destination, rev.pr_number.unwrap_or_default());
+            run_mutation(&["jj", "rebase", "-s", &rev.change_id, "-d", &destination], false, dry_run, verbose)?;
+            if dry_run {
+                return Ok(());
+            }
+
+            let rebased = get_revisions(&format!("{}::", rev.change_id), verbose)?;
+            if rebased.iter().any(|r| r.has_conflicts) {
+                run_command(&["jj", "undo"], false, verbose)?;
+                eprintln!("⚠️  Rebasing onto {} causes conflicts; undid it. Rebase and resolve them by hand", destination);
+                return Ok(());
+            }
+            for rev in revisions[lowest..].iter_mut() {
+                if let Some(new) = rebased.iter().find(|r| r.change_id == rev.change_id) {
+                    rev.commit_id = new.commit_id.clone();
+                }
+            }
+            record_action(format!("Rebased {} onto {}", &revisions[lowest].change_id[..8], destination));
+            push_branches(&mut revisions[lowest..], &config.branch_prefix, repo, false, false, verbose)?;
+            verify_pushed_branches(&revisions[lowest..], verbose)?;
+        }
+    }
+    Ok(())
+}
+
+// Label open PRs with the labels config, --label, the revision's `Labels:`
+// trailer and the path_labels rules it matches. Labels added by an earlier run
+// that no longer apply are removed again; labels set by hand are never touched.
+fn apply_labels(revisions: &[Revision], state: &mut State, repo: &str, config: &Config, dry_run: bool, verbose: bool) -> Result<()> {
+    for rev in revisions {
+        let Some(pr_number) = rev.pr_number else { continue };
+        if rev.pr_state.as_deref().is_some_and(|s| s != "OPEN") {
             continue;
         }
 
-        // Check if PR exists by branch name
-        if let Some(pr) = existing_prs.get(branch_name) {
-            rev.pr_number = Some(pr.0);
-            rev.pr_url = Some(pr.1.clone());
-            rev.pr_state = Some(pr.2.clone());
+        let wanted: BTreeSet<String> = config.labels.iter().cloned()
+            .chain(description::list_trailer(&rev.body, "Labels"))
+            .chain(config.path_labels.iter()
+                .filter(|rule| paths::any_match(&rule.paths, &rev.changed_paths))
+                .flat_map(|rule| rule.labels.iter().cloned()))
+            .collect();
+        let applied = state.labels.get(&pr_number).cloned().unwrap_or_default();
+        let add: Vec<&str> = wanted.difference(&applied).map(String::as_str).collect();
+        let remove: Vec<&str> = applied.difference(&wanted).map(String::as_str).collect();
+        if add.is_empty() && remove.is_empty() {
+            continue;
+        }
 
-            // Update base if needed and PR is open
-            if pr.2 == "OPEN" && &pr.3 != base_branch && !dry_run {
-                if verbose {
-                    eprintln!("  Updating PR #{} base from {} to {}", pr.0, pr.3, base_branch);
-                }
-                run_command(&["gh", "pr", "edit", &pr.0.to_string(), "-R", repo, "--base", base_branch], true, verbose)?;
+        let number = pr_number.to_string();
+        let mut args = vec!["gh", "pr", "edit", &number, "-R", repo];
+        let (add_list, remove_list) = (add.join(","), remove.join(","));
+        if !add.is_empty() {
+            args.extend(["--add-label", add_list.as_str()]);
+        }
+        if !remove.is_empty() {
+            args.extend(["--remove-label", remove_list.as_str()]);
+        }
+        if let Err(e) = forge_mutation(&args, false, dry_run, verbose, |forge| edit_labels(forge, repo, pr_number, &add, &remove, verbose)) {
+            eprintln!("⚠️  Could not update labels of PR #{} (do the labels exist?): {}", pr_number, e);
+            continue;
+        }
+
+        if !dry_run {
+            if !add.is_empty() {
+                record_action(format!("Labeled PR #{} {}", pr_number, add_list));
+            }
+            if !remove.is_empty() {
+                record_action(format!("Removed label {} from PR #{}", remove_list, pr_number));
             }
         }
-        // Also check if we have a PR for this change ID in state (might have different branch name)
-        else if let Some(existing_pr) = state.prs.iter()
-            .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
-            .map(|(_, info)| info) {
+        if verbose {
+            eprintln!("  PR #{}: labels +[{}] -[{}]", pr_number, add_list, remove_list);
+        }
+        if wanted.is_empty() {
+            state.labels.remove(&pr_number);
+        } else {
+            state.labels.insert(pr_number, wanted);
+        }
+    }
+    Ok(())
+}
 
-            // PR exists in state but not found by branch name - might have been renamed
-            rev.pr_number = Some(existing_pr.pr_number);
-            rev.pr_url = Some(existing_pr.pr_url.clone());
+// Keep `label` on open PRs based on another PR's branch so nobody merges them
+// before their parent, and take it off once they target trunk. Only labels
+// added by us are removed.
+fn update_blocked_labels(revisions: &[Revision], state: &mut State, repo: &str, label: &str, trunk: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    for rev in revisions {
+        let Some(pr_number) = rev.pr_number else { continue };
+        if rev.pr_state.as_deref().is_some_and(|s| s != "OPEN") {
+            continue;
+        }
+
+        let blocked = rev.base_branch.as_deref().is_some_and(|base| base != trunk);
+        let labeled = state.blocked_labeled.contains(&pr_number);
+        if blocked == labeled {
+            continue;
+        }
 
+        let number = pr_number.to_string();
+        let flag = if blocked { "--add-label" } else { "--remove-label" };
+        let (add, remove): (&[&str], &[&str]) = if blocked { (&[label], &[]) } else { (&[], &[label]) };
+        if let Err(e) = forge_mutation(&["gh", "pr", "edit", &number, "-R", repo, flag, label], false, dry_run, verbose,
+                                       |forge| edit_labels(forge, repo, pr_number, add, remove, verbose)) {
+            eprintln!("⚠️  Could not update label {} of PR #{} (does the label exist?): {}", label, pr_number, e);
+            continue;
+        }
+
+        if blocked {
+            if !dry_run {
+                record_action(format!("Labeled PR #{} {}", pr_number, label));
+            }
             if verbose {
-                eprintln!("  Found existing PR #{} for change {}", existing_pr.pr_number, &rev.change_id[..8]);
+                eprintln!("  PR #{}: +{} (based on {})", pr_number, label, rev.base_branch.as_deref().unwrap_or_default());
             }
-        } else if !dry_run {
-            // Create new PR
-            let title = &rev.description;
+            state.blocked_labeled.insert(pr_number);
+        } else {
+            if !dry_run {
+                record_action(format!("Removed label {} from PR #{}", label, pr_number));
+            }
+            if verbose {
+                eprintln!("  PR #{}: -{} (now based on {})", pr_number, label, trunk);
+            }
+            state.blocked_labeled.remove(&pr_number);
+        }
+    }
+    Ok(())
+}
 
-            // Build PR body with merge commit info if applicable
-            let mut body = format!("Change ID: {}\n\n", rev.change_id);
+// Request reviews per PR from the reviewers config, --reviewer, the revision's
+// `Reviewers:` trailer, the path_reviewers rules it matches and the CODEOWNERS
+// owners of its files. Each reviewer is requested once; ones we requested that
+// no longer apply are withdrawn.
+fn request_reviewers(revisions: &[Revision], state: &mut State, repo: &str, config: &Config, code_owners: Option<&codeowners::CodeOwners>,
+                     dry_run: bool, verbose: bool) -> Result<()> {
+    for rev in revisions {
+        let Some(pr_number) = rev.pr_number else { continue };
+        if rev.pr_state.as_deref().is_some_and(|s| s != "OPEN") {
+            continue;
+        }
 
-            if rev.parent_change_ids.len() > 1 {
-                body.push_str("**Note**: This is a merge commit with multiple parents:\n");
-                for (idx, parent_id) in rev.parent_change_ids.iter().enumerate() {
-                    if idx == 0 {
-                        body.push_str(&format!("- Primary: `{}`\n", &parent_id[..12.min(parent_id.len())]));
-                    } else {
-                        body.push_str(&format!("- Additional: `{}`\n", &parent_id[..12.min(parent_id.len())]));
-                    }
-                }
-                body.push('\n');
+        let mut wanted: BTreeSet<String> = config.reviewers.iter().cloned()
+            .chain(description::list_trailer(&rev.body, "Reviewers"))
+            .chain(config.path_reviewers.iter()
+                .filter(|rule| paths::any_match(&rule.paths, &rev.changed_paths))
+                .flat_map(|rule| rule.reviewers.iter().cloned()))
+            .chain(code_owners.map(|owners| owners.owners(&rev.changed_paths)).unwrap_or_default())
+            .collect();
+        let requested = state.reviewers.get(&pr_number).cloned().unwrap_or_default();
+        let new: BTreeSet<String> = wanted.difference(&requested).cloned().collect();
+        // GitHub refuses review requests to a PR's own author
+        if let Some(login) = (!new.is_empty()).then(|| current_login(verbose)).transpose()?.flatten() {
+            wanted.retain(|reviewer| !reviewer.eq_ignore_ascii_case(&login));
+        }
+        let new: Vec<&String> = new.iter().filter(|reviewer| wanted.contains(*reviewer)).collect();
+        let stale: Vec<&String> = requested.difference(&wanted).collect();
+        let number = pr_number.to_string();
+        let endpoint = format!("repos/{}/pulls/{}/requested_reviewers", repo, pr_number);
+
+        if !stale.is_empty() {
+            let reviewers = stale.iter().map(|r| r.as_str()).collect::<Vec<_>>().join(",");
+            forge_mutation(&["gh", "pr", "edit", &number, "-R", repo, "--remove-reviewer", &reviewers], true, dry_run, verbose,
+                           |forge| forge.rest("DELETE", &endpoint, reviewers_body(&stale), verbose).map(drop))?;
+            if !dry_run {
+                record_action(format!("Withdrew review request from {} on PR #{}", reviewers, pr_number));
             }
+            if verbose {
+                eprintln!("  PR #{}: withdrew {}", pr_number, reviewers);
+            }
+        }
 
-            let output = run_command(&[
-                "gh", "pr", "create",
-                "-R", repo,
-                "--head", branch_name,
-                "--base", base_branch,
-                "--title", title,
-                "--body", &body,
-            ], false, verbose)?;
-
-            // Extract PR URL
-            if let Some(url) = output.lines().find(|l| l.contains("github.com")) {
-                rev.pr_url = Some(url.to_string());
-                if let Some(num) = url.split('/').last() {
-                    rev.pr_number = num.parse().ok();
-                }
+        if !new.is_empty() {
+            let reviewers = new.iter().map(|r| r.as_str()).collect::<Vec<_>>().join(",");
+            if let Err(e) = forge_mutation(&["gh", "pr", "edit", &number, "-R", repo, "--add-reviewer", &reviewers], false, dry_run, verbose,
+                                           |forge| forge.rest("POST", &endpoint, reviewers_body(&new), verbose).map(drop)) {
+                eprintln!("⚠️  Could not request reviewers on PR #{}: {}", pr_number, e);
+                continue;
+            }
+            if !dry_run {
+                record_action(format!("Requested review from {} on PR #{}", reviewers, pr_number));
+            }
+            if verbose {
+                eprintln!("  PR #{}: requested {}", pr_number, reviewers);
             }
         }
-    }
 
+        if wanted.is_empty() {
+            state.reviewers.remove(&pr_number);
+        } else {
+            state.reviewers.insert(pr_number, wanted);
+        }
+    }
     Ok(())
 }
 
-// Detect and fix PR dependency cycles
-fn detect_and_fix_cycles(revisions: &[Revision], repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
-    let mut dependencies = HashMap::new();
-    for (i, rev) in revisions.iter().enumerate() {
-        if let Some(pr_num) = rev.pr_number {
-            if i > 0 {
-                if let Some(prev_pr) = revisions[i-1].pr_number {
-                    dependencies.insert(pr_num, prev_pr);
-                }
-            }
+// Body of a requested_reviewers call; teams are given by slug, without the organization
+fn reviewers_body(reviewers: &[&String]) -> serde_json::Value {
+    let (teams, users): (Vec<&String>, Vec<&String>) = reviewers.iter().partition(|reviewer| reviewer.contains('/'));
+    let teams: Vec<&str> = teams.iter().filter_map(|team| team.split_once('/').map(|(_, slug)| slug)).collect();
+    serde_json::json!({ "reviewers": users, "team_reviewers": teams })
+}
+
+// PR body for revision i: what body_template renders (by default the stack
+// section, optional mermaid graph and change ID) between the managed markers.
+// With a pull request template, the body starts with that template with the
+// revision's description filled in.
+fn stack_pr_body(revisions: &[Revision], i: usize, repo: &str, config: &Config) -> String {
+    let rev = &revisions[i];
+    let block = template::managed_block(&generated_body(revisions, i, repo, config));
+    match PR_TEMPLATE.get() {
+        Some(pr_template) => format!("{}\n\n{}",
+                                     template::fill_pr_template(pr_template, description::without_trailers(&rev.body)).trim_end(), block),
+        None => block,
+    }
+}
+
+// Body a PR starts with when stack_comment leaves bodies to their authors:
+// the pull request template with the description filled in, or the description
+fn author_pr_body(rev: &Revision) -> String {
+    let description = description::without_trailers(&rev.body);
+    match PR_TEMPLATE.get() {
+        Some(pr_template) => template::fill_pr_template(pr_template, description).trim_end().to_string(),
+        None => description.trim().to_string(),
+    }
+}
+
+// The part of revision i's PR body rendered from body_template
+fn generated_body(revisions: &[Revision], i: usize, repo: &str, config: &Config) -> String {
+    let rev = &revisions[i];
+    let stack = stack_section(revisions, i, repo, config);
+    let branch = rev.branch_name.clone().unwrap_or_default();
+
+    let default = if PR_TEMPLATE.get().is_some() { template::MANAGED_BODY_TEMPLATE } else { template::DEFAULT_BODY_TEMPLATE };
+    let template = config.body_template.as_deref().unwrap_or(default);
+    template::render(template, |name| match name {
+        "stack" => Some(stack.clone()),
+        "title" => Some(title_markdown(&rev.description, config)),
+        "change_id" => Some(rev.change_id.clone()),
+        "commit_sha" => Some(rev.commit_id.clone()),
+        "commit_short" => Some(rev.commit_id[..12.min(rev.commit_id.len())].to_string()),
+        "branch" => Some(branch.clone()),
+        "base" => rev.base_branch.clone(),
+        "repo" => Some(repo.to_string()),
+        "pr_number" => rev.pr_number.map(|n| n.to_string()),
+        "test_plan" => Some(description::test_plan(&rev.body)
+            .map(|plan| format!("\n## Test Plan\n\n{}\n", plan))
+            .unwrap_or_default()),
+        "co_authors" => Some(co_authors_section(&rev.body)),
+        "checklists" => Some(checklist::sections(&config.checklists, &rev.changed_paths)),
+        // Trailers at the very end survive squash merges that use the PR description
+        "co_author_trailers" => Some(description::co_authors(&rev.body).iter()
+            .map(|author| format!("\nCo-authored-by: {}", author))
+            .collect::<String>()),
+        // Repositories that use the PR description as the merge commit message
+        // keep these on the main branch, tying landed commits to their change
+        "merge_trailers" => Some(format!("\n{}", description::merge_trailers(
+            &rev.change_id, &revisions.iter().filter_map(|r| r.pr_number).collect::<Vec<_>>()))),
+        _ => match name.strip_prefix("badge:") {
+            Some(workflow) => Some(template::workflow_badge(repo, workflow, &branch)),
+            None => config.template_vars.get(name).cloned(),
+        },
+    })
+}
+
+// "## Co-authors" section listing the revision's Co-authored-by trailers
+fn co_authors_section(body: &str) -> String {
+    let authors = description::co_authors(body);
+    if authors.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from("\n## Co-authors\n\n");
+    for author in authors {
+        section.push_str(&format!("- {}\n", author));
+    }
+    section
+}
+
+// The "## Stack" list of PRs, with the mermaid graph when enabled
+fn stack_section(revisions: &[Revision], i: usize, repo: &str, config: &Config) -> String {
+    let mut body = String::new();
+    body.push_str("## Stack\n\n");
+
+    let default = if config.stack_checks { template::CHECKS_STACK_TEMPLATE } else { template::DEFAULT_STACK_TEMPLATE };
+    let template = config.stack_template.as_deref().unwrap_or(default);
+    let mut lines: Vec<String> = revisions.iter().enumerate().map(|(j, r)| {
+        let state_icon = match (r.pr_state.as_deref(), r.pr_mergeable.as_deref()) {
+            (Some("MERGED"), _) => "✓",
+            (Some("CLOSED"), _) => "✗",
+            (_, Some("CONFLICTING")) => "⚠️ conflicts with base",
+            _ => "",
+        };
+        let state = match (r.pr_number, r.pr_state.as_deref(), r.pr_mergeable.as_deref()) {
+            (None, _, _) => "queued".to_string(),
+            (_, Some("OPEN") | None, Some("CONFLICTING")) => "conflicting".to_string(),
+            (_, state, _) => state.unwrap_or("OPEN").to_lowercase(),
+        };
+        let line = template::render(template, |name| match name {
+            "marker" => Some(if i == j { "→" } else { "  " }.to_string()),
+            "index" => Some((j + 1).to_string()),
+            "count" => Some(revisions.len().to_string()),
+            "pr" => Some(r.pr_number.map_or_else(|| "(queued)".to_string(), |n| format!("#{}", n))),
+            "pr_number" => Some(r.pr_number.map(|n| n.to_string()).unwrap_or_default()),
+            "url" => Some(r.pr_url.clone().unwrap_or_default()),
+            "title" => Some(title_markdown(&r.description, config)),
+            "state" => Some(state.clone()),
+            "state_icon" => Some(state_icon.to_string()),
+            "checks" => Some(output::checks_icon(r.pr_checks.as_deref()).unwrap_or_default().to_string()),
+            "change_id" => Some(r.change_id.clone()),
+            "commit_short" => Some(r.commit_id[..12.min(r.commit_id.len())].to_string()),
+            "commit_link" => Some(commit_link(repo, &r.commit_id)),
+            "files_link" => Some(files_link(repo, r)),
+            "branch" => Some(r.branch_name.clone().unwrap_or_default()),
+            _ => config.template_vars.get(name).cloned(),
+        });
+        format!("{}\n", line)
+    }).collect();
+    if config.stack_order == StackOrder::TopDown {
+        lines.reverse();
+    }
+    body.extend(lines);
+
+    if config.mermaid_graph {
+        let nodes: Vec<(String, Option<&str>)> = revisions.iter()
+            .map(|r| match r.pr_number {
+                Some(n) => (format!("#{}: {}", n, r.description), r.pr_state.as_deref()),
+                None => (format!("(queued): {}", r.description), None),
+            })
+            .collect();
+        body.push('\n');
+        let base = revisions.first().and_then(|r| r.base_branch.as_deref())
+            .or(BASE_BRANCH.get().map(String::as_str)).unwrap_or("main");
+        body.push_str(&mermaid_graph(base, &nodes, Some(i)));
+    }
+    body
+}
+
+// A commit title as it is rendered into PR bodies and comments
+fn title_markdown(title: &str, config: &Config) -> String {
+    if config.escape_markdown { template::escape_markdown(title) } else { title.to_string() }
+}
+
+// Markdown link to the diff a PR adds on top of its base: its files view, or
+// for a queued revision the compare view of its branch; empty without either
+fn files_link(repo: &str, rev: &Revision) -> String {
+    match (rev.pr_number, &rev.base_branch, &rev.branch_name) {
+        (Some(n), _, _) => format!("[files]({}/{}/pull/{}/files)", forge::web_url(), repo, n),
+        (None, Some(base), Some(branch)) => format!("[diff]({}/{}/compare/{}...{})", forge::web_url(), repo, base, pr_head(branch)),
+        _ => String::new(),
+    }
+}
+
+// Markdown link to a commit's permalink, labelled with its short SHA
+fn commit_link(repo: &str, commit_id: &str) -> String {
+    format!("[`{}`]({}/{}/commit/{})", &commit_id[..7.min(commit_id.len())], forge::web_url(), repo, commit_id)
+}
+
+// Line diff of two texts ("-"/"+" for removed/added lines, longest common
+// subsequence kept as context); empty when they are identical
+fn line_diff(old: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] = length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
         }
     }
 
-    // Simple cycle detection using visited set
-    for &start in dependencies.keys() {
-        let mut visited = HashSet::new();
-        let mut current = start;
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push_str(&format!("    {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            diff.push_str(&format!("  + {}\n", new[j]));
+            j += 1;
+        } else {
+            diff.push_str(&format!("  - {}\n", old[i]));
+            i += 1;
+        }
+    }
+    diff
+}
 
-        while let Some(&next) = dependencies.get(&current) {
-            if !visited.insert(current) {
-                // Cycle detected
-                if verbose {
-                    eprintln!("  Cycle detected involving PR #{}", current);
-                }
-                if !dry_run {
-                    // Break cycle by updating base to main
-                    run_command(&[
-                        "gh", "pr", "edit", &current.to_string(),
-                        "-R", repo,
-                        "--base", "main"
-                    ], true, verbose)?;
-                }
-                break;
-            }
-            current = next;
+// Render the PR dependency chain as a mermaid graph, merged PRs greyed out and
+// the current one highlighted
+fn mermaid_graph(base: &str, nodes: &[(String, Option<&str>)], current: Option<usize>) -> String {
+    let mut graph = format!("```mermaid\ngraph TD\n    base[\"{}\"]\n", base);
+
+    for (i, (label, _)) in nodes.iter().enumerate() {
+        graph.push_str(&format!("    pr{}[\"{}\"]\n", i, label.replace('"', "#quot;")));
+        let parent = if i == 0 { "base".to_string() } else { format!("pr{}", i - 1) };
+        graph.push_str(&format!("    {} --> pr{}\n", parent, i));
+    }
+
+    graph.push_str("    classDef merged fill:#eee,color:#999,stroke:#bbb\n");
+    graph.push_str("    classDef current stroke-width:3px\n");
+    for (i, (_, state)) in nodes.iter().enumerate() {
+        if *state == Some("MERGED") {
+            graph.push_str(&format!("    class pr{} merged\n", i));
         }
     }
+    if let Some(i) = current {
+        graph.push_str(&format!("    class pr{} current\n", i));
+    }
 
-    Ok(())
+    graph.push_str("```\n");
+    graph
 }
 
-fn update_pr_descriptions(revisions: &[Revision], repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
-    eprintln!("Updating PR descriptions...");
-    
-    for (i, rev) in revisions.iter().enumerate() {
-        if let Some(pr_number) = rev.pr_number {
-            // Skip merged/closed PRs
-            if let Some(state) = &rev.pr_state {
-                if state != "OPEN" { continue; }
-            }
-            
-            let mut body = String::new();
-            body.push_str("## Stack\n\n");
-            
-            for (j, r) in revisions.iter().enumerate() {
-                let marker = if i == j { "→" } else { "  " };
-                let state_icon = match r.pr_state.as_deref() {
-                    Some("MERGED") => "✓",
-                    Some("CLOSED") => "✗",
-                    _ => "",
-                };
-                body.push_str(&format!("{} #{}: {} {}\n", 
-                    marker, 
-                    r.pr_number.unwrap_or(0), 
-                    r.description,
-                    state_icon
-                ));
-            }
-            
-            body.push_str(&format!("\n---\nChange ID: `{}`\n", rev.change_id));
-            
-            if !dry_run {
-                run_command(&["gh", "pr", "edit", &pr_number.to_string(), "-R", repo, "--body", &body], true, verbose)?;
-            }
+// Mark draft PRs ready for review once their parent merged and they target main
+fn ready_unblocked_drafts(revisions: &[Revision], unblocked: &[String], repo: &str, trunk: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    for rev in revisions {
+        let Some(pr_number) = rev.pr_number else { continue };
+        if rev.pr_state.as_deref().is_some_and(|s| s != "OPEN") {
+            continue;
+        }
+        if !unblocked.iter().any(|id| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str())) {
+            continue;
         }
+
+        let json = view_pr(repo, &pr_number.to_string(), &["id", "isDraft", "baseRefName"], verbose);
+        if json["isDraft"].as_bool() != Some(true) || json["baseRefName"].as_str() != Some(trunk) {
+            continue;
+        }
+
+        if dry_run {
+            eprintln!("Would mark draft PR #{} ready for review", pr_number);
+        } else {
+            eprintln!("Marking draft PR #{} ready for review (parent merged)", pr_number);
+            record_action(format!("Marked PR #{} ready for review", pr_number));
+        }
+        // Drafts can only be marked ready through GraphQL
+        let id = json["id"].as_str().unwrap_or_default();
+        forge_mutation(&["gh", "pr", "ready", &pr_number.to_string(), "-R", repo], true, dry_run, verbose, |forge| forge.graphql(
+            "mutation($id: ID!) { markPullRequestReadyForReview(input: {pullRequestId: $id}) { clientMutationId } }",
+            serde_json::json!({ "id": id }), verbose).map(drop))?;
     }
-    
+
     Ok(())
 }
 
 fn detect_merged_prs(revisions: &mut [Revision], state: &State, repo: &str, verbose: bool) -> Result<Vec<(usize, String, Option<String>)>> {
     let mut merged = Vec::new();
+    let prs = PrCache::fetch(repo, state.prs.values().map(|pr_info| pr_info.pr_number), PR_CACHE_FIELDS, verbose);
 
     // Check PRs from state
     for (change_id, pr_info) in &state.prs {
         // Check if PR is merged on GitHub and get its base branch
-        let output = run_command(&[
-            "gh", "pr", "view", &pr_info.pr_number.to_string(),
-            "-R", repo,
-            "--json", "state,mergedAt,baseRefName"
-        ], true, verbose)?;
+        let json = prs.get(pr_info.pr_number, &["state", "mergedAt", "baseRefName"], verbose);
 
-        if output.contains("\"mergedAt\":") && !output.contains("\"mergedAt\":null") || output.contains("\"state\":\"MERGED\"") {
-            // Extract base branch from JSON
-            let base_branch = if let Ok(json) = serde_json::from_str::<serde_json::Value>(&output) {
-                json["baseRefName"].as_str().map(String::from)
-            } else {
-                None
-            };
+        if !json["mergedAt"].is_null() || json["state"] == "MERGED" {
+            let base_branch = json["baseRefName"].as_str().map(String::from);
 
             // Find position in current stack using prefix matching
             if let Some(pos) = revisions.iter().position(|r| {
@@ -791,7 +4560,17 @@ fn detect_merged_prs(revisions: &mut [Revision], state: &State, repo: &str, verb
     Ok(merged)
 }
 
-fn handle_merged_prs(merged: &[(usize, String, Option<String>)], revisions: &mut Vec<Revision>, verbose: bool) -> Result<()> {
+// A merged PR is out of order when it went into another PR's branch, or
+// while PRs below it are still open
+fn is_out_of_order_merge(revisions: &[Revision], idx: usize, base: Option<&str>, config: &Config, trunk: &str) -> bool {
+    if base.is_some_and(|b| config.is_managed_branch(b) && b != trunk) {
+        return true;
+    }
+    revisions[..idx].iter().any(|r| r.pr_state.as_deref() != Some("MERGED"))
+}
+
+fn handle_merged_prs(merged: &[(usize, String, Option<String>)], revisions: &mut [Revision], config: &Config, trunk: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    let trunk_head = format!("{}@{}", trunk, pr_remote());
     eprintln!("Handling {} merged PRs...", merged.len());
 
     // Filter out merged PRs that are no longer in the stack (marked with usize::MAX)
@@ -816,18 +4595,18 @@ fn handle_merged_prs(merged: &[(usize, String, Option<String>)], revisions: &mut
 
             // Determine destination based on where this PR was merged
             let destination = if let Some(ref base) = base_branch {
-                if base.starts_with("push-") && base != "main" {
+                if config.is_managed_branch(base) && base != trunk {
                     // PR was merged into another PR branch - rebase onto that branch's current state
                     if verbose {
-                        eprintln!("    PR was merged into another PR branch ({}), rebasing onto {}@origin", base, base);
+                        eprintln!("    PR was merged into another PR branch ({}), rebasing onto {}@{}", base, base, remote());
                     }
-                    format!("{}@origin", base)
+                    format!("{}@{}", base, remote())
                 } else {
                     // PR was merged into main
-                    "main@origin".to_string()
+                    trunk_head.clone()
                 }
             } else if idx == 0 {
-                "main@origin".to_string()
+                trunk_head.clone()
             } else {
                 // For out-of-order merges to main, find the previous unmerged commit
                 let mut dest_idx = idx - 1;
@@ -836,24 +4615,89 @@ fn handle_merged_prs(merged: &[(usize, String, Option<String>)], revisions: &mut
                 }
 
                 if revisions[dest_idx].pr_state.as_deref() == Some("MERGED") {
-                    "main@origin".to_string()
+                    trunk_head.clone()
                 } else {
                     revisions[dest_idx].change_id.clone()
                 }
             };
 
-            if verbose {
-                eprintln!("  Rebasing {} onto {}", &source[..8], destination);
+            if verbose || dry_run {
+                eprintln!("  {} {} onto {}", if dry_run { "Would rebase" } else { "Rebasing" }, &source[..8], destination);
             }
-            run_command(&["jj", "rebase", "-s", source, "-d", &destination], false, verbose)?;
+            run_mutation(&["jj", "rebase", "-s", source, "-d", &destination], false, dry_run, verbose)?;
+            record_action(format!("Rebased {} onto {}", &source[..8], destination));
         }
     }
 
     Ok(())
 }
 
-fn close_orphaned_prs(current: &[Revision], state: &mut State, squashed: &HashSet<String>, repo: &str, delete_branches: bool, dry_run: bool, verbose: bool) -> Result<()> {
-    let current_change_ids: HashSet<_> = current.iter().map(|r| r.change_id.clone()).collect();
+// Find PRs in the stack that were closed on GitHub without merging (and not by us)
+fn detect_closed_prs(revisions: &[Revision], state: &State, repo: &str, config: &Config, verbose: bool) -> Result<Vec<(usize, String, u32)>> {
+    let mut closed = Vec::new();
+    if state.prs.is_empty() {
+        return Ok(closed);
+    }
+
+    // PRs are looked up on the branches they were pushed to, which an older
+    // branch_prefix, a rename or a split may have named
+    let branches: Vec<String> = revisions.iter().map(|rev| tracked_branch(state, config, &rev.change_id)).collect();
+    let existing_prs = get_existing_prs(repo, &branches, verbose)?;
+
+    for (idx, rev) in revisions.iter().enumerate() {
+        let matches = |id: &String| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str());
+
+        // Only PRs we created, and not ones we closed ourselves
+        if !state.prs.keys().any(matches) || state.closed_prs.iter().any(matches) {
+            continue;
+        }
+
+        if let Some(pr) = existing_prs.get(&branches[idx]) {
+            if pr.2 == "CLOSED" {
+                closed.push((idx, rev.change_id.clone(), pr.0));
+            }
+        }
+    }
+
+    Ok(closed)
+}
+
+// Rebase the stack over changes whose PR was closed manually, so their children
+// get retargeted onto the closed PR's parent
+fn handle_closed_prs(closed: &[(usize, String, u32)], revisions: &[Revision], state: &mut State, dry_run: bool, verbose: bool) -> Result<()> {
+    let closed_idx: HashSet<usize> = closed.iter().map(|(idx, _, _)| *idx).collect();
+
+    for (idx, change_id, pr_number) in closed {
+        eprintln!("PR #{} was closed on GitHub, removing {} from the stack", pr_number, &change_id[..8]);
+        state.closed_externally.insert(change_id.clone());
+
+        // Consecutive closed changes are handled once, from the top of the run
+        if closed_idx.contains(&(idx + 1)) || idx + 1 >= revisions.len() {
+            continue;
+        }
+
+        let source = &revisions[idx + 1].change_id;
+        let destination = (0..*idx).rev()
+            .find(|i| !closed_idx.contains(i))
+            .map(|i| revisions[i].change_id.clone())
+            .unwrap_or_else(|| stack_base(state));
+
+        if verbose || dry_run {
+            eprintln!("  {} {} onto {}", if dry_run { "Would rebase" } else { "Rebasing" }, &source[..8], destination);
+        }
+        run_mutation(&["jj", "rebase", "-s", source, "-d", &destination], false, dry_run, verbose)?;
+        record_action(format!("Rebased {} onto {}", &source[..8], destination));
+    }
+
+    Ok(())
+}
+
+// Find open PRs whose change left the stack (and wasn't merged) or was squashed
+fn find_orphaned_prs(current: &[Revision], withheld: &[String], state: &State, squashed: &HashSet<String>, repo: &str, config: &Config, verbose: bool) -> Result<Vec<Orphan>> {
+    // Revisions withheld above a private commit are still part of the stack
+    let current_change_ids: HashSet<_> = current.iter().map(|r| r.change_id.clone()).chain(withheld.iter().cloned()).collect();
+    let mut orphans = Vec::new();
+    let mut squash_destinations: Option<Vec<String>> = None;
 
     for (change_id, pr_info) in &state.prs {
         // Check if this PR's change is still in the stack
@@ -870,52 +4714,152 @@ fn close_orphaned_prs(current: &[Revision], state: &mut State, squashed: &HashSe
 
         // Close if: removed from stack (and not merged), or was squashed
         let should_close = (!still_in_stack && !is_merged) || was_squashed;
+        if !should_close {
+            continue;
+        }
+
+        let reason = if was_squashed {
+            "the commit was squashed"
+        } else {
+            "the commit was removed from the stack"
+        };
+
+        // First check PR state to avoid closing already closed/merged PRs
+        let pr = view_pr(repo, &pr_info.pr_number.to_string(), &["state", "author"], verbose);
+
+        // PRs opened by someone else are theirs to close, even if our state tracks them
+        let author = pr["author"]["login"].as_str().unwrap_or_default();
+        if let Some(login) = current_login(verbose)?.filter(|login| !author.is_empty() && !author.eq_ignore_ascii_case(login)) {
+            eprintln!("⚠️  Not closing PR #{}: it was opened by @{}, not @{}", pr_info.pr_number, author, login);
+            continue;
+        }
+
+        let status = pr["state"].as_str().unwrap_or_default();
+        if status == "OPEN" {
+            let destinations = match &squash_destinations {
+                Some(destinations) => destinations,
+                None => {
+                    let op_log = run_command(&[
+                        "jj", "op", "log", "--limit", "50", "--no-graph",
+                        "--template", r#"description ++ "\n""#
+                    ], true, verbose)?;
+                    squash_destinations.insert(stack::squash_destinations(&op_log).into_iter().map(String::from).collect())
+                }
+            };
+            let superseded_by = superseding_pr(change_id, destinations, state, repo, config, verbose)?;
+            orphans.push(Orphan { change_id: change_id.clone(), pr_info: pr_info.clone(), reason, superseded_by });
+        } else if verbose {
+            eprintln!("  Skipping PR #{} (already {})", pr_info.pr_number, status.to_lowercase());
+        }
+    }
+
+    orphans.sort_by_key(|orphan| orphan.pr_info.pr_number);
+    Ok(orphans)
+}
+
+// An open PR whose change left the stack
+struct Orphan {
+    change_id: String,
+    pr_info: PrInfo,
+    reason: &'static str,
+    // PR of the change that absorbed this one, for the closing comment
+    superseded_by: Option<u32>,
+}
+
+// The PR of the change an orphaned change was squashed into: a recent
+// `jj squash` destination whose evolution log leads back to the orphan
+fn superseding_pr(change_id: &str, destinations: &[String], state: &State, repo: &str, config: &Config, verbose: bool) -> Result<Option<u32>> {
+    let same = |a: &str, b: &str| a.starts_with(b) || b.starts_with(a);
+
+    for commit in destinations {
+        let output = run_command(&["jj", "log", "-r", commit, "--no-graph", "--template", "change_id"], true, verbose)?;
+        let survivor = output.trim();
+        if survivor.is_empty() || output.contains("Error:") || same(survivor, change_id) {
+            continue;
+        }
+        if !predecessor_change_ids(survivor, verbose)?.iter().any(|id| same(id, change_id)) {
+            continue;
+        }
+
+        if let Some((_, info)) = state.prs.iter().find(|(id, _)| same(id, survivor)) {
+            return Ok(Some(info.pr_number));
+        }
+        let branch = branch_name_for(&config.branch_prefix, survivor);
+        return Ok(view_pr(repo, &branch, &["number"], verbose)["number"].as_u64().map(|n| n as u32));
+    }
+    Ok(None)
+}
+
+// List the PRs about to be closed and ask for confirmation in interactive terminals
+fn confirm_close_prs(orphans: &[Orphan], config: &Config, dry_run: bool) -> Result<bool> {
+    eprintln!("\n{}:", if dry_run { "Orphaned PRs that would be closed" } else { "Orphaned PRs to close" });
+    for orphan in orphans {
+        let superseded = orphan.superseded_by.map(|n| format!(" (superseded by #{})", n)).unwrap_or_default();
+        eprintln!("  - #{} ({}): {}{}", orphan.pr_info.pr_number,
+                 &orphan.change_id[..8.min(orphan.change_id.len())], orphan.reason, superseded);
+    }
+
+    if dry_run || !config.confirm_close_prs || !std::io::stdin().is_terminal() {
+        return Ok(true);
+    }
+
+    let confirmed = prompt_yes_no(&format!("Close {} PR{}?", orphans.len(), if orphans.len() == 1 { "" } else { "s" }))?;
+    if !confirmed {
+        eprintln!("Leaving orphaned PRs open");
+    }
+    Ok(confirmed)
+}
+
+fn prompt_yes_no(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
 
-        if should_close {
-            if !dry_run {
-                // First check PR state to avoid closing already closed/merged PRs
-                let pr_status = run_command(&[
-                    "gh", "pr", "view", &pr_info.pr_number.to_string(),
-                    "-R", repo,
-                    "--json", "state", "-q", ".state"
-                ], true, verbose)?;
-
-                let status = pr_status.trim();
-                if status == "OPEN" {
-                    eprintln!("Closing orphaned PR #{}", pr_info.pr_number);
-
-                    let comment = if squashed.iter().any(|s| change_id.starts_with(s)) {
-                        "This PR was closed because the commit was squashed"
-                    } else {
-                        "This PR was closed because the commit was removed from the stack"
-                    };
+fn close_orphaned_prs(orphans: &[Orphan], state: &mut State, repo: &str, delete_branches: bool, dry_run: bool, verbose: bool) -> Result<()> {
+    for Orphan { change_id, pr_info, reason, superseded_by } in orphans {
+        if !dry_run {
+            eprintln!("Closing orphaned PR #{}", pr_info.pr_number);
+        }
 
-                    run_command(&[
-                        "gh", "pr", "close", &pr_info.pr_number.to_string(),
-                        "-R", repo,
-                        "--comment", comment
-                    ], true, verbose)?;
+        let mut comment = format!("This PR was closed because {}", reason);
+        if let Some(number) = superseded_by {
+            comment.push_str(&format!("; it is superseded by #{}", number));
+            state.superseded_by.insert(pr_info.pr_number, *number);
+        }
+        forge_mutation(&["gh", "pr", "close", &pr_info.pr_number.to_string(), "-R", repo, "--comment", &comment], true, dry_run, verbose,
+                       |forge| forge.close_pr(repo, pr_info.pr_number, Some(&comment), verbose))?;
+        record_action(format!("Closed PR #{} ({})", pr_info.pr_number, reason));
 
-                    // Track closed PR for potential reopening
-                    state.closed_prs.insert(change_id.clone());
+        // Track closed PR for potential reopening
+        state.closed_prs.insert(change_id.clone());
 
-                    if delete_branches {
-                        run_command(&[
-                            "jj", "git", "push", "-b", &pr_info.branch_name, "--delete"
-                        ], true, verbose)?;
-                    }
-                } else if verbose {
-                    eprintln!("  Skipping PR #{} (already {})", pr_info.pr_number, status.to_lowercase());
-                }
-            } else {
-                eprintln!("Would close orphaned PR #{}", pr_info.pr_number);
-            }
+        if delete_branches {
+            delete_branch(&pr_info.branch_name, dry_run, verbose)?;
         }
     }
 
     Ok(())
 }
 
+// Delete the remote branches (and local bookmarks) of merged PRs
+fn delete_merged_branches(branches: &[String], dry_run: bool, verbose: bool) -> Result<()> {
+    for branch in branches {
+        eprintln!("{} merged branch {}", if dry_run { "Would delete" } else { "Deleting" }, branch);
+        delete_branch(branch, dry_run, verbose)?;
+    }
+    Ok(())
+}
+
+fn delete_branch(branch: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    run_mutation(&["jj", "bookmark", "delete", branch], true, dry_run, verbose)?;
+    run_mutation(&["jj", "git", "push", "--remote", remote(), "-b", branch], true, dry_run, verbose)?;
+    record_action(format!("Deleted branch {}", branch));
+    Ok(())
+}
+
 // Reopen previously closed PRs if they're back in the stack
 fn reopen_prs(revisions: &mut [Revision], state: &State, repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
     for rev in revisions {
@@ -924,7 +4868,12 @@ fn reopen_prs(revisions: &mut [Revision], state: &State, repo: &str, dry_run: bo
             closed_id.starts_with(&rev.change_id) || rev.change_id.starts_with(closed_id)
         });
 
-        if was_closed {
+        // Never reopen a PR someone closed by hand on GitHub
+        let closed_externally = state.closed_externally.iter().any(|closed_id| {
+            closed_id.starts_with(&rev.change_id) || rev.change_id.starts_with(closed_id)
+        });
+
+        if was_closed && !closed_externally {
             // Look for the closed PR (using prefix matching)
             let pr_info = state.prs.iter()
                 .find(|(id, _)| id.starts_with(&rev.change_id) || rev.change_id.starts_with(id.as_str()))
@@ -936,30 +4885,29 @@ fn reopen_prs(revisions: &mut [Revision], state: &State, repo: &str, dry_run: bo
                              pr_info.pr_number, &rev.change_id[..8]);
                 }
 
-                if !dry_run {
-                    // Check if PR is actually closed
-                    let pr_status = run_command(&[
-                        "gh", "pr", "view", &pr_info.pr_number.to_string(),
-                        "-R", repo,
-                        "--json", "state", "-q", ".state"
-                    ], true, verbose)?;
-
-                    if pr_status.trim() == "CLOSED" {
-                        // Reopen the PR
-                        let result = run_command(&[
-                            "gh", "pr", "reopen", &pr_info.pr_number.to_string(),
-                            "-R", repo
-                        ], true, verbose);
-
-                        if result.is_ok() {
-                            // Update revision with PR info
-                            rev.pr_number = Some(pr_info.pr_number);
-                            rev.pr_url = Some(pr_info.pr_url.clone());
-                            rev.pr_state = Some("OPEN".to_string());
+                // Check if PR is actually closed
+                let pr_status = view_pr(repo, &pr_info.pr_number.to_string(), &["state"], verbose);
+
+                if pr_status["state"] == "CLOSED" {
+                    // Reopen the PR
+                    let result = forge_mutation(&["gh", "pr", "reopen", &pr_info.pr_number.to_string(), "-R", repo], false, dry_run, verbose, |forge| {
+                        forge.rest("PATCH", &format!("repos/{}/pulls/{}", repo, pr_info.pr_number),
+                                   serde_json::json!({ "state": "open" }), verbose).map(drop)
+                    });
+
+                    if result.is_ok() {
+                        // Update revision with PR info
+                        rev.pr_number = Some(pr_info.pr_number);
+                        rev.pr_url = Some(pr_info.pr_url.clone());
+                        rev.pr_state = Some("OPEN".to_string());
+                        if dry_run {
+                            eprintln!("  Would reopen PR #{}", pr_info.pr_number);
+                        } else {
                             eprintln!("  Successfully reopened PR #{}", pr_info.pr_number);
-                        } else if verbose {
-                            eprintln!("  Failed to reopen PR #{}", pr_info.pr_number);
+                            record_action(format!("Reopened PR #{}", pr_info.pr_number));
                         }
+                    } else if verbose {
+                        eprintln!("  Failed to reopen PR #{}", pr_info.pr_number);
                     }
                 }
             }
@@ -969,39 +4917,84 @@ fn reopen_prs(revisions: &mut [Revision], state: &State, repo: &str, dry_run: bo
     Ok(())
 }
 
-fn get_existing_prs(repo: &str, verbose: bool) -> Result<HashMap<String, (u32, String, String, String)>> {
-    let output = run_command(&[
-        "gh", "pr", "list", "-R", repo, "--state", "all", "--limit", "1000",
-        "--json", "number,url,state,headRefName,baseRefName"
-    ], true, verbose)?;
-    
+// Every PR of the repository (or only the open ones), each with the given
+// GraphQL fields. Pages are followed to the end: cleanup decisions made on a
+// truncated list would treat unlisted PRs as gone, so a failure is an error.
+fn list_pull_requests(repo: &str, open_only: bool, fields: &str, verbose: bool) -> Result<Vec<serde_json::Value>> {
+    let (owner, name) = repo.split_once('/').with_context(|| format!("Invalid repository {}", repo))?;
+    let query = format!(
+        "query($owner: String!, $name: String!, $endCursor: String) {{ repository(owner: $owner, name: $name) {{ \
+         pullRequests(first: 100, after: $endCursor{}) {{ nodes {{ {} }} pageInfo {{ hasNextPage endCursor }} }} }} }}",
+        if open_only { ", states: OPEN" } else { "" }, fields);
+    let mut prs = Vec::new();
+    let mut cursor = serde_json::Value::Null;
+    loop {
+        let data = forge::forge().graphql(&query, serde_json::json!({ "owner": owner, "name": name, "endCursor": cursor }), verbose)?;
+        let page = &data["repository"]["pullRequests"];
+        prs.extend(page["nodes"].as_array().cloned().context("Failed to parse PR list")?);
+        if page["pageInfo"]["hasNextPage"].as_bool() != Some(true) {
+            return Ok(prs);
+        }
+        cursor = page["pageInfo"]["endCursor"].clone();
+    }
+}
+
+// PRs whose head is one of `branches`, by branch name, looked up directly
+// rather than by listing every PR in the repository. A branch with several PRs
+// maps to its open one, or else the newest.
+fn get_existing_prs(repo: &str, branches: &[String], verbose: bool) -> Result<HashMap<String, (u32, String, String, String)>> {
+    // Branches looked up per GraphQL request
+    const BATCH: usize = 50;
+
+    let (owner, name) = repo.split_once('/').with_context(|| format!("Invalid repository {}", repo))?;
     let mut prs = HashMap::new();
-    
-    if let Ok(json) = serde_json::from_str::<Vec<serde_json::Value>>(&output) {
-        for pr in json {
-            if let (Some(head_ref), Some(number), Some(url), Some(state), Some(base_ref)) = (
-                pr["headRefName"].as_str(),
-                pr["number"].as_u64(),
-                pr["url"].as_str(),
-                pr["state"].as_str(),
-                pr["baseRefName"].as_str(),
-            ) {
-                if head_ref.starts_with("push-") {
-                    prs.insert(
-                        head_ref.to_string(), 
-                        (number as u32, url.to_string(), state.to_string(), base_ref.to_string())
-                    );
-                }
+    for batch in branches.chunks(BATCH) {
+        let lookups: String = batch.iter().enumerate()
+            .map(|(i, branch)| format!(
+                "b{}: pullRequests(headRefName: {}, first: 10, orderBy: {{field: CREATED_AT, direction: DESC}}) \
+                 {{ nodes {{ number url state headRefName baseRefName }} }} ",
+                i, serde_json::Value::from(branch.as_str())))
+            .collect();
+        let query = format!("query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ {}}} }}", lookups);
+        let data = forge::forge().graphql(&query, serde_json::json!({ "owner": owner, "name": name }), verbose)?;
+        let found = &data["repository"];
+
+        for (i, branch) in batch.iter().enumerate() {
+            let nodes = found[format!("b{}", i)]["nodes"].as_array().cloned().unwrap_or_default();
+            let pr = nodes.iter().find(|pr| pr["state"].as_str() == Some("OPEN")).or(nodes.first());
+            if let Some((number, url, state, base_ref)) = pr.and_then(|pr| Some((
+                pr["number"].as_u64()?,
+                pr["url"].as_str()?,
+                pr["state"].as_str()?,
+                pr["baseRefName"].as_str()?,
+            ))) {
+                prs.insert(branch.clone(), (number as u32, url.to_string(), state.to_string(), base_ref.to_string()));
             }
         }
     }
-    
+
     Ok(prs)
 }
 
+// Branch of a change's PR: the one the state file recorded when it was pushed
+// (which an older branch_prefix may have named), else the one it would get
+fn tracked_branch(state: &State, config: &Config, change_id: &str) -> String {
+    state.prs.iter()
+        .find(|(id, _)| id.starts_with(change_id) || change_id.starts_with(id.as_str()))
+        .map(|(_, info)| info.branch_name.clone())
+        .unwrap_or_else(|| branch_name_for(&config.branch_prefix, change_id))
+}
+
+// Branch of each revision: the one it was pushed to, or the one it would get
+fn stack_branches(revisions: &[Revision], config: &Config) -> Vec<String> {
+    revisions.iter()
+        .map(|rev| rev.branch_name.clone().unwrap_or_else(|| branch_name_for(&config.branch_prefix, &rev.change_id)))
+        .collect()
+}
+
 fn load_state() -> Result<State> {
     match fs::read_to_string(".almighty") {
-        Ok(content) => serde_json::from_str(&content).context("Failed to parse state"),
+        Ok(content) => State::from_json(&content).context("Failed to parse state"),
         Err(_) => Ok(State::default()),
     }
 }
@@ -1038,6 +5031,7 @@ fn save_state(state: &mut State, revisions: &[Revision]) -> Result<()> {
                     branch_name: rev.branch_name.clone().unwrap_or_default(),
                     commit_id: rev.commit_id.clone(),
                     change_id: Some(full_change_id),
+                    base_branch: rev.base_branch.clone(),
                 },
             );
             
@@ -1054,36 +5048,124 @@ fn save_state(state: &mut State, revisions: &[Revision]) -> Result<()> {
     // Replace the PRs map with the new one
     state.prs = new_prs;
 
-    let content = serde_json::to_string_pretty(&state)?;
-    fs::write(".almighty", content)?;
+    write_state(state)
+}
+
+// Write the state file atomically so an interrupted run never leaves it half-written
+fn write_state(state: &State) -> Result<()> {
+    let content = state.to_json()?;
+    fs::write(".almighty.tmp", content)?;
+    fs::rename(".almighty.tmp", ".almighty")?;
     Ok(())
 }
 
-// Extract GitHub repo info from jj remote
-fn get_repo_info(verbose: bool) -> Result<String> {
+// Remote the stack is pushed to and compared against, chosen by select_remotes
+static REMOTE: OnceLock<String> = OnceLock::new();
+// Remote of the repository PRs are opened on, when it isn't REMOTE (a fork)
+static PR_REMOTE: OnceLock<String> = OnceLock::new();
+// Owner of the fork pushed to, for `--head owner:branch`
+static FORK_OWNER: OnceLock<String> = OnceLock::new();
+
+fn remote() -> &'static str {
+    REMOTE.get().map_or("origin", String::as_str)
+}
+
+// Remote the base branch and PRs live on; the push remote unless pushing to a fork
+fn pr_remote() -> &'static str {
+    PR_REMOTE.get().map_or(remote(), String::as_str)
+}
+
+// `--head` of a new PR: the branch, qualified with the fork's owner in a fork workflow
+fn pr_head(branch: &str) -> String {
+    match FORK_OWNER.get() {
+        Some(owner) => format!("{}:{}", owner, branch),
+        None => branch.to_string(),
+    }
+}
+
+// Remotes configured in jj, as (name, URL)
+fn jj_remotes(verbose: bool) -> Result<Vec<(String, String)>> {
     let output = run_command(&["jj", "git", "remote", "list"], false, verbose)?;
+    Ok(output.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        Some((fields.next()?.to_string(), fields.next().unwrap_or("").to_string()))
+    }).collect())
+}
+
+// Push to --remote if given, else origin, else the only remote when there is
+// just one. With a separate PR remote, branches go to the push remote (a fork)
+// and PRs are opened on the PR remote's repository.
+fn select_remotes(push: Option<&str>, pr: Option<&str>, verbose: bool) -> Result<()> {
+    // Outside a jj repository (init, doctor) there is nothing to choose from
+    let Ok(remotes) = jj_remotes(verbose) else { return Ok(()) };
+    let url_of = |name: &str| remotes.iter().find(|(remote, _)| remote == name).map(|(_, url)| url.as_str());
+    let missing = |name: &str| CodedError::new(ErrorCode::NoGithubRemote, format!("No remote named {} in jj", name));
+
+    let chosen = match push {
+        Some(name) if url_of(name).is_some() => Some(name.to_string()),
+        Some(name) => bail!(missing(name)),
+        None if url_of("origin").is_some() => None,
+        None => match remotes.as_slice() {
+            [(only, _)] => Some(only.clone()),
+            _ => None,
+        },
+    };
+    if let Some(chosen) = chosen {
+        if verbose {
+            eprintln!("[debug] Using remote {}", chosen);
+        }
+        let _ = REMOTE.set(chosen);
+    }
+
+    let Some(pr) = pr.filter(|pr| *pr != remote()) else { return Ok(()) };
+    if url_of(pr).is_none() {
+        bail!(missing(pr));
+    }
+    let owner = url_of(remote()).and_then(extract_github_repo)
+        .and_then(|repo| repo.split_once('/').map(|(owner, _)| owner.to_string()))
+        .with_context(|| format!("Remote {} is not a GitHub repository", remote()))?;
+    if verbose {
+        eprintln!("[debug] Pushing to {} ({}), opening PRs on {}", remote(), owner, pr);
+    }
+    let _ = PR_REMOTE.set(pr.to_string());
+    let _ = FORK_OWNER.set(owner);
+    Ok(())
+}
 
-    for line in output.lines() {
-        if line.starts_with("origin") {
+// Fetch the push remote, and the PR remote too in a fork workflow
+fn fetch_remotes(verbose: bool) -> Result<()> {
+    let mut fetch = vec!["jj", "git", "fetch", "--remote", remote()];
+    if pr_remote() != remote() {
+        fetch.extend(["--remote", pr_remote()]);
+    }
+    run_command(&fetch, false, verbose)?;
+    Ok(())
+}
+
+// Extract GitHub repo info from jj remote
+fn get_repo_info(verbose: bool) -> Result<String> {
+    for (name, url) in jj_remotes(verbose)? {
+        if name == pr_remote() {
             // Parse GitHub URL formats:
             // - git@github.com:owner/repo.git
             // - https://github.com/owner/repo.git
             // - https://github.com/owner/repo
-            let url = line.split_whitespace().nth(1).unwrap_or("");
-
-            if let Some(repo) = extract_github_repo(url) {
+            if let Some(repo) = extract_github_repo(&url) {
                 return Ok(repo);
             }
         }
     }
 
-    bail!("Could not determine GitHub repository from jj remotes")
+    bail!(CodedError::new(ErrorCode::NoGithubRemote, format!("Could not determine GitHub repository from jj remote {}", pr_remote())))
 }
 
 fn extract_github_repo(url: &str) -> Option<String> {
+    // ssh remotes may name a host alias from ~/.ssh/config (git@work-github:owner/repo)
+    let url = ssh::resolve_host_alias(url);
+    let url = url.as_str();
+
     // Handle git@github.com:owner/repo.git
-    if url.starts_with("git@github.com:") {
-        let path = url.strip_prefix("git@github.com:")?;
+    if let Some((_, path)) = url.split_once("github.com:").filter(|(user, _)| user.is_empty() || (user.ends_with('@') && !user.contains('/'))) {
         let repo = path.strip_suffix(".git").unwrap_or(path);
         return Some(repo.to_string());
     }
@@ -1105,10 +5187,12 @@ fn run_command(args: &[&str], ignore_errors: bool, verbose: bool) -> Result<Stri
         eprintln!("[debug] Running: {}", args.join(" "));
     }
 
+    let started = Instant::now();
     let output = Command::new(args[0])
         .args(&args[1..])
         .output()
         .with_context(|| format!("Failed to run: {}", args.join(" ")))?;
+    record_call(args, started.elapsed());
 
     let stdout = String::from_utf8_lossy(&output.stdout).to_string();
     let stderr = String::from_utf8_lossy(&output.stderr).to_string();
@@ -1118,7 +5202,7 @@ fn run_command(args: &[&str], ignore_errors: bool, verbose: bool) -> Result<Stri
     }
 
     if !output.status.success() && !ignore_errors {
-        bail!("Command failed: {}\nStderr: {}", args.join(" "), stderr);
+        bail!(CodedError::new(ErrorCode::CommandFailed, format!("Command failed: {}\nStderr: {}", args.join(" "), stderr)));
     }
 
     Ok(stdout + &stderr)
@@ -1135,6 +5219,8 @@ fn track_operation_start(state: &mut State, op_type: &str, revisions: &[Revision
         timestamp,
         changes_affected: revisions.iter().map(|r| r.change_id.clone()).collect(),
         success: false,
+        actions: Vec::new(),
+        calls: BTreeMap::new(),
     });
 
     // Keep only last 50 operations
@@ -1149,73 +5235,568 @@ fn track_operation_start(state: &mut State, op_type: &str, revisions: &[Revision
 fn track_operation_end(state: &mut State, op_id: &str, success: bool) -> Result<()> {
     if let Some(op) = state.operations.iter_mut().find(|o| o.id == op_id) {
         op.success = success;
+        op.actions = std::mem::take(&mut *ACTIONS.lock().unwrap());
+        op.calls = CALLS.lock().unwrap().iter().map(|(category, (count, _))| (category.clone(), *count)).collect();
     }
     state.last_operation_id = Some(op_id.to_string());
     Ok(())
 }
 
-// Detect split commits
-fn detect_split_commits(current: &[Revision], _state: &State, verbose: bool) -> Result<Vec<SplitOperation>> {
-    let mut splits = Vec::new();
-    let split_pattern = regex::Regex::new(r"^\((\d+)/(\d+)\)\s+(.+)").unwrap();
+// Mutating commands skipped by --dry-run, printed as the plan at the end of the run
+static PLAN: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
-    // Group commits by base description
-    let mut groups: HashMap<String, Vec<&Revision>> = HashMap::new();
+// Run a command that changes the repository or GitHub. In dry-run mode the
+// exact command is added to the plan instead and empty output is returned.
+fn run_mutation(args: &[&str], ignore_errors: bool, dry_run: bool, verbose: bool) -> Result<String> {
+    if !dry_run {
+        return run_command(args, ignore_errors, verbose);
+    }
 
-    for rev in current {
-        if let Some(captures) = split_pattern.captures(&rev.description) {
-            let base_msg = captures.get(3).unwrap().as_str().to_string();
-            groups.entry(base_msg).or_default().push(rev);
+    let command = args.iter().map(|arg| {
+        if arg.contains('\n') {
+            format!("<{} lines>", arg.lines().count())
+        } else if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || "'\"$`\\|&;()<>*?".contains(c)) {
+            format!("'{}'", arg.replace('\'', "'\\''"))
+        } else {
+            arg.to_string()
         }
+    }).collect::<Vec<_>>().join(" ");
+    if verbose {
+        eprintln!("[debug] Would run: {}", command);
+    }
+    PLAN.lock().unwrap().push(command);
+    Ok(String::new())
+}
+
+// Add and remove labels on a PR (labels live on the PR's issue)
+fn edit_labels(forge: &dyn forge::Forge, repo: &str, number: u32, add: &[&str], remove: &[&str], verbose: bool) -> Result<()> {
+    if !add.is_empty() {
+        forge.rest("POST", &format!("repos/{}/issues/{}/labels", repo, number), serde_json::json!({ "labels": add }), verbose)?;
+    }
+    for label in remove {
+        forge.rest("DELETE", &format!("repos/{}/issues/{}/labels/{}", repo, number, forge::encode_path_segment(label)),
+                   serde_json::Value::Null, verbose)?;
     }
+    Ok(())
+}
+
+// Fields PrCache usually fetches for every PR, named as for view_pr
+const PR_CACHE_FIELDS: &[&str] = &["number", "url", "state", "headRefName", "baseRefName", "mergeable", "mergeStateStatus", "mergedAt"];
+
+// PRs fetched by number in one GraphQL query rather than a view_pr each.
+// Lookups of a PR it doesn't hold, or of fields it didn't fetch, fall back to
+// view_pr, so a failed batch only costs the round trips it was meant to save.
+struct PrCache<'a> {
+    repo: &'a str,
+    prs: HashMap<u32, serde_json::Value>,
+}
 
-    // Create split operations for grouped commits
-    for (base_msg, revs) in groups {
-        if revs.len() > 1 {
+impl<'a> PrCache<'a> {
+    fn fetch(repo: &'a str, numbers: impl IntoIterator<Item = u32>, fields: &[&str], verbose: bool) -> Self {
+        let mut numbers: Vec<u32> = numbers.into_iter().collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        let prs = fetch_prs(repo, &numbers, fields, verbose).unwrap_or_else(|e| {
             if verbose {
-                eprintln!("  Detected split commit: '{}' split into {} parts", base_msg, revs.len());
+                eprintln!("[debug] Batched PR lookup failed, viewing PRs one by one: {:#}", e);
             }
-            splits.push(SplitOperation {
-                original_message: base_msg,
-                new_change_ids: revs.iter().map(|r| r.change_id.clone()).collect(),
-            });
+            HashMap::new()
+        });
+        Self { repo, prs }
+    }
+
+    fn get(&self, number: u32, fields: &[&str], verbose: bool) -> serde_json::Value {
+        match self.prs.get(&number) {
+            Some(pr) if fields.iter().all(|field| pr.get(field).is_some()) => pr.clone(),
+            _ => view_pr(self.repo, &number.to_string(), fields, verbose),
+        }
+    }
+}
+
+// The fields of each PR in `numbers`. A number without a PR fails the whole
+// query, as GraphQL reports it as an error.
+fn fetch_prs(repo: &str, numbers: &[u32], fields: &[&str], verbose: bool) -> Result<HashMap<u32, serde_json::Value>> {
+    // PRs looked up per GraphQL request
+    const BATCH: usize = 50;
+
+    let (owner, name) = repo.split_once('/').with_context(|| format!("Invalid repository {}", repo))?;
+    let fields = fields.join(" ");
+    let mut prs = HashMap::new();
+    for batch in numbers.chunks(BATCH) {
+        let lookups: String = batch.iter()
+            .map(|number| format!("p{}: pullRequest(number: {}) {{ {} }} ", number, number, fields))
+            .collect();
+        let query = format!("query($owner: String!, $name: String!) {{ repository(owner: $owner, name: $name) {{ {}}} }}", lookups);
+        let data = forge::forge().graphql(&query, serde_json::json!({ "owner": owner, "name": name }), verbose)?;
+        for &number in batch {
+            let pr = &data["repository"][format!("p{}", number)];
+            if pr.is_object() {
+                prs.insert(number, pr.clone());
+            }
+        }
+    }
+    Ok(prs)
+}
+
+// A PR by number or branch with the given fields, or Null when there is no
+// such PR or GitHub couldn't be asked; callers treat missing fields as unknown
+fn view_pr(repo: &str, pr: &str, fields: &[&str], verbose: bool) -> serde_json::Value {
+    match forge::forge().view_pr(repo, pr, fields, verbose) {
+        Ok(pr) => pr.unwrap_or_default(),
+        Err(e) => {
+            if verbose {
+                eprintln!("[debug] {:#}", e);
+            }
+            serde_json::Value::Null
+        }
+    }
+}
+
+// Make a change on GitHub through the forge; in a dry run the equivalent gh
+// command goes into the plan instead. With ignore_errors a failure is only
+// reported with --verbose, as for a command run with ignore_errors.
+fn forge_mutation(plan: &[&str], ignore_errors: bool, dry_run: bool, verbose: bool,
+                  change: impl FnOnce(&dyn forge::Forge) -> Result<()>) -> Result<()> {
+    if dry_run {
+        run_mutation(plan, ignore_errors, dry_run, verbose)?;
+        return Ok(());
+    }
+    match change(forge::forge()) {
+        Err(e) if ignore_errors => {
+            if verbose {
+                eprintln!("[debug] {:#}", e);
+            }
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn print_plan() {
+    let plan = PLAN.lock().unwrap();
+    if plan.is_empty() {
+        eprintln!("\nDry run: nothing to do");
+        return;
+    }
+    eprintln!("\nDry run: {} operation{} would run:", plan.len(), if plan.len() == 1 { "" } else { "s" });
+    for command in plan.iter() {
+        eprintln!("  {}", command);
+    }
+}
+
+// Subprocess calls made during the current run: count and total time per category
+static CALLS: Mutex<BTreeMap<String, (usize, Duration)>> = Mutex::new(BTreeMap::new());
+
+// Category of a jj/gh invocation, e.g. "jj log", "jj git push", "gh pr create", "gh api"
+fn call_category(args: &[&str]) -> String {
+    let mut parts = vec![args[0]];
+    if let Some(command) = args.get(1) {
+        parts.push(command);
+        if matches!(*command, "git" | "bookmark" | "op" | "pr" | "repo" | "gist" | "auth") {
+            parts.extend(args.get(2));
+        }
+    }
+    parts.join(" ")
+}
+
+fn record_call(args: &[&str], elapsed: Duration) {
+    crash::record_command(args);
+    let mut calls = CALLS.lock().unwrap();
+    let entry = calls.entry(call_category(args)).or_default();
+    entry.0 += 1;
+    entry.1 += elapsed;
+}
+
+// Print the per-category call counts and timings collected during this run
+fn print_call_report() {
+    let calls = CALLS.lock().unwrap();
+    let (mut total_count, mut total_time) = (0, Duration::ZERO);
+
+    eprintln!("\nCalls:");
+    for (category, (count, elapsed)) in calls.iter() {
+        eprintln!("  {:<24} {:>4}  {:>8.2}s", category, count, elapsed.as_secs_f64());
+        total_count += count;
+        total_time += *elapsed;
+    }
+    let jj = calls.iter().filter(|(c, _)| c.starts_with("jj ")).map(|(_, (n, _))| n).sum::<usize>();
+    eprintln!("  {:<24} {:>4}  {:>8.2}s", "total", total_count, total_time.as_secs_f64());
+    eprintln!("  ({} jj, {} GitHub)", jj, total_count - jj);
+}
+
+// Actions performed during the current run, attached to its operation record
+static ACTIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn record_action(action: String) {
+    ACTIONS.lock().unwrap().push(action);
+}
+
+// Find changes that `jj split` made out of a change with a PR. Each new
+// piece's evolution log leads back to the original change; a lone successor
+// is a squash or rewrite, not a split.
+fn detect_split_commits(current: &[Revision], state: &State, verbose: bool) -> Result<Vec<SplitOperation>> {
+    let tracked = |change_id: &str| state.prs.iter()
+        .find(|(id, _)| id.starts_with(change_id) || change_id.starts_with(id.as_str()))
+        .map(|(id, info)| (id.clone(), info.pr_number));
+    let mut splits: Vec<SplitOperation> = Vec::new();
+
+    for rev in current {
+        if tracked(&rev.change_id).is_some() {
+            continue;
+        }
+        let original = predecessor_change_ids(&rev.change_id, verbose)?.iter().find_map(|id| tracked(id));
+        let Some((original_change_id, pr_number)) = original else { continue };
+
+        match splits.iter_mut().find(|s| s.original_change_id == original_change_id) {
+            Some(split) => split.new_change_ids.push(rev.change_id.clone()),
+            None => splits.push(SplitOperation {
+                original_change_id,
+                pr_number,
+                new_change_ids: vec![rev.change_id.clone()],
+                heir: None,
+            }),
+        }
+    }
+
+    splits.retain(|split| {
+        let survives = current.iter().any(|r| {
+            r.change_id.starts_with(&split.original_change_id) || split.original_change_id.starts_with(&r.change_id)
+        });
+        split.new_change_ids.len() + usize::from(survives) > 1
+    });
+    if verbose {
+        for split in &splits {
+            eprintln!("  Detected split of {} (PR #{}) into {} new change(s)",
+                     &split.original_change_id[..8.min(split.original_change_id.len())], split.pr_number, split.new_change_ids.len());
         }
     }
 
     Ok(splits)
 }
 
+// Change IDs a change's commits were rewritten from, newest first
+fn predecessor_change_ids(change_id: &str, verbose: bool) -> Result<Vec<String>> {
+    let args = |template| ["jj", "evolog", "-r", change_id, "--no-graph", "--template", template];
+    let mut output = run_command(&args(r#"commit.change_id() ++ "\n""#), true, verbose)?;
+    if output.contains("Error:") {
+        // Before jj 0.30 evolog entries were plain commits
+        output = run_command(&args(r#"change_id ++ "\n""#), true, verbose)?;
+        if output.contains("Error:") {
+            return Ok(Vec::new());
+        }
+    }
+
+    let mut ids: Vec<String> = Vec::new();
+    for id in output.lines().map(str::trim) {
+        if !id.is_empty() && !id.starts_with(change_id) && !change_id.starts_with(id) && !ids.iter().any(|i| i == id) {
+            ids.push(id.to_string());
+        }
+    }
+    Ok(ids)
+}
+
 #[derive(Debug)]
 struct SplitOperation {
-    original_message: String,
+    original_change_id: String,
+    pr_number: u32,
     new_change_ids: Vec<String>,
+    // New change that took over the PR because the original change is gone
+    heir: Option<String>,
 }
 
-// Handle split commits
+// Keep each split change's PR on one of its pieces. A piece that kept the
+// original change ID keeps the PR; otherwise split_pr picks the successor,
+// which inherits the PR's branch. The remaining pieces get new PRs.
 fn handle_split_commits(
-    splits: &[SplitOperation],
-    revisions: &mut [Revision],
-    _state: &mut State,
-    dry_run: bool,
+    splits: &mut [SplitOperation],
+    revisions: &[Revision],
+    state: &mut State,
+    config: &Config,
     verbose: bool
 ) -> Result<()> {
-    for split in splits {
-        if verbose {
-            eprintln!("Handling split commit: {} -> {} parts",
-                     split.original_message, split.new_change_ids.len());
+    for split in splits.iter_mut() {
+        let short = &split.original_change_id[..8.min(split.original_change_id.len())];
+        let pieces: Vec<&Revision> = revisions.iter()
+            .filter(|r| split.new_change_ids.contains(&r.change_id))
+            .collect();
+        if pieces.is_empty() {
+            continue;
         }
 
-        // Mark revisions as part of a split
-        for rev in revisions.iter_mut() {
-            if split.new_change_ids.contains(&rev.change_id) {
-                // Add note to PR description about split
-                if !dry_run && rev.pr_number.is_some() {
-                    // This will be handled in PR description update
-                    if verbose {
-                        eprintln!("  Marking {} as part of split", &rev.change_id[..8]);
+        let survives = revisions.iter().any(|r| {
+            r.change_id.starts_with(&split.original_change_id) || split.original_change_id.starts_with(&r.change_id)
+        });
+        if survives {
+            eprintln!("{} was split; it keeps PR #{} and {} new piece(s) get their own PRs",
+                     short, split.pr_number, pieces.len());
+            continue;
+        }
+
+        let heir = match config.split_pr {
+            SplitSuccessor::First => pieces[0],
+            SplitSuccessor::Largest => {
+                let mut largest = (pieces[0], 0);
+                for piece in &pieces {
+                    let stat = run_command(&["jj", "diff", "--stat", "-r", &piece.commit_id], true, verbose)?;
+                    let (lines, _) = diff_stat_lines(&stat);
+                    if lines > largest.1 {
+                        largest = (piece, lines);
                     }
                 }
+                largest.0
+            }
+        };
+
+        if !transfer_pr(state, &split.original_change_id, &heir.change_id) {
+            continue;
+        }
+        eprintln!("{} was split; PR #{} continues with \"{}\" ({})",
+                 short, split.pr_number, heir.description, &heir.change_id[..8.min(heir.change_id.len())]);
+        record_action(format!("Moved PR #{} from split change {} to {}", split.pr_number, short, heir.change_id));
+        split.heir = Some(heir.change_id.clone());
+    }
+    Ok(())
+}
+
+// Move a change's PR onto another change, which pushes to the PR's branch from now on
+fn transfer_pr(state: &mut State, from: &str, to: &str) -> bool {
+    let Some(pr_info) = state.prs.remove(from) else { return false };
+    state.inherited_branches.insert(to.to_string(), pr_info.branch_name.clone());
+    if let Some(base) = state.base_overrides.remove(from) {
+        state.base_overrides.insert(to.to_string(), base);
+    }
+    state.prs.insert(to.to_string(), PrInfo { change_id: Some(to.to_string()), ..pr_info });
+    true
+}
+
+// Find changes that `jj squash` folded into another change of the stack: the
+// surviving change's evolution log includes the folded one
+fn detect_folded_changes(current: &[Revision], state: &State, splits: &[SplitOperation], verbose: bool) -> Result<Vec<FoldOperation>> {
+    let same = |a: &str, b: &str| a.starts_with(b) || b.starts_with(a);
+    let gone: Vec<(&String, u32)> = state.prs.iter()
+        .filter(|(id, _)| !current.iter().any(|r| same(id, &r.change_id)))
+        .filter(|(id, _)| !state.merged_prs.iter().any(|m| same(id, m)))
+        .filter(|(id, _)| !splits.iter().any(|s| &s.original_change_id == *id))
+        .map(|(id, info)| (id, info.pr_number))
+        .collect();
+    if gone.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut folds: Vec<FoldOperation> = Vec::new();
+    for rev in current {
+        for predecessor in predecessor_change_ids(&rev.change_id, verbose)? {
+            let Some((change_id, pr_number)) = gone.iter().find(|(id, _)| same(id, &predecessor)) else { continue };
+            if folds.iter().any(|f| &f.change_id == *change_id) {
+                continue;
+            }
+            if verbose {
+                eprintln!("  Detected {} (PR #{}) folded into {}", &change_id[..8.min(change_id.len())], pr_number, &rev.change_id[..8]);
+            }
+            folds.push(FoldOperation {
+                change_id: change_id.to_string(),
+                pr_number: *pr_number,
+                into: rev.change_id.clone(),
+                into_pr: None,
+            });
+        }
+    }
+    Ok(folds)
+}
+
+// Find changes that were abandoned and recreated under a new change ID (jj
+// duplicate + abandon, a re-imported patch): an untracked revision with the
+// same title as a vanished change's last pushed commit and mostly the same diff
+fn detect_recreated_changes(current: &[Revision], state: &State, splits: &[SplitOperation], folds: &[FoldOperation], verbose: bool) -> Result<Vec<RecreatedChange>> {
+    let same = |a: &str, b: &str| a.starts_with(b) || b.starts_with(a);
+    let gone: Vec<(&String, &PrInfo)> = state.prs.iter()
+        .filter(|(id, _)| !current.iter().any(|r| same(id, &r.change_id)))
+        .filter(|(id, _)| !state.merged_prs.iter().any(|m| same(id, m)))
+        .filter(|(id, _)| !splits.iter().any(|s| &s.original_change_id == *id))
+        .filter(|(id, _)| !folds.iter().any(|f| &f.change_id == *id))
+        .collect();
+    let untracked: Vec<&Revision> = current.iter()
+        .filter(|r| !state.prs.keys().any(|id| same(id, &r.change_id)))
+        .filter(|r| !splits.iter().any(|s| s.new_change_ids.contains(&r.change_id)))
+        .collect();
+    if gone.is_empty() || untracked.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut recreated: Vec<RecreatedChange> = Vec::new();
+    for (change_id, pr_info) in gone {
+        // The vanished commit is hidden but can still be looked up by its ID
+        let description = run_command(&["jj", "log", "-r", &pr_info.commit_id, "--no-graph", "--template", "description"], true, verbose)?;
+        if description.contains("Error:") {
+            continue;
+        }
+        let title = description.lines().next().unwrap_or_default().trim();
+        let candidates: Vec<&&Revision> = untracked.iter()
+            .filter(|r| !title.is_empty() && r.description.trim().eq_ignore_ascii_case(title))
+            .filter(|r| !recreated.iter().any(|c| c.new_change_id == r.change_id))
+            .collect();
+        if candidates.is_empty() {
+            continue;
+        }
+
+        let old_diff = run_command(&["jj", "diff", "-r", &pr_info.commit_id, "--git"], true, verbose)?;
+        let mut best: Option<(&Revision, f64)> = None;
+        for rev in candidates {
+            let new_diff = run_command(&["jj", "diff", "-r", &rev.change_id, "--git"], false, verbose)?;
+            let similarity = stack::diff_similarity(&old_diff, &new_diff);
+            if similarity >= RECREATED_SIMILARITY && best.is_none_or(|(_, s)| similarity > s) {
+                best = Some((rev, similarity));
+            }
+        }
+        let Some((rev, similarity)) = best else { continue };
+
+        if verbose {
+            eprintln!("  {} (PR #{}) looks recreated as {} ({:.0}% of the diff matches)",
+                     &change_id[..8.min(change_id.len())], pr_info.pr_number, &rev.change_id[..8], similarity * 100.0);
+        }
+        recreated.push(RecreatedChange {
+            old_change_id: change_id.clone(),
+            new_change_id: rev.change_id.clone(),
+            pr_number: pr_info.pr_number,
+            identical: similarity >= 1.0 && title == rev.description
+                && description.split_once('\n').map_or("", |(_, body)| body).trim() == rev.body,
+            similarity,
+        });
+    }
+    Ok(recreated)
+}
+
+#[derive(Debug)]
+struct RecreatedChange {
+    old_change_id: String,
+    new_change_id: String,
+    pr_number: u32,
+    // Same description and diff, so adopting needs no confirmation
+    identical: bool,
+    similarity: f64,
+}
+
+// Let recreated changes take over the PR of the change they replace, keeping
+// its review history. Identical ones are adopted outright; the rest are offered
+// in a terminal and otherwise left to be closed as orphans.
+fn adopt_recreated_changes(recreated: &[RecreatedChange], state: &mut State, dry_run: bool) -> Result<()> {
+    for change in recreated {
+        let (old, new) = (&change.old_change_id[..8.min(change.old_change_id.len())], &change.new_change_id[..8]);
+        eprintln!("{} looks like a recreation of {} (PR #{}): same title, {:.0}% of the diff matches",
+                 new, old, change.pr_number, change.similarity * 100.0);
+
+        let adopt = if dry_run || change.identical {
+            true
+        } else if std::io::stdin().is_terminal() {
+            prompt_yes_no(&format!("Continue PR #{} with {}?", change.pr_number, new))?
+        } else {
+            eprintln!("  Not adopting PR #{} without confirmation; run in a terminal to adopt it", change.pr_number);
+            false
+        };
+        if !adopt {
+            continue;
+        }
+
+        // A dry run moves the PR too, so the plan doesn't close it as an orphan
+        if !transfer_pr(state, &change.old_change_id, &change.new_change_id) {
+            continue;
+        }
+        if dry_run {
+            eprintln!("Would move PR #{} from {} to {}", change.pr_number, old, new);
+        } else {
+            eprintln!("PR #{} continues with {}", change.pr_number, new);
+            record_action(format!("Moved PR #{} from recreated change {} to {}", change.pr_number, old, change.new_change_id));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct FoldOperation {
+    change_id: String,
+    pr_number: u32,
+    into: String,
+    // PR of the surviving change, which the folded change's PR is closed in favor of
+    into_pr: Option<u32>,
+}
+
+// Keep review history of folded changes: a surviving change without a PR
+// takes over the folded change's PR; one with a PR gets the folded PR closed
+// in its favor once the stack is pushed
+fn handle_folded_changes(folds: &mut [FoldOperation], state: &mut State, verbose: bool) -> Result<()> {
+    for fold in folds.iter_mut() {
+        let short = &fold.change_id[..8.min(fold.change_id.len())];
+        let into_pr = state.prs.iter()
+            .find(|(id, _)| id.starts_with(&fold.into) || fold.into.starts_with(id.as_str()))
+            .map(|(_, info)| info.pr_number);
+
+        match into_pr {
+            Some(number) => {
+                eprintln!("{} was folded into {}; PR #{} will be closed in favor of #{}",
+                         short, &fold.into[..8], fold.pr_number, number);
+                fold.into_pr = Some(number);
+            }
+            None => {
+                if transfer_pr(state, &fold.change_id, &fold.into) {
+                    eprintln!("{} was folded into {}; PR #{} continues with it", short, &fold.into[..8], fold.pr_number);
+                    record_action(format!("Moved PR #{} from folded change {} to {}", fold.pr_number, short, fold.into));
+                } else if verbose {
+                    eprintln!("  No PR left to move for folded change {}", short);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Close the PRs of changes folded into a change that has its own PR, linking the two
+fn close_folded_prs(folds: &[FoldOperation], state: &mut State, repo: &str, delete_branches: bool, dry_run: bool, verbose: bool) -> Result<()> {
+    for fold in folds {
+        let Some(into_pr) = fold.into_pr else { continue };
+        let Some(pr_info) = state.prs.remove(&fold.change_id) else { continue };
+
+        if !dry_run {
+            eprintln!("Closing PR #{} (folded into #{})", fold.pr_number, into_pr);
+        }
+        let comment = format!("This change was folded into #{}, which now contains it.", into_pr);
+        forge_mutation(&["gh", "pr", "close", &fold.pr_number.to_string(), "-R", repo, "--comment", &comment], true, dry_run, verbose,
+                       |forge| forge.close_pr(repo, fold.pr_number, Some(&comment), verbose))?;
+        let comment = format!("Now includes the changes of #{}, which was folded into this PR.", fold.pr_number);
+        forge_mutation(&["gh", "pr", "comment", &into_pr.to_string(), "-R", repo, "--body", &comment], true, dry_run, verbose,
+                       |forge| forge.comment_pr(repo, into_pr, &comment, verbose))?;
+        record_action(format!("Closed PR #{} (folded into #{})", fold.pr_number, into_pr));
+        state.superseded_by.insert(fold.pr_number, into_pr);
+
+        if delete_branches {
+            delete_branch(&pr_info.branch_name, dry_run, verbose)?;
+        }
+    }
+    Ok(())
+}
+
+// Cross-reference the new PRs of split-off pieces with the PR they came from.
+// Pieces still waiting for a PR are picked up again on a later run.
+fn link_split_prs(splits: &[SplitOperation], revisions: &[Revision], repo: &str, dry_run: bool, verbose: bool) -> Result<()> {
+    for split in splits {
+        let mut linked = Vec::new();
+        for rev in revisions.iter().filter(|r| split.new_change_ids.contains(&r.change_id)) {
+            let Some(number) = rev.pr_number else { continue };
+            if number == split.pr_number || split.heir.as_ref() == Some(&rev.change_id) {
+                continue;
             }
+            let comment = format!("Split from #{}.", split.pr_number);
+            forge_mutation(&["gh", "pr", "comment", &number.to_string(), "-R", repo, "--body", &comment], true, dry_run, verbose,
+                           |forge| forge.comment_pr(repo, number, &comment, verbose))?;
+            linked.push(format!("#{}", number));
+        }
+        if linked.is_empty() {
+            continue;
+        }
+
+        let comment = format!("Split into {}.", linked.join(", "));
+        forge_mutation(&["gh", "pr", "comment", &split.pr_number.to_string(), "-R", repo, "--body", &comment], true, dry_run, verbose,
+                       |forge| forge.comment_pr(repo, split.pr_number, &comment, verbose))?;
+        if !dry_run {
+            record_action(format!("Linked PR #{} to its split-off PRs {}", split.pr_number, linked.join(", ")));
         }
     }
     Ok(())
@@ -1277,12 +5858,12 @@ fn handle_out_of_order_merge(
 
             state.prs.get(&state.stack_order[parent_pos])
                 .map(|p| p.branch_name.clone())
-                .unwrap_or_else(|| "main".to_string())
+                .unwrap_or_else(|| trunk(state).to_string())
         } else {
-            "main".to_string()
+            trunk(state).to_string()
         }
     } else {
-        "main".to_string()
+        trunk(state).to_string()
     };
 
     // Update children bases
@@ -1291,13 +5872,9 @@ fn handle_out_of_order_merge(
             eprintln!("    Updating PR #{} base to {}", child.pr_number, new_base);
         }
 
-        if !dry_run {
-            run_command(&[
-                "gh", "pr", "edit", &child.pr_number.to_string(),
-                "-R", repo,
-                "--base", &new_base
-            ], true, verbose)?;
-        }
+        forge_mutation(&["gh", "pr", "edit", &child.pr_number.to_string(), "-R", repo, "--base", &new_base], true, dry_run, verbose,
+                       |forge| forge.edit_pr(repo, child.pr_number, &PrEdit { base: Some(&new_base), ..PrEdit::default() }, verbose))?;
+        record_action(format!("Retargeted PR #{} to {}", child.pr_number, new_base));
     }
 
     Ok(())
@@ -1327,6 +5904,13 @@ fn garbage_collect_state(state: &mut State) -> Result<()> {
             })
     });
 
+    // Forget labels and reviewers of PRs that are no longer tracked
+    let tracked: HashSet<u32> = state.prs.values().map(|pr| pr.pr_number).collect();
+    state.labels.retain(|number, _| tracked.contains(number));
+    state.reviewers.retain(|number, _| tracked.contains(number));
+    state.blocked_labeled.retain(|number| tracked.contains(number));
+    state.inherited_branches.retain(|change_id, _| state.prs.contains_key(change_id));
+
     // Remove old operations
     if state.operations.len() > 100 {
         state.operations = state.operations.split_off(state.operations.len() - 100);
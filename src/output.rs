@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use almighty_push::stack::Revision;
+
+// Version of the `--format json` documents. Within a version fields are only
+// ever added; removing, renaming or changing the meaning of one bumps it.
+// The schema lives in schema/output-v1.schema.json.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Human-readable output
+    Text,
+    /// Versioned JSON documents on stdout
+    Json,
+}
+
+#[derive(Serialize)]
+struct Document<'a, T: Serialize> {
+    schema_version: u32,
+    kind: &'a str,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+fn to_json<T: Serialize>(kind: &str, data: &T) -> Result<String> {
+    let document = Document { schema_version: SCHEMA_VERSION, kind, data };
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+// Print a JSON document of the given kind ("report", "plan", "stats", ...)
+pub fn print_json<T: Serialize>(kind: &str, data: &T) -> Result<()> {
+    println!("{}", to_json(kind, data)?);
+    Ok(())
+}
+
+// A document that can be saved with --output: JSON for *.json paths, markdown otherwise
+pub trait Report: Serialize {
+    const KIND: &'static str;
+    fn to_markdown(&self) -> String;
+}
+
+pub fn write_report<T: Report>(path: &Path, report: &T) -> Result<()> {
+    let content = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+        to_json(T::KIND, report)? + "\n"
+    } else {
+        report.to_markdown()
+    };
+    fs::write(path, content).with_context(|| format!("Failed to write report to {}", path.display()))?;
+    eprintln!("Report written to {}", path.display());
+    Ok(())
+}
+
+// Outcome of a push run
+#[derive(Serialize)]
+pub struct RunReport {
+    pub repo: String,
+    pub base: String,
+    pub revisions: Vec<RevisionReport>,
+}
+
+#[derive(Serialize)]
+pub struct RevisionReport {
+    pub change_id: String,
+    pub commit_id: String,
+    pub title: String,
+    pub branch: Option<String>,
+    pub base: Option<String>,
+    pub pr_number: Option<u32>,
+    pub pr_url: Option<String>,
+    pub pr_state: Option<String>,
+    pub pr_mergeable: Option<String>,
+    pub pr_checks: Option<String>,
+    pub pr_review: Option<String>,
+}
+
+impl From<&Revision> for RevisionReport {
+    fn from(rev: &Revision) -> Self {
+        Self {
+            change_id: rev.change_id.clone(),
+            commit_id: rev.commit_id.clone(),
+            title: rev.description.clone(),
+            branch: rev.branch_name.clone(),
+            base: rev.base_branch.clone(),
+            pr_number: rev.pr_number,
+            pr_url: rev.pr_url.clone(),
+            pr_state: rev.pr_state.clone(),
+            pr_mergeable: rev.pr_mergeable.clone(),
+            pr_checks: rev.pr_checks.clone(),
+            pr_review: rev.pr_review.clone(),
+        }
+    }
+}
+
+impl Report for RunReport {
+    const KIND: &'static str = "report";
+
+    fn to_markdown(&self) -> String {
+        let mut doc = format!("## almighty-push: {} → `{}`\n\n", self.repo, self.base);
+        if self.revisions.is_empty() {
+            doc.push_str("No revisions in the stack.\n");
+            return doc;
+        }
+        doc.push_str("| # | Change | Branch | PR | State |\n");
+        doc.push_str("|---|--------|--------|----|-------|\n");
+        for (i, rev) in self.revisions.iter().enumerate() {
+            let pr = match (rev.pr_number, &rev.pr_url) {
+                (Some(number), Some(url)) => format!("[#{}]({})", number, url),
+                _ => "—".to_string(),
+            };
+            let mut state = rev.pr_state.as_deref().map(str::to_lowercase).unwrap_or_else(|| "no PR".to_string());
+            if rev.pr_mergeable.as_deref() == Some("CONFLICTING") {
+                state.push_str(", conflicts with base");
+            }
+            if let Some(icon) = checks_icon(rev.pr_checks.as_deref()) {
+                state.push_str(&format!(" {}", icon));
+            }
+            doc.push_str(&format!("| {} | {} (`{}`) | `{}` | {} | {} |\n",
+                                  i + 1, rev.title.replace('|', "\\|"), &rev.change_id[..8.min(rev.change_id.len())],
+                                  rev.branch.as_deref().unwrap_or("—"), pr, state));
+        }
+        doc
+    }
+}
+
+// ✅, ❌ or 🟡 for the combined state of a PR's CI checks
+pub fn checks_icon(checks: Option<&str>) -> Option<&'static str> {
+    match checks? {
+        "SUCCESS" => Some("✅"),
+        "FAILURE" | "ERROR" => Some("❌"),
+        "PENDING" | "EXPECTED" => Some("🟡"),
+        _ => None,
+    }
+}
+
+// A change and its PR, as resolved by `find`
+#[derive(Serialize)]
+pub struct PrLookup {
+    pub change_id: Option<String>,
+    pub branch: Option<String>,
+    pub pr_number: u32,
+    pub pr_url: Option<String>,
+    pub pr_state: Option<String>,
+}
+
+// Problems found by `verify`
+#[derive(Serialize)]
+pub struct VerifyReport {
+    pub repo: String,
+    pub base: String,
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Serialize)]
+pub struct Issue {
+    /// base, push, pr, state, linearity, conflict or divergence
+    pub check: &'static str,
+    pub change_id: Option<String>,
+    pub pr_number: Option<u32>,
+    pub message: String,
+}
+
+impl Report for VerifyReport {
+    const KIND: &'static str = "verify";
+
+    fn to_markdown(&self) -> String {
+        let mut doc = format!("## almighty-push verify: {} → `{}`\n\n", self.repo, self.base);
+        if self.issues.is_empty() {
+            doc.push_str("No problems found.\n");
+            return doc;
+        }
+        doc.push_str("| Check | Change | PR | Problem |\n");
+        doc.push_str("|-------|--------|----|---------|\n");
+        for issue in &self.issues {
+            doc.push_str(&format!("| {} | {} | {} | {} |\n", issue.check,
+                                  issue.change_id.as_deref().map_or("—".to_string(), |id| format!("`{}`", &id[..8.min(id.len())])),
+                                  issue.pr_number.map_or("—".to_string(), |n| format!("#{}", n)),
+                                  issue.message.replace('|', "\\|")));
+        }
+        doc
+    }
+}
+
+// Commands a dry run would have executed
+#[derive(Serialize)]
+pub struct Plan {
+    pub commands: Vec<String>,
+}
+
+impl Report for Plan {
+    const KIND: &'static str = "plan";
+
+    fn to_markdown(&self) -> String {
+        if self.commands.is_empty() {
+            return "## almighty-push dry run\n\nNothing to do.\n".to_string();
+        }
+        format!("## almighty-push dry run\n\n{} operation{} would run:\n\n```sh\n{}\n```\n",
+                self.commands.len(), if self.commands.len() == 1 { "" } else { "s" }, self.commands.join("\n"))
+    }
+}
@@ -0,0 +1,68 @@
+use regex::Regex;
+
+// Whether a repository-relative path matches a glob: `*` and `?` stay within
+// one path component, `**` spans directories (`auth/**`, `**/*.sql`)
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let mut regex = String::from("^");
+    let mut chars = pattern.trim_start_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+
+    Regex::new(&regex).is_ok_and(|re| re.is_match(path))
+}
+
+// Whether any of the paths matches any of the globs
+pub fn any_match(patterns: &[String], paths: &[String]) -> bool {
+    patterns.iter().any(|pattern| paths.iter().any(|path| glob_matches(pattern, path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_star_stays_within_a_component() {
+        assert!(glob_matches("src/*.rs", "src/main.rs"));
+        assert!(!glob_matches("src/*.rs", "src/bin/main.rs"));
+        assert!(glob_matches("file?.txt", "file1.txt"));
+        assert!(!glob_matches("file?.txt", "file/.txt"));
+    }
+
+    #[test]
+    fn double_star_spans_directories() {
+        assert!(glob_matches("auth/**", "auth/login/form.rs"));
+        assert!(glob_matches("**/*.sql", "schema.sql"));
+        assert!(glob_matches("**/*.sql", "db/migrations/001.sql"));
+        assert!(!glob_matches("auth/**", "billing/auth.rs"));
+    }
+
+    #[test]
+    fn patterns_are_anchored_and_literal() {
+        assert!(glob_matches("/docs/*.md", "docs/intro.md"));
+        assert!(!glob_matches("docs/*.md", "site/docs/intro.md"));
+        assert!(glob_matches("a+b.(c)", "a+b.(c)"));
+        assert!(!glob_matches("a.c", "abc"));
+    }
+
+    #[test]
+    fn any_match_needs_one_pair() {
+        let patterns = vec!["docs/**".to_string(), "*.toml".to_string()];
+        assert!(any_match(&patterns, &["src/lib.rs".to_string(), "Cargo.toml".to_string()]));
+        assert!(!any_match(&patterns, &["src/lib.rs".to_string()]));
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+
+use crate::paths;
+
+// Replace an ssh host alias in a remote URL (`git@work-github:org/repo.git`,
+// `ssh://work-github/org/repo`) with the HostName ~/.ssh/config gives it.
+// Other URLs, and hosts without a HostName, are returned unchanged.
+pub fn resolve_host_alias(url: &str) -> String {
+    let Some((prefix, host, rest)) = split_ssh_url(url) else { return url.to_string() };
+    let Some(config) = std::env::var_os("HOME")
+        .and_then(|home| fs::read_to_string(std::path::Path::new(&home).join(".ssh/config")).ok()) else {
+        return url.to_string();
+    };
+
+    match hostname_for(&config, host) {
+        // GitHub's ssh-over-443 endpoint serves the same repositories
+        Some(hostname) if hostname.eq_ignore_ascii_case("ssh.github.com") => format!("{}github.com{}", prefix, rest),
+        Some(hostname) => format!("{}{}{}", prefix, hostname, rest),
+        None => url.to_string(),
+    }
+}
+
+// (everything before the host, host, everything after it) of an ssh remote URL
+fn split_ssh_url(url: &str) -> Option<(&str, &str, &str)> {
+    if let Some(after_scheme) = url.strip_prefix("ssh://") {
+        let start = url.len() - after_scheme.len() + after_scheme.find('@').map_or(0, |at| at + 1);
+        let end = start + url[start..].find([':', '/'])?;
+        return Some((&url[..start], &url[start..end], &url[end..]));
+    }
+    if url.contains("://") {
+        return None;
+    }
+
+    // scp-like syntax: [user@]host:path
+    let colon = url.find(':')?;
+    let start = url[..colon].find('@').map_or(0, |at| at + 1);
+    Some((&url[..start], &url[start..colon], &url[colon..]))
+}
+
+// HostName for `alias`; like ssh, the first matching Host block that sets it
+// wins. Host patterns match case-insensitively; %h expands to the alias as given.
+fn hostname_for(config: &str, alias: &str) -> Option<String> {
+    let lowercase = alias.to_lowercase();
+    let mut matching = false;
+
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(|c: char| c.is_whitespace() || c == '=') else { continue };
+        let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim();
+
+        match key.to_lowercase().as_str() {
+            "host" => matching = host_matches(value, &lowercase),
+            // Match blocks depend on more than the host name; don't guess
+            "match" => matching = false,
+            "hostname" if matching => return Some(value.replace("%h", alias)),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Whether a Host line's patterns (`*`, `?`, `!negated`) select the alias
+fn host_matches(patterns: &str, alias: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace().map(str::to_lowercase) {
+        match pattern.strip_prefix('!') {
+            Some(negated) if paths::glob_matches(negated, alias) => return false,
+            Some(_) => {}
+            None => matched |= paths::glob_matches(&pattern, alias),
+        }
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# work account
+Host work-github
+    HostName github.com
+    User git
+
+Host *.corp !legacy.corp
+    HostName=%h.example.com
+
+Host legacy.corp
+    HostName old.example.com
+
+Match host other
+    HostName ignored.example.com
+";
+
+    #[test]
+    fn resolves_aliases_from_the_first_matching_block() {
+        assert_eq!(hostname_for(CONFIG, "work-github").as_deref(), Some("github.com"));
+        assert_eq!(hostname_for(CONFIG, "WORK-GITHUB").as_deref(), Some("github.com"));
+        assert_eq!(hostname_for(CONFIG, "git.corp").as_deref(), Some("git.corp.example.com"));
+    }
+
+    #[test]
+    fn expands_the_alias_as_given() {
+        assert_eq!(hostname_for(CONFIG, "Git.CORP").as_deref(), Some("Git.CORP.example.com"));
+    }
+
+    #[test]
+    fn honours_negated_patterns_and_skips_match_blocks() {
+        assert_eq!(hostname_for(CONFIG, "legacy.corp").as_deref(), Some("old.example.com"));
+        assert_eq!(hostname_for(CONFIG, "other"), None);
+        assert_eq!(hostname_for(CONFIG, "github.com"), None);
+    }
+
+    #[test]
+    fn splits_ssh_urls_around_the_host() {
+        assert_eq!(split_ssh_url("git@work-github:org/repo.git"), Some(("git@", "work-github", ":org/repo.git")));
+        assert_eq!(split_ssh_url("work-github:org/repo"), Some(("", "work-github", ":org/repo")));
+        assert_eq!(split_ssh_url("ssh://git@work-github/org/repo"), Some(("ssh://git@", "work-github", "/org/repo")));
+        assert_eq!(split_ssh_url("https://github.com/org/repo"), None);
+    }
+}
@@ -0,0 +1,285 @@
+//! The stack model: jj revisions above a base, ordered bottom to top.
+
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::process::Command;
+
+// Change ID of jj's root commit, which is never part of a stack
+const ROOT_CHANGE_ID: &str = "zzzzzzzzzzzz";
+
+/// `jj log` template producing the lines [`parse_log`] reads
+pub const LOG_TEMPLATE: &str = r#"change_id ++ "|" ++ commit_id ++ "|" ++ if(description, description.first_line(), "(no description)") ++ "|" ++ if(conflict, "true", "false") ++ "|" ++ parents.map(|p| p.change_id()).join(",") ++ "\n""#;
+
+/// Separates the entries of a description query, since descriptions can span lines
+pub const DESCRIPTION_SEPARATOR: &str = "@@almighty-push-description@@";
+
+/// `jj log` template producing the output [`parse_description_bodies`] reads
+pub const DESCRIPTION_TEMPLATE: &str = r#"change_id ++ "\n" ++ description ++ "\n@@almighty-push-description@@\n""#;
+
+/// A revision in the stack, with the PR it is pushed as once that is known
+#[derive(Debug, Clone)]
+pub struct Revision {
+    pub change_id: String,
+    pub commit_id: String,
+    /// First line of the description
+    pub description: String,
+    /// Description after the first line
+    pub body: String,
+    pub branch_name: Option<String>,
+    pub base_branch: Option<String>,
+    pub pr_number: Option<u32>,
+    pub pr_url: Option<String>,
+    pub pr_state: Option<String>,
+    /// GitHub's merge check for an open PR: MERGEABLE, CONFLICTING or UNKNOWN
+    pub pr_mergeable: Option<String>,
+    /// Combined state of the CI checks on the PR's head commit: SUCCESS, FAILURE, ERROR, PENDING or EXPECTED
+    pub pr_checks: Option<String>,
+    /// GitHub's review decision for an open PR: APPROVED, CHANGES_REQUESTED or REVIEW_REQUIRED
+    pub pr_review: Option<String>,
+    pub has_conflicts: bool,
+    pub parent_change_ids: Vec<String>,
+    /// Files the revision changes; only filled in when something needs them
+    pub changed_paths: Vec<String>,
+}
+
+/// A linear stack of revisions, ordered bottom (closest to the base) to top
+#[derive(Debug, Clone, Default)]
+pub struct Stack {
+    revisions: Vec<Revision>,
+    skipped: usize,
+}
+
+impl Stack {
+    /// Load the stack for a jj revset, e.g. `main@origin..@`, by running `jj log`
+    /// in the current directory. Commits without a description are left out, and
+    /// so are side branches merged into the bottom of the stack.
+    pub fn from_revset(revset: &str) -> Result<Self> {
+        let log = jj_log(revset, LOG_TEMPLATE)?;
+        let descriptions = jj_log(revset, DESCRIPTION_TEMPLATE)?;
+        Ok(Self::from_jj_output(&log, &descriptions, false))
+    }
+
+    /// Build the stack from the output of `jj log --no-graph` with [`LOG_TEMPLATE`]
+    /// and [`DESCRIPTION_TEMPLATE`] over the same revset
+    pub fn from_jj_output(log: &str, descriptions: &str, verbose: bool) -> Self {
+        let (mut revisions, skipped) = parse_log(log, verbose);
+        drop_out_of_stack_parents(&mut revisions, verbose);
+
+        let bodies = parse_description_bodies(descriptions);
+        for rev in &mut revisions {
+            if let Some(body) = bodies.get(&rev.change_id) {
+                rev.body = body.clone();
+            }
+        }
+
+        revisions.reverse(); // jj lists newest first
+        Self { revisions, skipped }
+    }
+
+    /// Revisions, bottom to top
+    pub fn revisions(&self) -> &[Revision] {
+        &self.revisions
+    }
+
+    pub fn into_revisions(self) -> Vec<Revision> {
+        self.revisions
+    }
+
+    /// Number of commits left out because they have no description
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn len(&self) -> usize {
+        self.revisions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.revisions.is_empty()
+    }
+
+    /// Revision directly above the base
+    pub fn bottom(&self) -> Option<&Revision> {
+        self.revisions.first()
+    }
+
+    pub fn top(&self) -> Option<&Revision> {
+        self.revisions.last()
+    }
+
+    /// Revision whose change ID starts with `prefix` (or is a prefix of `prefix`)
+    pub fn find(&self, prefix: &str) -> Option<&Revision> {
+        self.revisions.iter()
+            .find(|r| r.change_id.starts_with(prefix) || prefix.starts_with(r.change_id.as_str()))
+    }
+}
+
+fn jj_log(revset: &str, template: &str) -> Result<String> {
+    let output = Command::new("jj")
+        .args(["log", "-r", revset, "--no-graph", "--template", template])
+        .output()
+        .context("Failed to run: jj log")?;
+    if !output.status.success() {
+        bail!("jj log -r {} failed: {}", revset, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse [`LOG_TEMPLATE`] output (newest first). Commits without a description are
+/// skipped, as jj won't push them; the number skipped is returned alongside.
+pub fn parse_log(output: &str, verbose: bool) -> (Vec<Revision>, usize) {
+    let mut revisions = Vec::new();
+    let mut skipped_count = 0;
+
+    for line in output.lines() {
+        if line.trim().is_empty() { continue; }
+        let parts: Vec<&str> = line.split('|').collect();
+        if parts.len() >= 5 {
+            let change_id = parts[0].to_string();
+            if change_id == ROOT_CHANGE_ID { continue; }
+
+            let parent_ids = if parts[4].is_empty() {
+                Vec::new()
+            } else {
+                parts[4].split(',').map(|s| s.to_string()).collect()
+            };
+
+            let description = parts[2].to_string();
+
+            if description == "(no description)" {
+                skipped_count += 1;
+                if verbose {
+                    eprintln!("  Skipping commit {} with no description", &change_id[..8.min(change_id.len())]);
+                }
+                continue;
+            }
+
+            revisions.push(Revision {
+                change_id,
+                commit_id: parts[1].to_string(),
+                description,
+                body: String::new(),
+                has_conflicts: parts[3] == "true",
+                parent_change_ids: parent_ids,
+                branch_name: None,
+                base_branch: None,
+                pr_number: None,
+                pr_url: None,
+                pr_state: None,
+                pr_mergeable: None,
+                pr_checks: None,
+                pr_review: None,
+                changed_paths: Vec::new(),
+            });
+        }
+    }
+
+    (revisions, skipped_count)
+}
+
+/// A merge whose first parent is on the base (e.g. `jj new main other-work`) is
+/// the bottom of the stack and sits directly on the base. Its other parents
+/// belong to work outside the stack, so they and their ancestors are left out
+/// instead of being pushed as PRs of their own.
+pub fn drop_out_of_stack_parents(revisions: &mut Vec<Revision>, verbose: bool) {
+    let in_stack = |id: &str, revs: &[Revision]| revs.iter().any(|r| r.change_id == id);
+
+    let mut excluded: HashSet<String> = HashSet::new();
+    for rev in revisions.iter() {
+        if rev.parent_change_ids.len() < 2 || in_stack(&rev.parent_change_ids[0], revisions) {
+            continue;
+        }
+        let (inside, outside): (Vec<&String>, Vec<&String>) = rev.parent_change_ids.iter()
+            .partition(|id| in_stack(id, revisions));
+        if inside.is_empty() {
+            continue;
+        }
+
+        // Everything reachable from the in-stack parents
+        let mut pending: Vec<String> = inside.into_iter().cloned().collect();
+        while let Some(id) = pending.pop() {
+            if !excluded.insert(id.clone()) {
+                continue;
+            }
+            if let Some(parent) = revisions.iter().find(|r| r.change_id == id) {
+                pending.extend(parent.parent_change_ids.iter().filter(|p| in_stack(p, revisions)).cloned());
+            }
+        }
+
+        if verbose {
+            eprintln!("  {} merges {} into the stack; treating it as based on {}",
+                     &rev.change_id[..8.min(rev.change_id.len())],
+                     rev.parent_change_ids.iter().filter(|p| excluded.contains(*p))
+                         .map(|p| &p[..8.min(p.len())]).collect::<Vec<_>>().join(", "),
+                     &outside[0][..8.min(outside[0].len())]);
+        }
+    }
+
+    if !excluded.is_empty() {
+        revisions.retain(|r| !excluded.contains(&r.change_id));
+    }
+}
+
+/// Description bodies (everything after the first line) keyed by change ID, from
+/// [`DESCRIPTION_TEMPLATE`] output
+pub fn parse_description_bodies(output: &str) -> HashMap<String, String> {
+    output.split(DESCRIPTION_SEPARATOR)
+        .filter_map(|entry| {
+            let mut lines = entry.trim_start_matches('\n').splitn(3, '\n');
+            let change_id = lines.next().filter(|id| !id.is_empty())?;
+            let _title = lines.next();
+            Some((change_id.to_string(), lines.next().unwrap_or_default().trim().to_string()))
+        })
+        .collect()
+}
+
+/// Name of the managed branch pushed for a change
+pub fn branch_name_for(prefix: &str, change_id: &str) -> String {
+    format!("{}{}", prefix, &change_id[..12.min(change_id.len())])
+}
+
+/// Whether `jj git push` output reports a bookmark being created or moved
+pub fn push_updated_bookmark(output: &str) -> bool {
+    output.contains("Creating") || output.contains("Moving")
+}
+
+/// Words that look like change IDs in squash/abandon entries of `jj op log` output
+pub fn squashed_change_ids(op_log: &str) -> Vec<&str> {
+    op_log.lines()
+        .filter(|line| line.contains("squash") || line.contains("abandon"))
+        .flat_map(|line| line.split_whitespace())
+        .filter(|word| word.len() >= 8 && word.chars().all(|c| c.is_alphanumeric()))
+        .collect()
+}
+
+/// Commit IDs that squash entries of `jj op log` output squashed into
+pub fn squash_destinations(op_log: &str) -> Vec<&str> {
+    op_log.lines()
+        .filter(|line| line.contains("squash"))
+        .filter_map(|line| line.split_once(" into ").map(|(_, rest)| rest))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .collect()
+}
+
+/// How much of two `jj diff --git` outputs is the same change, from 0.0 to 1.0:
+/// the share of added and removed lines they have in common. Headers and
+/// context lines don't count, so a rebase that shifted hunks still matches.
+pub fn diff_similarity(a: &str, b: &str) -> f64 {
+    fn changed_lines(diff: &str) -> HashMap<&str, usize> {
+        let mut lines = HashMap::new();
+        for line in diff.lines() {
+            if (line.starts_with('+') && !line.starts_with("+++")) || (line.starts_with('-') && !line.starts_with("---")) {
+                *lines.entry(line).or_insert(0) += 1;
+            }
+        }
+        lines
+    }
+
+    let (a, b) = (changed_lines(a), changed_lines(b));
+    let total: usize = a.values().sum::<usize>() + b.values().sum::<usize>();
+    if total == 0 {
+        return 1.0;
+    }
+    let common: usize = a.iter().map(|(line, n)| (*n).min(b.get(line).copied().unwrap_or(0))).sum();
+    2.0 * common as f64 / total as f64
+}
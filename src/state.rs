@@ -0,0 +1,112 @@
+//! The `.almighty` state file: PR mappings and run history.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Current state file format version
+pub const STATE_VERSION: u32 = 2;
+
+/// Contents of the `.almighty` state file, keyed by change ID
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct State {
+    pub version: u32,
+    pub prs: HashMap<String, PrInfo>,
+    pub merged_prs: HashSet<String>,
+    pub closed_prs: HashSet<String>,
+    pub last_operation_id: Option<String>,
+    #[serde(default)]
+    pub stack_order: Vec<String>,
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+    #[serde(default)]
+    pub last_updated: Option<String>,
+    #[serde(default)]
+    pub merged_into_pr: HashMap<String, String>,  // Maps change_id -> PR branch it was merged into
+    #[serde(default)]
+    pub closed_externally: HashSet<String>,  // Changes whose PR was closed on GitHub, not by us
+    #[serde(default)]
+    pub base_overrides: HashMap<String, String>,  // Maps change_id -> base branch set manually on GitHub
+    #[serde(default)]
+    pub pr_levels: HashMap<u32, usize>,  // Maps PR number -> stack position when it was opened
+    #[serde(default)]
+    pub base_pr: Option<BasePr>,  // PR the bottom of the stack is built on, until it merges
+    #[serde(default)]
+    pub default_branch: Option<String>,  // Remote default branch as last seen
+    #[serde(default, alias = "path_labels")]
+    pub labels: HashMap<u32, BTreeSet<String>>,  // Maps PR number -> labels we applied to it
+    #[serde(default, alias = "path_reviewers")]
+    pub reviewers: HashMap<u32, BTreeSet<String>>,  // Maps PR number -> reviewers we requested on it
+    #[serde(default)]
+    pub inherited_branches: HashMap<String, String>,  // Maps change_id -> branch of a PR it took over from a split or folded change
+    #[serde(default)]
+    pub superseded_by: HashMap<u32, u32>,  // Maps closed PR number -> PR of the change that absorbed it
+    #[serde(default)]
+    pub backports: HashMap<String, Vec<Backport>>,  // Maps release branch -> revisions copied onto it, bottom to top
+    #[serde(default)]
+    pub blocked_labeled: HashSet<u32>,  // PRs carrying the blocked_label because their base is another PR
+    #[serde(default)]
+    pub backups: Vec<Backup>,  // Snapshots taken before automatic rebases, oldest first
+}
+
+/// PR of someone else's stack that this stack is built on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasePr {
+    pub number: u32,
+    pub branch: String,
+}
+
+/// A past run and what it did
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: String,
+    pub op_type: String,
+    pub timestamp: String,
+    pub changes_affected: Vec<String>,
+    pub success: bool,
+    #[serde(default)]
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub calls: BTreeMap<String, usize>,  // Subprocess calls made, by category
+}
+
+/// The PR a change is pushed as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrInfo {
+    pub pr_number: u32,
+    pub pr_url: String,
+    pub branch_name: String,
+    pub commit_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_branch: Option<String>,  // Base we last applied to the PR
+}
+
+/// A revision copied onto a release branch by `backport`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backport {
+    pub original_change_id: String,
+    pub change_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pr_number: Option<u32>,
+}
+
+/// Where the repository was before an automatic rebase, for `restore-backup`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    pub id: String,
+    pub operation: String,  // jj operation to restore
+    pub reason: String,
+    pub timestamp: String,
+    pub commits: BTreeMap<String, String>,  // Maps change_id -> commit before the rebase
+}
+
+impl State {
+    pub fn from_json(content: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(content)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Body used when no body_template is configured
+pub const DEFAULT_BODY_TEMPLATE: &str =
+    "{{stack}}{{test_plan}}{{co_authors}}{{checklists}}\n---\nChange ID: `{{change_id}}`\n{{co_author_trailers}}{{merge_trailers}}";
+
+// Line of the stack section for each PR when no stack_template is configured
+pub const DEFAULT_STACK_TEMPLATE: &str = "{{marker}} {{pr}}: {{title}} {{commit_link}} {{files_link}} {{state_icon}}";
+
+// DEFAULT_STACK_TEMPLATE with each PR's CI check status, for stack_checks
+pub const CHECKS_STACK_TEMPLATE: &str = "{{marker}} {{checks}} {{pr}}: {{title}} {{commit_link}} {{files_link}} {{state_icon}}";
+
+// Generated part of bodies built from the repository's PR template; the
+// revision's own description (test plan, co-authors) is already in the template
+pub const MANAGED_BODY_TEMPLATE: &str =
+    "{{stack}}{{checklists}}\n---\nChange ID: `{{change_id}}`\n{{co_author_trailers}}{{merge_trailers}}";
+
+// Markers around the generated part of a PR body. Updates only rewrite what
+// is between them (and the trailers right after), so text written on GitHub
+// above or below survives.
+pub const MANAGED_BEGIN: &str = "<!-- almighty:begin -->";
+pub const MANAGED_END: &str = "<!-- almighty:end -->";
+
+// Hidden first line of the stack comment posted with stack_comment, by which
+// later runs find the comment to update
+pub const STACK_COMMENT_MARKER: &str = "<!-- almighty:stack -->";
+
+// `generated` between the managed markers. Its closing trailers go after the
+// end marker, as their own paragraph, so they still end the body and squash
+// merges that use the PR description keep them.
+pub fn managed_block(generated: &str) -> String {
+    let (content, trailers) = crate::description::split_trailer_block(generated.trim_end());
+    let mut block = format!("{}\n{}\n{}\n", MANAGED_BEGIN, content.trim_end(), MANAGED_END);
+    if !trailers.is_empty() {
+        block.push_str(&format!("\n{}\n", trailers));
+    }
+    block
+}
+
+// `current` with its managed block, and the trailers after it, replaced by
+// those of `body`; None when either has no complete managed block
+pub fn replace_managed(current: &str, body: &str) -> Option<String> {
+    let (before, rest) = current.split_once(MANAGED_BEGIN)?;
+    let (_, after) = rest.split_once(MANAGED_END)?;
+    let (_, new_rest) = body.split_once(MANAGED_BEGIN)?;
+    let (block, new_after) = new_rest.split_once(MANAGED_END)?;
+
+    // Our trailers are the paragraph right after the end marker; anything
+    // else there was written by someone else
+    let after = after.trim_start_matches(['\r', '\n']);
+    let (first, rest) = after.split_once("\n\n").unwrap_or((after, ""));
+    let user = if !first.is_empty() && crate::description::split_trailer_block(first).0.is_empty() { rest } else { after };
+
+    let mut replaced = format!("{}{}{}{}\n", before, MANAGED_BEGIN, block, MANAGED_END);
+    let trailers = new_after.trim();
+    if !trailers.is_empty() {
+        replaced.push_str(&format!("\n{}\n", trailers));
+    }
+    if !user.trim().is_empty() {
+        replaced.push_str(&format!("\n{}\n", user.trim_end()));
+    }
+    Some(replaced)
+}
+
+// GitHub rejects PR bodies and comments longer than this many characters
+pub const MAX_BODY_CHARS: usize = 65536;
+
+const TRUNCATED_NOTE: &str = "*(truncated)*";
+
+// `body` cut to GitHub's size limit. The text before the managed block (the
+// description) is shortened at a line break and marked as truncated, so the
+// stack section and trailers stay whole; a body whose managed block alone is
+// too long, or that has none, is cut at its end instead.
+pub fn limit_body(body: &str) -> String {
+    if body.chars().count() <= MAX_BODY_CHARS {
+        return body.to_string();
+    }
+    let note_chars = TRUNCATED_NOTE.chars().count() + 4;
+    if let Some(start) = body.find(MANAGED_BEGIN) {
+        let (description, block) = body.split_at(start);
+        if let Some(budget) = MAX_BODY_CHARS.checked_sub(block.chars().count() + note_chars) {
+            return format!("{}\n\n{}\n\n{}", truncate_at_line(description, budget), TRUNCATED_NOTE, block);
+        }
+    }
+    format!("{}\n\n{}", truncate_at_line(body, MAX_BODY_CHARS - note_chars), TRUNCATED_NOTE)
+}
+
+// The first `max_chars` characters of `text`, back to the last line break if there is one
+fn truncate_at_line(text: &str, max_chars: usize) -> &str {
+    let end = text.char_indices().nth(max_chars).map_or(text.len(), |(i, _)| i);
+    let cut = &text[..end];
+    cut.rfind('\n').map_or(cut, |newline| &cut[..newline]).trim_end()
+}
+
+// Where GitHub looks for a repository's pull request template
+const PR_TEMPLATE_LOCATIONS: &[&str] = &[
+    ".github/pull_request_template.md", ".github/PULL_REQUEST_TEMPLATE.md",
+    "pull_request_template.md", "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md", "docs/PULL_REQUEST_TEMPLATE.md",
+];
+
+// The PR template at `path`, or else the working copy's pull request template
+pub fn pr_template(path: Option<&Path>) -> Result<Option<String>> {
+    if let Some(path) = path {
+        return fs::read_to_string(path).map(Some).with_context(|| format!("Failed to read {}", path.display()));
+    }
+    Ok(PR_TEMPLATE_LOCATIONS.iter().find_map(|path| fs::read_to_string(path).ok()))
+}
+
+// A PR template with a revision's description filled in: at `{{description}}`,
+// or else below the template's first heading, or else above the template
+pub fn fill_pr_template(template: &str, description: &str) -> String {
+    if template.contains("{{description}}") {
+        return render(template, |name| (name == "description").then(|| description.to_string()));
+    }
+    if description.is_empty() {
+        return template.to_string();
+    }
+    let mut filled = String::new();
+    let mut lines = template.split_inclusive('\n');
+    for line in lines.by_ref() {
+        filled.push_str(line);
+        if line.trim_start().starts_with('#') {
+            if !line.ends_with('\n') {
+                filled.push('\n');
+            }
+            filled.push_str(&format!("\n{}\n", description));
+            filled.extend(lines);
+            return filled;
+        }
+    }
+    format!("{}\n\n{}", description, template)
+}
+
+// Expand `{{name}}` placeholders using `lookup`. Unknown names are left in
+// place so typos show up in the rendered PR instead of vanishing.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*([\w.:/-]+)\s*\}\}").unwrap());
+
+    placeholder.replace_all(template, |caps: &regex::Captures| {
+        lookup(&caps[1]).unwrap_or_else(|| caps[0].to_string())
+    }).into_owned()
+}
+
+// `text` with the characters Markdown and GitHub give meaning escaped, so a
+// commit title renders as written: no emphasis, code spans, HTML, links,
+// table cells, or `#123` and `@name` references
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '~' | '[' | ']' | '<' | '>' | '#' | '|' | '@' | '!') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Markdown for a GitHub Actions workflow status badge on a branch
+pub fn workflow_badge(repo: &str, workflow: &str, branch: &str) -> String {
+    let url = format!("{}/{}/actions/workflows/{}", crate::forge::web_url(), repo, crate::forge::encode_path_segment(workflow));
+    // Branch names may hold `#`, `&` or `+`, which would end or change the query
+    let branch = crate::forge::encode_path_segment(branch);
+    format!("[![{}]({}/badge.svg?branch={})]({}?query=branch%3A{})", workflow, url, branch, url, branch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn managed_block_keeps_trailers_after_the_end_marker() {
+        let block = managed_block("Stack\n\nChange-Id: abc\n");
+        assert_eq!(block, format!("{}\nStack\n{}\n\nChange-Id: abc\n", MANAGED_BEGIN, MANAGED_END));
+    }
+
+    #[test]
+    fn replace_managed_keeps_text_written_on_github() {
+        let current = format!("Intro\n{}\nold\n{}\n\nChange-Id: old\n\nNotes from review\n", MANAGED_BEGIN, MANAGED_END);
+        let body = format!("Ignored\n{}\nnew\n{}\n\nChange-Id: new\n", MANAGED_BEGIN, MANAGED_END);
+        assert_eq!(replace_managed(&current, &body).unwrap(),
+                   format!("Intro\n{}\nnew\n{}\n\nChange-Id: new\n\nNotes from review\n", MANAGED_BEGIN, MANAGED_END));
+    }
+
+    #[test]
+    fn replace_managed_needs_both_blocks() {
+        let body = format!("{}\nnew\n{}\n", MANAGED_BEGIN, MANAGED_END);
+        assert_eq!(replace_managed("Hand-written body", &body), None);
+        assert_eq!(replace_managed(&body, "No markers"), None);
+    }
+
+    #[test]
+    fn workflow_badge_encodes_the_branch() {
+        let badge = workflow_badge("o/r", "ci.yml", "push/ab#c+d");
+        assert!(badge.contains("badge.svg?branch=push%2Fab%23c%2Bd)"));
+        assert!(badge.ends_with("?query=branch%3Apush%2Fab%23c%2Bd)"));
+    }
+
+    #[test]
+    fn limit_body_leaves_short_bodies_alone() {
+        assert_eq!(limit_body("short"), "short");
+    }
+
+    #[test]
+    fn limit_body_shortens_the_description_and_keeps_the_block() {
+        let block = format!("{}\nstack\n{}\n", MANAGED_BEGIN, MANAGED_END);
+        let description = "line of prose\n".repeat(MAX_BODY_CHARS / 10);
+        let limited = limit_body(&format!("{}{}", description, block));
+        assert!(limited.chars().count() <= MAX_BODY_CHARS);
+        assert!(limited.ends_with(&block));
+        assert!(limited.contains(&format!("line of prose\n\n{}\n\n{}", TRUNCATED_NOTE, MANAGED_BEGIN)));
+    }
+
+    #[test]
+    fn limit_body_cuts_the_end_without_a_block() {
+        let limited = limit_body(&"é".repeat(MAX_BODY_CHARS + 10));
+        assert!(limited.chars().count() <= MAX_BODY_CHARS);
+        assert!(limited.ends_with(TRUNCATED_NOTE));
+    }
+}